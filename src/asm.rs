@@ -0,0 +1,663 @@
+//! An assembler for CHIP-8, SUPER-CHIP and XO-CHIP programs.
+//!
+//! It reads the same mnemonics that the disassembler writes, so listings
+//! assemble back into the ROM they came from.  Each line looks like
+//!
+//! ```text
+//! label:  MNEMONIC operand, operand  ; comment
+//! ```
+//!
+//! where any part may be missing.  Mnemonics, registers and directives don't
+//! care about case, but labels and constants do.  The directives are:
+//!
+//! * `NAME EQU value` defines a constant.
+//! * `DB value, "text", ...` and `DW value, ...` place bytes and big-endian
+//!   words.
+//! * `ORG address` moves on to a later address, filling the gap with zeros.
+//! * `INCLUDE "file"` assembles another file in place, found relative to the
+//!   file doing the including.
+//!
+//! Values are decimal, hex (`0x1F` or `#1F`) or binary (`0b101`) numbers,
+//! labels, constants and `$` for the current address, added and subtracted
+//! together.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::PROGRAM_START;
+
+/// How deeply files may include each other, which stops a file from
+/// including itself forever.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Names that mean something in operands, so can't be used for symbols.
+const RESERVED: [&str; 11] = ["I", "[I]", "DT", "ST", "K", "F", "HF", "B", "R", "PITCH", "LONG"];
+
+/// A problem in the source, and where it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    /// The file the problem is in, or `<input>` for source given directly.
+    pub file: String,
+    /// The line number, starting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A line of source, with includes already replaced by the included lines.
+struct Line {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+impl Line {
+    fn error(&self, message: String) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.number, message }
+    }
+}
+
+/// An operand of an instruction.
+#[derive(Debug)]
+enum Operand {
+    Reg(u8),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+    Pitch,
+    /// `LONG value`, the 16 bit address of the XO-CHIP long index load
+    Long(String),
+    /// A value to work out once every label is known
+    Value(String),
+}
+
+/// What a line asks for, apart from its label.
+enum Statement {
+    Empty,
+    Equ(String, String),
+    Org(String),
+    Db(Vec<String>),
+    Dw(Vec<String>),
+    Instruction(String, Vec<Operand>),
+}
+
+/// Assembles source into a ROM image to be loaded at `PROGRAM_START`.
+/// Included files are found relative to the current directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    add_lines(&mut lines, source, "<input>", Path::new(""), 0)?;
+    assemble_lines(&lines)
+}
+
+/// Assembles the source in a file into a ROM image to be loaded at
+/// `PROGRAM_START`.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let name = path.display().to_string();
+    let source = read_source(path).map_err(|message| {
+        AsmError { file: name.clone(), line: 0, message }
+    })?;
+    let mut lines = Vec::new();
+    add_lines(&mut lines, &source, &name, path.parent().unwrap_or(Path::new("")), 0)?;
+    assemble_lines(&lines)
+}
+
+fn read_source(path: &Path) -> Result<String, String> {
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+    Ok(source)
+}
+
+/// Appends the lines of `source` to `lines`, replacing includes with the
+/// lines of the files they name.
+fn add_lines(lines: &mut Vec<Line>, source: &str, file: &str, dir: &Path, depth: usize)
+             -> Result<(), AsmError> {
+    for (number, text) in source.lines().enumerate() {
+        let line = Line { file: file.to_string(), number: number + 1, text: text.to_string() };
+        let mut words = strip_comment(text).trim().splitn(2, char::is_whitespace);
+        if !words.next().is_some_and(|word| word.eq_ignore_ascii_case("INCLUDE")) {
+            lines.push(line);
+            continue;
+        }
+
+        let name = match parse_string(words.next().unwrap_or("").trim()) {
+            Some(name) => name,
+            None => return Err(line.error("INCLUDE needs a quoted file name".to_string())),
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(line.error(format!("Includes nested too deeply at {}", name)));
+        }
+        let path: PathBuf = dir.join(&name);
+        let included = read_source(&path).map_err(|message| line.error(message))?;
+        add_lines(lines, &included, &path.display().to_string(),
+                  path.parent().unwrap_or(Path::new("")), depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Cuts off a `;` comment, leaving any inside quotes alone.
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Reads a double quoted string, with no escapes.
+fn parse_string(text: &str) -> Option<String> {
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        Some(text[1..text.len() - 1].to_string())
+    } else {
+        None
+    }
+}
+
+/// Splits operands on commas, leaving any inside quotes alone.
+fn split_operands(text: &str) -> Vec<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => {
+                operands.push(current.trim().to_string());
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+    operands.push(current.trim().to_string());
+    operands
+}
+
+fn is_symbol(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn is_reserved(name: &str) -> bool {
+    parse_register(name).is_some() || RESERVED.iter().any(|word| word.eq_ignore_ascii_case(name))
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    if text.len() == 2 && (text.starts_with('V') || text.starts_with('v')) {
+        u8::from_str_radix(&text[1..], 16).ok()
+    } else {
+        None
+    }
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    if text.is_empty() {
+        return Err("Missing operand".to_string());
+    }
+    if let Some(x) = parse_register(text) {
+        return Ok(Operand::Reg(x));
+    }
+    let upper = text.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DT,
+        "ST" => Operand::ST,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::HF,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        "PITCH" => Operand::Pitch,
+        _ if upper.starts_with("LONG ") => Operand::Long(text[5..].trim().to_string()),
+        _ => Operand::Value(text.to_string()),
+    };
+    Ok(operand)
+}
+
+/// Works out what a line asks for, returning its label too if it has one.
+fn parse_line(text: &str) -> Result<(Option<String>, Statement), String> {
+    let mut code = strip_comment(text).trim();
+    let mut label = None;
+    if let Some(colon) = code.find(':') {
+        let name = code[..colon].trim();
+        if !code[..colon].contains('"') {
+            if !is_symbol(name) || is_reserved(name) {
+                return Err(format!("Bad label name: {}", name));
+            }
+            label = Some(name.to_string());
+            code = code[colon + 1..].trim();
+        }
+    }
+    if code.is_empty() {
+        return Ok((label, Statement::Empty));
+    }
+
+    let (word, rest) = match code.find(char::is_whitespace) {
+        Some(space) => (&code[..space], code[space..].trim()),
+        None => (code, ""),
+    };
+    let mnemonic = word.to_ascii_uppercase();
+
+    // NAME EQU value
+    let (second, value) = match rest.find(char::is_whitespace) {
+        Some(space) => (&rest[..space], rest[space..].trim()),
+        None => (rest, ""),
+    };
+    if second.eq_ignore_ascii_case("EQU") {
+        if label.is_some() {
+            return Err("A constant can't have a label".to_string());
+        }
+        if !is_symbol(word) || is_reserved(word) {
+            return Err(format!("Bad constant name: {}", word));
+        }
+        if value.is_empty() {
+            return Err(format!("Missing value for {}", word));
+        }
+        return Ok((None, Statement::Equ(word.to_string(), value.to_string())));
+    }
+
+    let operands = split_operands(rest);
+    if operands.iter().any(|operand| operand.is_empty()) {
+        return Err("Missing operand".to_string());
+    }
+    let statement = match mnemonic.as_str() {
+        "ORG" if operands.len() == 1 => Statement::Org(operands[0].clone()),
+        "ORG" => return Err("ORG needs one address".to_string()),
+        "DB" if !operands.is_empty() => Statement::Db(operands),
+        "DW" if !operands.is_empty() => Statement::Dw(operands),
+        "DB" | "DW" => return Err(format!("{} needs at least one value", mnemonic)),
+        _ => {
+            let operands = operands.iter()
+                .map(|operand| parse_operand(operand))
+                .collect::<Result<Vec<_>, _>>()?;
+            Statement::Instruction(mnemonic, operands)
+        }
+    };
+    Ok((label, statement))
+}
+
+/// Works out a value from numbers and symbols added and subtracted together.
+fn evaluate(text: &str, symbols: &HashMap<String, i64>, here: i64) -> Result<i64, String> {
+    let mut total = 0;
+    let mut sign = 1;
+    let mut expecting_term = true;
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        if expecting_term {
+            if rest.starts_with('-') || rest.starts_with('+') {
+                if rest.starts_with('-') {
+                    sign = -sign;
+                }
+                rest = rest[1..].trim_start();
+                continue;
+            }
+            let end = rest.find(|c: char| c == '+' || c == '-' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let term = &rest[..end];
+            total += sign * evaluate_term(term, symbols, here)?;
+            rest = rest[end..].trim_start();
+            expecting_term = false;
+        } else {
+            sign = match rest.chars().next() {
+                Some('+') => 1,
+                Some('-') => -1,
+                _ => return Err(format!("Bad value: {}", text)),
+            };
+            rest = rest[1..].trim_start();
+            expecting_term = true;
+        }
+    }
+    if expecting_term {
+        return Err(format!("Bad value: {}", text));
+    }
+    Ok(total)
+}
+
+fn evaluate_term(term: &str, symbols: &HashMap<String, i64>, here: i64) -> Result<i64, String> {
+    let lower = term.to_ascii_lowercase();
+    let number = if term == "$" {
+        Ok(here)
+    } else if lower.starts_with("0x") {
+        i64::from_str_radix(&term[2..], 16)
+    } else if lower.starts_with('#') {
+        i64::from_str_radix(&term[1..], 16)
+    } else if lower.starts_with("0b") {
+        i64::from_str_radix(&term[2..], 2)
+    } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+        term.parse()
+    } else if is_symbol(term) {
+        return symbols.get(term).cloned().ok_or_else(|| format!("Unknown symbol: {}", term));
+    } else {
+        return Err(format!("Bad value: {}", term));
+    };
+    number.map_err(|_| format!("Bad number: {}", term))
+}
+
+/// Checks that a value fits in `bits` bits, allowing negative numbers for
+/// bytes and words.
+fn fit(value: i64, bits: u32, what: &str) -> Result<u16, String> {
+    let max = (1 << bits) - 1;
+    let min = if bits == 8 || bits == 16 { -(1 << (bits - 1)) } else { 0 };
+    if value < min || value > max {
+        return Err(format!("{} doesn't fit in {}", value, what));
+    }
+    Ok((value & max) as u16)
+}
+
+/// The number of bytes an instruction assembles to.
+fn instruction_size(mnemonic: &str, operands: &[Operand]) -> usize {
+    match (mnemonic, operands) {
+        ("LD", &[Operand::I, Operand::Long(_)]) => 4,
+        _ => 2,
+    }
+}
+
+/// Encodes an instruction, returning its opcode and the extra word that
+/// follows the long index load.
+fn encode(mnemonic: &str, operands: &[Operand], symbols: &HashMap<String, i64>, here: i64)
+          -> Result<(u16, Option<u16>), String> {
+    use self::Operand::*;
+
+    let value = |text: &str, bits: u32, what: &str| {
+        evaluate(text, symbols, here).and_then(|value| fit(value, bits, what))
+    };
+    let xy = |opcode: u16, x: u8, y: u8| opcode | (x as u16) << 8 | (y as u16) << 4;
+    let x = |opcode: u16, x: u8| opcode | (x as u16) << 8;
+
+    let opcode = match (mnemonic, operands) {
+        ("CLS", &[]) => 0x00E0,
+        ("RET", &[]) => 0x00EE,
+        ("SCD", &[Value(ref n)]) => 0x00C0 | value(n, 4, "a nibble")?,
+        ("SCU", &[Value(ref n)]) => 0x00D0 | value(n, 4, "a nibble")?,
+        ("SCR", &[]) => 0x00FB,
+        ("SCL", &[]) => 0x00FC,
+        ("EXIT", &[]) => 0x00FD,
+        ("LOW", &[]) => 0x00FE,
+        ("HIGH", &[]) => 0x00FF,
+        ("SYS", &[Value(ref a)]) => value(a, 12, "an address")?,
+        ("JP", &[Value(ref a)]) => 0x1000 | value(a, 12, "an address")?,
+        ("JP", &[Reg(0), Value(ref a)]) => 0xB000 | value(a, 12, "an address")?,
+        ("CALL", &[Value(ref a)]) => 0x2000 | value(a, 12, "an address")?,
+        ("SE", &[Reg(vx), Value(ref n)]) => x(0x3000, vx) | value(n, 8, "a byte")?,
+        ("SNE", &[Reg(vx), Value(ref n)]) => x(0x4000, vx) | value(n, 8, "a byte")?,
+        ("SE", &[Reg(vx), Reg(vy)]) => xy(0x5000, vx, vy),
+        ("SAVE", &[Reg(vx), Reg(vy)]) => xy(0x5002, vx, vy),
+        ("LOAD", &[Reg(vx), Reg(vy)]) => xy(0x5003, vx, vy),
+        ("LD", &[Reg(vx), Value(ref n)]) => x(0x6000, vx) | value(n, 8, "a byte")?,
+        ("ADD", &[Reg(vx), Value(ref n)]) => x(0x7000, vx) | value(n, 8, "a byte")?,
+        ("LD", &[Reg(vx), Reg(vy)]) => xy(0x8000, vx, vy),
+        ("OR", &[Reg(vx), Reg(vy)]) => xy(0x8001, vx, vy),
+        ("AND", &[Reg(vx), Reg(vy)]) => xy(0x8002, vx, vy),
+        ("XOR", &[Reg(vx), Reg(vy)]) => xy(0x8003, vx, vy),
+        ("ADD", &[Reg(vx), Reg(vy)]) => xy(0x8004, vx, vy),
+        ("SUB", &[Reg(vx), Reg(vy)]) => xy(0x8005, vx, vy),
+        ("SHR", &[Reg(vx), Reg(vy)]) => xy(0x8006, vx, vy),
+        ("SHR", &[Reg(vx)]) => xy(0x8006, vx, vx),
+        ("SUBN", &[Reg(vx), Reg(vy)]) => xy(0x8007, vx, vy),
+        ("SHL", &[Reg(vx), Reg(vy)]) => xy(0x800E, vx, vy),
+        ("SHL", &[Reg(vx)]) => xy(0x800E, vx, vx),
+        ("SNE", &[Reg(vx), Reg(vy)]) => xy(0x9000, vx, vy),
+        ("LD", &[I, Value(ref a)]) => 0xA000 | value(a, 12, "an address")?,
+        ("LD", &[I, Long(ref a)]) => return Ok((0xF000, Some(value(a, 16, "a word")?))),
+        ("RND", &[Reg(vx), Value(ref n)]) => x(0xC000, vx) | value(n, 8, "a byte")?,
+        ("DRW", &[Reg(vx), Reg(vy), Value(ref n)]) => xy(0xD000, vx, vy) | value(n, 4, "a nibble")?,
+        ("SKP", &[Reg(vx)]) => x(0xE09E, vx),
+        ("SKNP", &[Reg(vx)]) => x(0xE0A1, vx),
+        ("PLANE", &[Value(ref n)]) => 0xF001 | value(n, 4, "a nibble")? << 8,
+        ("AUDIO", &[]) => 0xF002,
+        ("LD", &[Reg(vx), DT]) => x(0xF007, vx),
+        ("LD", &[Reg(vx), K]) => x(0xF00A, vx),
+        ("LD", &[DT, Reg(vx)]) => x(0xF015, vx),
+        ("LD", &[ST, Reg(vx)]) => x(0xF018, vx),
+        ("ADD", &[I, Reg(vx)]) => x(0xF01E, vx),
+        ("LD", &[F, Reg(vx)]) => x(0xF029, vx),
+        ("LD", &[HF, Reg(vx)]) => x(0xF030, vx),
+        ("LD", &[B, Reg(vx)]) => x(0xF033, vx),
+        ("LD", &[Pitch, Reg(vx)]) => x(0xF03A, vx),
+        ("LD", &[IndirectI, Reg(vx)]) => x(0xF055, vx),
+        ("LD", &[Reg(vx), IndirectI]) => x(0xF065, vx),
+        ("LD", &[R, Reg(vx)]) => x(0xF075, vx),
+        ("LD", &[Reg(vx), R]) => x(0xF085, vx),
+        ("CLS", _) | ("RET", _) | ("SCD", _) | ("SCU", _) | ("SCR", _) | ("SCL", _) |
+        ("EXIT", _) | ("LOW", _) | ("HIGH", _) | ("SYS", _) | ("JP", _) | ("CALL", _) |
+        ("SE", _) | ("SNE", _) | ("SAVE", _) | ("LOAD", _) | ("LD", _) | ("ADD", _) |
+        ("OR", _) | ("AND", _) | ("XOR", _) | ("SUB", _) | ("SHR", _) | ("SUBN", _) |
+        ("SHL", _) | ("RND", _) | ("DRW", _) | ("SKP", _) | ("SKNP", _) | ("PLANE", _) |
+        ("AUDIO", _) =>
+            return Err(format!("Bad operands for {}", mnemonic)),
+        _ => return Err(format!("Unknown instruction: {}", mnemonic)),
+    };
+    Ok((opcode, None))
+}
+
+/// Defines a label or constant, which mustn't exist already.
+fn define(symbols: &mut HashMap<String, i64>, name: &str, value: i64) -> Result<(), String> {
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err(format!("{} is already defined", name));
+    }
+    Ok(())
+}
+
+/// Moves `address` past a statement, defining any label or constant.
+fn place(label: &Option<String>, statement: &Statement, symbols: &mut HashMap<String, i64>,
+         address: &mut i64) -> Result<(), String> {
+    if let Some(ref label) = *label {
+        define(symbols, label, *address)?;
+    }
+    match *statement {
+        Statement::Empty => {}
+        Statement::Equ(ref name, ref value) => {
+            let value = evaluate(value, symbols, *address)?;
+            define(symbols, name, value)?;
+        }
+        Statement::Org(ref value) => {
+            let org = evaluate(value, symbols, *address)?;
+            if org < *address {
+                return Err(format!("ORG {:#X} is before the current address {:#X}",
+                                   org, address));
+            }
+            *address = org;
+        }
+        Statement::Db(ref values) => {
+            for value in values {
+                *address += parse_string(value).map_or(1, |text| text.len() as i64);
+            }
+        }
+        Statement::Dw(ref values) => *address += 2 * values.len() as i64,
+        Statement::Instruction(ref mnemonic, ref operands) =>
+            *address += instruction_size(mnemonic, operands) as i64,
+    }
+    if *address > 0x10000 {
+        return Err("Program doesn't fit in memory".to_string());
+    }
+    Ok(())
+}
+
+/// Appends the bytes of a statement to the ROM.
+fn emit(statement: &Statement, symbols: &HashMap<String, i64>, rom: &mut Vec<u8>)
+        -> Result<(), String> {
+    let here = PROGRAM_START as i64 + rom.len() as i64;
+    match *statement {
+        Statement::Empty | Statement::Equ(..) => {}
+        Statement::Org(ref value) => {
+            let org = evaluate(value, symbols, here)?;
+            rom.resize((org - PROGRAM_START as i64) as usize, 0);
+        }
+        Statement::Db(ref values) => {
+            for value in values {
+                match parse_string(value) {
+                    Some(text) => rom.extend_from_slice(text.as_bytes()),
+                    None => {
+                        let byte = fit(evaluate(value, symbols, here)?, 8, "a byte")?;
+                        rom.push(byte as u8);
+                    }
+                }
+            }
+        }
+        Statement::Dw(ref values) => {
+            for value in values {
+                let word = fit(evaluate(value, symbols, here)?, 16, "a word")?;
+                rom.push((word >> 8) as u8);
+                rom.push(word as u8);
+            }
+        }
+        Statement::Instruction(ref mnemonic, ref operands) => {
+            let (opcode, long) = encode(mnemonic, operands, symbols, here)?;
+            for word in Some(opcode).iter().chain(long.iter()) {
+                rom.push((word >> 8) as u8);
+                rom.push(*word as u8);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn assemble_lines(lines: &[Line]) -> Result<Vec<u8>, AsmError> {
+    let mut statements = Vec::with_capacity(lines.len());
+    for line in lines {
+        statements.push(parse_line(&line.text).map_err(|message| line.error(message))?);
+    }
+
+    // First pass finds the address of every label, so the second can encode
+    // instructions that refer to labels further on
+    let mut symbols = HashMap::new();
+    let mut address = PROGRAM_START as i64;
+    for (line, (label, statement)) in lines.iter().zip(&statements) {
+        place(label, statement, &mut symbols, &mut address)
+            .map_err(|message| line.error(message))?;
+    }
+    let mut rom = Vec::new();
+    for (line, (_, statement)) in lines.iter().zip(&statements) {
+        emit(statement, &symbols, &mut rom).map_err(|message| line.error(message))?;
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assemble, assemble_file, AsmError};
+    use disasm::listing;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::process;
+    use {Chip8, PROGRAM_START};
+
+    #[test]
+    fn assembles_instructions() {
+        let rom = assemble("CLS\nLD V1, 0x2F\nDRW V0, V1, 5\nld va, [i]\nLD I, LONG 0x1234").unwrap();
+        assert_eq!(rom, vec![0x00, 0xE0, 0x61, 0x2F, 0xD0, 0x15, 0xFA, 0x65,
+                             0xF0, 0x00, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "
+            SPRITE_ROWS EQU 2
+            start:  LD I, sprite    ; forward reference
+                    DRW V0, V1, SPRITE_ROWS
+            loop:   JP loop
+            sprite: DB 0b11110000, #90
+                    DW start + 2, $
+                    DB \"Hi; there\"
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(rom, vec![0xA2, 0x06, 0xD0, 0x12, 0x12, 0x04, 0xF0, 0x90,
+                             0x02, 0x02, 0x02, 0x08, b'H', b'i', b';', b' ',
+                             b't', b'h', b'e', b'r', b'e']);
+    }
+
+    #[test]
+    fn org_pads_with_zeros() {
+        let rom = assemble("CLS\nORG 0x206\nRET").unwrap();
+        assert_eq!(rom, vec![0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00, 0xEE]);
+        assert!(assemble("CLS\nORG 0x200").is_err());
+    }
+
+    #[test]
+    fn errors_give_line_numbers() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(error("CLS\n\nFOO V1"), AsmError {
+            file: "<input>".to_string(),
+            line: 3,
+            message: "Unknown instruction: FOO".to_string(),
+        });
+        assert_eq!(error("LD V1, 0x100").message, "256 doesn't fit in a byte");
+        assert_eq!(error("JP -2").message, "-2 doesn't fit in an address");
+        assert_eq!(error("JP nowhere").message, "Unknown symbol: nowhere");
+        assert_eq!(error("a: CLS\na: CLS").line, 2);
+        assert_eq!(error("DRW V0, V1").message, "Bad operands for DRW");
+        assert_eq!(error("INCLUDE missing").message, "INCLUDE needs a quoted file name");
+    }
+
+    #[test]
+    fn includes_files() {
+        // A directory of its own, so that test runs at the same time don't
+        // trip over each other's files
+        let dir = env::temp_dir().join(format!("chip8-asm-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.s");
+        let sprites = dir.join("sprites.s");
+        File::create(&main).unwrap()
+            .write_all(b"LD I, sprite\nINCLUDE \"sprites.s\"\n").unwrap();
+        File::create(&sprites).unwrap().write_all(b"sprite: DB 0xFF\n").unwrap();
+        assert_eq!(assemble_file(&main).unwrap(), vec![0xA2, 0x02, 0xFF]);
+
+        File::create(&sprites).unwrap().write_all(b"sprite: DB 0xFF\nJP 0x1000\n").unwrap();
+        let error = assemble_file(&main).unwrap_err();
+        assert_eq!((error.file, error.line), (sprites.display().to_string(), 2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn round_trips_disassembly() {
+        let rom = vec![
+            0x00, 0xFF, 0x22, 0x10, 0x6A, 0x2F, 0x3A, 0x2F, 0xF0, 0x00, 0x12, 0x34,
+            0xFA, 0x3A, 0x12, 0x0E, 0xAB, 0xCD, 0x8A, 0xB6, 0xF3, 0x01, 0x51, 0x23,
+            0xF1, 0x85, 0xD0, 0x10, 0x00, 0xEE, 0x51, 0x21, 0xFF,
+        ];
+        let source = listing(&rom, PROGRAM_START);
+        assert_eq!(assemble(&source).unwrap(), rom);
+    }
+
+    #[test]
+    fn runs_assembled_programs() {
+        let mut chip = Chip8::default();
+        chip.load(&assemble("LD V0, 5\nloop: ADD V1, V0\nSE V1, 20\nJP loop\nEXIT").unwrap());
+        chip.run_frame(100).unwrap();
+        assert_eq!(chip.registers()[1], 20);
+    }
+}
@@ -1,44 +1,41 @@
+use chip8::frontend::{AudioSink, FRAME_RATE};
+use chip8::tone::{Sound, Tone, SAMPLE_RATE};
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::Sdl;
-use std::time::{Duration, Instant};
 
-/// Represents a square wave.
-pub struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+/// The size of SDL's buffer (in samples): the largest power of two that
+/// holds no more than a frame, so that the beep starts and stops within a
+/// frame of the sound timer.
+fn buffer_size() -> u16 {
+    let frame = SAMPLE_RATE / FRAME_RATE;
+    (if frame.is_power_of_two() { frame } else { frame.next_power_of_two() / 2 }) as u16
 }
 
-impl AudioCallback for SquareWave {
+/// Plays the chip's tone through SDL.
+pub struct Callback(Tone);
+
+impl AudioCallback for Callback {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase >= 0.0 && self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
+        self.0.fill(out);
     }
 }
 
-/// Represents a device for making a 'beep' noise.
+/// Represents a device for making a 'beep' noise.  The device keeps
+/// running while the tone is silent, so that it can fade out.
 pub struct Beeper {
-    pub device: AudioDevice<SquareWave>,
-    duration: Duration,
-    start: Instant,
+    pub device: AudioDevice<Callback>,
 }
 
 impl Beeper {
-    /// Constructs a Beeper using the give SDL context.
-    pub fn new(context: &Sdl, duration: Duration) -> Self {
+    /// Constructs a Beeper using the give SDL context, playing the given
+    /// sound.
+    pub fn new(context: &Sdl, sound: Sound) -> Self {
         let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
+            freq: Some(SAMPLE_RATE as i32),
             channels: Some(1),
-            samples: None,
+            samples: Some(buffer_size()),
         };
         let sub = context.audio().unwrap();
         let device = sub.open_playback(None, &desired_spec, |spec| {
@@ -46,30 +43,23 @@ impl Beeper {
             debug!("{:?}", spec);
 
             // initialize the audio callback
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25
-            }
+            Callback(Tone::new(spec.freq as u32, sound))
         }).unwrap();
 
-        Beeper {
-            device: device,
-            duration: duration,
-            start: Instant::now(),
-        }
+        device.resume();
+        Beeper { device }
+    }
+}
+
+impl AudioSink for Beeper {
+    /// Sets the XO-CHIP audio pattern to play instead of the square wave, and
+    /// the rate (in bits per second) to play it at.
+    fn set_pattern(&mut self, pattern: Option<[u8; chip8::PATTERN_SIZE]>, rate: f32) {
+        self.device.lock().0.set_pattern(pattern, rate);
     }
 
-    /// Starts the beep if necessary, and stops the beep if the beep duration
-    /// has passed.
-    pub fn set_beep(&mut self, enable: bool) {
-        if enable {
-            self.start = Instant::now();
-            self.device.resume();
-        } else {
-            if self.duration <= Instant::now().duration_since(self.start) {
-                self.device.pause();
-            }
-        }
+    /// Plays the beep while the sound timer runs.
+    fn set_beep(&mut self, enable: bool) {
+        self.device.lock().0.set_playing(enable);
     }
 }
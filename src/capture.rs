@@ -0,0 +1,220 @@
+use chip8::flicker::{self, Filter};
+use chip8::frontend::{VideoSink, FRAME_RATE};
+use chip8::gif;
+use chip8::palette::Palette;
+use chip8::tone::{Sound, Tone, SAMPLE_RATE};
+use chip8::wav;
+use chip8::{Chip8, HIRES_HEIGHT, HIRES_WIDTH};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The first file named after the ROM with the given extension that can be
+/// written without replacing an earlier one.
+pub fn unused_path(rom: &str, extension: &str) -> String {
+    (1..).map(|number| format!("{}.{}.{}", rom, number, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+/// How recordings are made.
+#[derive(Clone, Copy)]
+pub struct Options {
+    /// How many times bigger than the high resolution display frames are
+    pub scale: usize,
+    /// How flicker is hidden in the recorded frames
+    pub flicker: flicker::Mode,
+    /// Whether to write raw frames and sound as well as the GIF
+    pub raw: bool,
+    /// How the recorded beep sounds
+    pub sound: Sound,
+}
+
+/// Every frame as raw RGB, and the sound as a WAV, for muxing into a video
+/// later.
+struct Raw {
+    video: BufWriter<File>,
+    sound: wav::Writer<BufWriter<File>>,
+    tone: Tone,
+    samples: Vec<f32>,
+}
+
+/// A recording of the display, as it looks after anti-flicker, in a GIF.
+pub struct Capture {
+    path: String,
+    scale: usize,
+    filter: Filter,
+    gif: gif::Encoder<BufWriter<File>>,
+    raw: Option<Raw>,
+}
+
+impl Capture {
+    /// Starts recording to the GIF at `path`.  Raw frames go to a `.rgb`
+    /// file and the sound to a `.wav` file of the same name.
+    pub fn new(path: &str, options: Options) -> io::Result<Self> {
+        let (width, height) = Capture::size(options.scale);
+        let gif = gif::Encoder::new(BufWriter::new(File::create(path)?), width, height)?;
+        let raw = if options.raw {
+            let path = Path::new(path);
+            let video = BufWriter::new(File::create(path.with_extension("rgb"))?);
+            let sound = BufWriter::new(File::create(path.with_extension("wav"))?);
+            Some(Raw {
+                video,
+                sound: wav::Writer::new(sound, SAMPLE_RATE)?,
+                tone: Tone::new(SAMPLE_RATE, options.sound),
+                samples: vec![0.0; (SAMPLE_RATE / FRAME_RATE) as usize],
+            })
+        } else {
+            None
+        };
+        Ok(Capture {
+            path: path.to_string(),
+            scale: options.scale,
+            filter: Filter::new(options.flicker, Palette::default()),
+            gif,
+            raw,
+        })
+    }
+
+    /// The size of recorded frames: the high resolution display, scaled up.
+    /// Low resolution frames are doubled to fill it, so that a game that
+    /// switches resolution records at one size.
+    pub fn size(scale: usize) -> (usize, usize) {
+        (HIRES_WIDTH as usize * scale, HIRES_HEIGHT as usize * scale)
+    }
+
+    /// The GIF being recorded to.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Records a 60 Hz frame of the chip's display in the given colours,
+    /// and its sound.
+    pub fn frame(&mut self, chip: &Chip8, palette: Palette) -> io::Result<()> {
+        self.filter.palette = palette;
+        self.filter.push(chip);
+        let colors = self.filter.render();
+        let (width, height) = Capture::size(self.scale);
+        let zoom = width / chip.width();
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in colors.chunks(chip.width()) {
+            for _ in 0..zoom {
+                for &color in row {
+                    pixels.extend((0..zoom).map(|_| color));
+                }
+            }
+        }
+
+        if let Some(ref mut raw) = self.raw {
+            raw.video.write_all(&pixels.concat())?;
+            raw.tone.set_pattern(chip.audio_pattern(), chip.pattern_rate());
+            raw.tone.set_playing(chip.sound_active());
+            raw.tone.fill(&mut raw.samples);
+            raw.sound.write(&raw.samples)?;
+        }
+        self.gif.frame(pixels)
+    }
+
+    /// Ends the recording, writing out what's left.
+    pub fn finish(self) -> io::Result<()> {
+        self.gif.finish()?;
+        if let Some(raw) = self.raw {
+            raw.video.into_inner().map_err(|e| e.into_error())?;
+            raw.sound.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// A display that can record what it shows, toggled by a hotkey.
+pub struct Recorder<V> {
+    pub video: V,
+    rom: String,
+    options: Options,
+    capture: Option<Capture>,
+}
+
+impl<V: VideoSink> Recorder<V> {
+    /// Wraps the display of the game loaded from `rom`, recording straight
+    /// away to `path` if there is one.
+    /// # Errors
+    /// Returns why the recording couldn't be started.
+    pub fn new(video: V, rom: &str, options: Options, path: Option<&str>) -> Result<Self, String> {
+        let capture = match path {
+            Some(path) => Some(Capture::new(path, options)
+                .map_err(|e| format!("Couldn't record to {}: {}", path, e))?),
+            None => None,
+        };
+        Ok(Recorder { video, rom: rom.to_string(), options, capture })
+    }
+
+    /// Stops recording, if it's recording.
+    /// # Errors
+    /// Returns why the recording couldn't be saved.
+    pub fn finish(&mut self) -> Result<Option<String>, String> {
+        match self.capture.take() {
+            Some(capture) => {
+                let path = capture.path().to_string();
+                capture.finish()
+                    .map(|()| Some(path.clone()))
+                    .map_err(|e| format!("Couldn't save recording to {}: {}", path, e))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl<V: VideoSink> VideoSink for Recorder<V> {
+    fn draw_frame(&mut self, chip: &Chip8) {
+        self.video.draw_frame(chip);
+        let palette = self.video.palette();
+        let failed = match self.capture {
+            Some(ref mut capture) => capture.frame(chip, palette).err(),
+            None => None,
+        };
+        if let Some(e) = failed {
+            let path = self.capture.take().unwrap().path().to_string();
+            self.video.show_status(&format!("Stopped recording to {}: {}", path, e));
+        }
+    }
+
+    fn show_status(&mut self, status: &str) {
+        self.video.show_status(status);
+    }
+
+    fn next_theme(&mut self) {
+        self.video.next_theme();
+    }
+
+    fn palette(&self) -> Palette {
+        self.video.palette()
+    }
+
+    /// Every frame is recorded, whether or not the display changed.
+    fn needs_redraw(&self) -> bool {
+        self.capture.is_some() || self.video.needs_redraw()
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.video.failure()
+    }
+
+    fn toggle_recording(&mut self) {
+        let status = if self.capture.is_some() {
+            match self.finish() {
+                Ok(path) => format!("Saved recording to {}", path.unwrap()),
+                Err(e) => e,
+            }
+        } else {
+            let path = unused_path(&self.rom, "gif");
+            match Capture::new(&path, self.options) {
+                Ok(capture) => {
+                    self.capture = Some(capture);
+                    format!("Recording to {}", path)
+                }
+                Err(e) => format!("Couldn't record to {}: {}", path, e),
+            }
+        };
+        self.video.show_status(&status);
+    }
+}
@@ -0,0 +1,127 @@
+//! Checksums used to identify ROMs and check data for corruption.
+
+/// The reversed CRC-32 polynomial used by zlib, PNG and friends.
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// Calculates the standard (zlib) CRC-32 of the given bytes.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Continues a CRC-32 from a previous result, as if the bytes given then
+/// were followed by these ones.  Starting from 0 is the same as `crc32`.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32_POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// The modulus of Adler-32's two sums: the largest prime below 65536.
+const ADLER32_MODULUS: u32 = 65521;
+
+/// Calculates the Adler-32 checksum that ends a zlib stream.
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1, 0);
+    for byte in data {
+        a = (a + *byte as u32) % ADLER32_MODULUS;
+        b = (b + a) % ADLER32_MODULUS;
+    }
+    b << 16 | a
+}
+
+/// Calculates the SHA-1 digest of the given bytes.  It's only used to
+/// recognise ROMs, where a stronger hash than CRC-32 means two games never
+/// share settings by accident.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+
+    // Pad with a 1 bit, then zeros up to 8 bytes short of a 64 byte block,
+    // then the length in bits
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    message.extend((0..8).rev().map(|byte| (bits >> (byte * 8)) as u8));
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from(bytes[0]) << 24 | u32::from(bytes[1]) << 16 |
+                    u32::from(bytes[2]) << 8 | u32::from(bytes[3]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e)
+                .wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, value) in h.iter_mut().zip(&[a, b, c, d, e]) {
+            *h = h.wrapping_add(*value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(h.iter()) {
+        bytes.copy_from_slice(&[(word >> 24) as u8, (word >> 16) as u8, (word >> 8) as u8, *word as u8]);
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::{adler32, crc32, crc32_update, sha1};
+
+    #[test]
+    fn crc32_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414F_A339);
+    }
+
+    #[test]
+    fn crc32_in_pieces() {
+        assert_eq!(crc32_update(crc32(b"12345"), b"6789"), crc32(b"123456789"));
+    }
+
+    #[test]
+    fn adler32_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn sha1_known_values() {
+        let hex = |digest: [u8; 20]| digest.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        assert_eq!(hex(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // Long enough to need a second block
+        assert_eq!(hex(sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+                   "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+        assert_eq!(hex(sha1(&[b'a'; 1000])), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+    }
+}
@@ -0,0 +1,206 @@
+//! Settings kept in a config file, so they needn't be given every time.
+//!
+//! The file is TOML, at `~/.config/chip8/config.toml` unless
+//! `$XDG_CONFIG_HOME` says otherwise.  Settings are named after the command
+//! line options they stand in for, and options given on the command line
+//! win.  Settings for a particular game go in a section under `roms`, keyed
+//! by the SHA-1 of the ROM, and win over the ones above them:
+//!
+//! ```toml
+//! scale = 10
+//! theme = "amber"
+//! keymap = "azerty"
+//! volume = 0.1
+//!
+//! [roms.0123456789abcdef0123456789abcdef01234567]
+//! quirks = "schip"
+//! ipf = 30
+//! mute = true
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+
+use toml::Value;
+
+use checksum::sha1;
+
+/// The settings that take a value, as the options of the same name do.
+pub const SETTINGS: [&str; 16] = [
+    "scale", "theme", "palette", "fg", "bg", "flicker", "ipf", "quirks", "keymap", "rewind",
+    "tone", "waveform", "volume", "frontend", "glyphs", "screenshot-scale",
+];
+
+/// The settings that are switched on or off, as the flags of the same name
+/// are.
+pub const FLAGS: [&str; 2] = ["mute", "panel"];
+
+/// Settings that replace another one when they're given: a theme replaces
+/// all of a palette.
+const REPLACES: [(&str, &str); 1] = [("theme", "palette")];
+
+/// Where the config file is, if there's a home directory to put it in.
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("chip8").join("config.toml"))
+}
+
+/// The name a ROM's section goes by: its SHA-1, in lower case hex.
+pub fn rom_key(rom: &[u8]) -> String {
+    sha1(rom).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The settings that apply to one game.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Each setting's value, as it would be given on the command line
+    settings: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Reads a config file, applying the section for the ROM with the given
+    /// key.
+    pub fn parse(text: &str, rom_key: &str) -> Result<Self, String> {
+        let file = text.parse::<Value>().map_err(|e| format!("Bad config: {}", e))?;
+        let mut config = Config::default();
+        config.set_all(&file)?;
+        if let Some(roms) = file.get("roms") {
+            let roms = roms.as_table()
+                .ok_or_else(|| "roms must be a table of ROM sections".to_string())?;
+            if let Some(section) = roms.get(&rom_key.to_lowercase()) {
+                config.set_all(section)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Sets every setting in a table, apart from the ROM sections.
+    fn set_all(&mut self, table: &Value) -> Result<(), String> {
+        let table = table.as_table()
+            .ok_or_else(|| "A ROM's settings must be a table".to_string())?;
+        let mut settings = BTreeMap::new();
+        for (name, value) in table {
+            if name == "roms" {
+                continue;
+            }
+            let value = if FLAGS.contains(&name.as_str()) {
+                value.as_bool()
+                    .ok_or_else(|| format!("{} must be true or false", name))?
+                    .to_string()
+            } else if SETTINGS.contains(&name.as_str()) {
+                match *value {
+                    Value::String(ref text) => text.clone(),
+                    Value::Integer(number) => number.to_string(),
+                    Value::Float(number) => number.to_string(),
+                    _ => return Err(format!("{} must be a string or a number", name)),
+                }
+            } else {
+                return Err(format!("Unknown setting: {}", name));
+            };
+            settings.insert(name.clone(), value);
+        }
+        // Only settings from an earlier table are replaced, since the ones
+        // in this table are given together
+        for &(setting, replaced) in REPLACES.iter() {
+            if settings.contains_key(setting) {
+                self.settings.remove(replaced);
+            }
+        }
+        self.settings.extend(settings);
+        Ok(())
+    }
+
+    /// The value of a setting, if it's set.  Flags are `true` or `false`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.settings.get(name).map(String::as_str)
+    }
+
+    /// The settings as command line arguments, leaving out the ones the
+    /// command line already has according to `given`, so that it wins.
+    pub fn args<F: Fn(&str) -> bool>(&self, given: F) -> Vec<String> {
+        let mut args = Vec::new();
+        for (name, value) in &self.settings {
+            let replaced = REPLACES.iter()
+                .any(|&(setting, replaced)| replaced == name && given(setting));
+            if given(name) || replaced {
+                continue;
+            }
+            if FLAGS.contains(&name.as_str()) {
+                if value == "true" {
+                    args.push(format!("--{}", name));
+                }
+            } else {
+                args.push(format!("--{}", name));
+                args.push(value.clone());
+            }
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{rom_key, Config};
+
+    const FILE: &str = r#"
+        scale = 10
+        theme = "amber"
+        palette = "000000,ffffff,ff0000,00ff00"
+        volume = 0.5
+        panel = true
+
+        [roms.a9993e364706816aba3e25717850c26c9cd0d89d]
+        quirks = "schip"
+        theme = "classic"
+        panel = false
+    "#;
+
+    #[test]
+    fn rom_sections() {
+        assert_eq!(rom_key(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+
+        let config = Config::parse(FILE, &rom_key(b"tetris")).unwrap();
+        assert_eq!(config.get("scale"), Some("10"));
+        assert_eq!(config.get("volume"), Some("0.5"));
+        assert_eq!(config.get("palette"), Some("000000,ffffff,ff0000,00ff00"));
+        assert_eq!(config.get("quirks"), None);
+
+        let config = Config::parse(FILE, &rom_key(b"abc")).unwrap();
+        assert_eq!(config.get("scale"), Some("10"));
+        assert_eq!(config.get("quirks"), Some("schip"));
+        assert_eq!(config.get("theme"), Some("classic"));
+        assert_eq!(config.get("panel"), Some("false"));
+        // The game's theme replaces the palette given for every game
+        assert_eq!(config.get("palette"), None);
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let config = Config::parse(FILE, "").unwrap();
+        let args = config.args(|name| name == "scale");
+        assert_eq!(args, ["--palette", "000000,ffffff,ff0000,00ff00", "--panel",
+                          "--theme", "amber", "--volume", "0.5"]);
+
+        // A theme on the command line replaces the palette too
+        let args = config.args(|name| name == "theme");
+        assert_eq!(args, ["--panel", "--scale", "10", "--volume", "0.5"]);
+
+        let config = Config::parse(FILE, "A9993E364706816ABA3E25717850C26C9CD0D89D").unwrap();
+        assert!(!config.args(|_| false).contains(&"--panel".to_string()));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Config::parse("speed = 10", "").is_err());
+        assert!(Config::parse("mute = \"yes\"", "").is_err());
+        assert!(Config::parse("theme = [\"amber\"]", "").is_err());
+        assert!(Config::parse("roms = 1", "").is_err());
+        assert!(Config::parse("[roms]\nabc = 1", "abc").is_err());
+        assert!(Config::parse("scale = ", "").is_err());
+        assert_eq!(Config::parse("", "").unwrap(), Config::default());
+    }
+}
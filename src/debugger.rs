@@ -0,0 +1,374 @@
+use chip8::disasm;
+use chip8::{Chip8, CycleOutcome};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+/// The help message listing the debugger's commands.
+const HELP: &str = "\
+Commands (numbers are decimal, or hex with a 0x prefix):
+  break, b [ADDR]        Break at ADDR, or list the breakpoints
+  delete, d ADDR         Remove the breakpoint at ADDR
+  step, s [N]            Run N instructions (default 1)
+  continue, c            Run until a breakpoint, or F12 in the window
+  regs, r                Print the registers, I, PC and timers
+  stack                  Print the stack
+  x ADDR [LEN]           Hexdump LEN bytes of memory (default 64)
+  dis [ADDR] [N]         Disassemble N instructions (default around PC)
+  set REG VALUE          Set v0-vF, i, pc, dt or st
+  poke ADDR BYTE...      Write bytes into memory
+  quit, q                Stop emulating
+An empty line repeats the last command.";
+
+/// What the emulation loop should do once the debugger hands back control.
+enum Action {
+    /// Run the next instruction.
+    Run,
+    /// Stop emulating.
+    Quit,
+}
+
+/// An interactive, gdb style debugger that takes commands from stdin while
+/// the chip is stopped.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    /// How many more instructions to run before stopping, when stepping.
+    steps: Option<usize>,
+    /// Set when resuming, so the breakpoint we're sat on doesn't stop us
+    /// straight away.
+    resuming: bool,
+    last_command: String,
+}
+
+impl Debugger {
+    /// Constructs a Debugger that stops before the first instruction.
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            steps: Some(0),
+            resuming: false,
+            last_command: String::new(),
+        }
+    }
+
+    /// Stops before the next instruction, e.g. when the user presses the
+    /// break key.
+    pub fn interrupt(&mut self) {
+        self.steps = Some(0);
+    }
+
+    /// Runs one 60 Hz frame of up to `instructions` instructions, the same as
+    /// `Chip8::run_frame`, but stopping for breakpoints, steps and faults.
+    ///
+    /// Returns `None` if the user quit.
+    pub fn run_frame(&mut self, chip: &mut Chip8, instructions: usize) -> Option<CycleOutcome> {
+        let mut outcome = CycleOutcome::Continue;
+        for _ in 0..instructions {
+            loop {
+                if let Action::Quit = self.check(chip) {
+                    return None;
+                }
+                match chip.step_instruction() {
+                    Ok(result) => {
+                        outcome = result;
+                        break;
+                    }
+                    Err(e) => {
+                        // Let the user look around, and maybe fix things up
+                        println!("Emulation stopped: {}", e);
+                        self.interrupt();
+                    }
+                }
+            }
+            match outcome {
+                CycleOutcome::Continue => {}
+                CycleOutcome::WaitingForKey => break,
+                CycleOutcome::Exit => return Some(outcome),
+            }
+        }
+
+        chip.tick_timers();
+
+        Some(outcome)
+    }
+
+    /// Checks whether to stop before the chip's next instruction, and if so
+    /// takes commands until the user carries on.
+    fn check(&mut self, chip: &mut Chip8) -> Action {
+        let at_breakpoint = !self.resuming && self.breakpoints.contains(&chip.pc());
+        self.resuming = false;
+        let stop = match self.steps {
+            Some(0) => true,
+            Some(n) => {
+                self.steps = Some(n - 1);
+                at_breakpoint
+            }
+            None => at_breakpoint,
+        };
+        if !stop {
+            return Action::Run;
+        }
+
+        if at_breakpoint {
+            println!("Breakpoint at {:#06X}", chip.pc());
+        }
+        self.steps = None;
+        self.disassemble(chip, chip.pc() as usize, 1);
+        self.repl(chip)
+    }
+
+    /// Reads and runs commands until one of them carries on or quits.
+    fn repl(&mut self, chip: &mut Chip8) -> Action {
+        let stdin = io::stdin();
+        loop {
+            print!("(chip8) ");
+            io::stdout().flush().unwrap();
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return Action::Quit,
+                Ok(_) => {}
+            }
+
+            if line.trim().is_empty() {
+                line = self.last_command.clone();
+            } else {
+                self.last_command = line.trim().to_string();
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match self.command(chip, &words) {
+                Ok(Some(action)) => return action,
+                Ok(None) => {}
+                Err(message) => println!("{}", message),
+            }
+        }
+    }
+
+    /// Runs a single command, returning the action to take if it ends the
+    /// prompt.
+    fn command(&mut self, chip: &mut Chip8, words: &[&str]) -> Result<Option<Action>, String> {
+        match words {
+            [] => {}
+            ["help"] | ["h"] => println!("{}", HELP),
+            ["break"] | ["b"] => {
+                for address in &self.breakpoints {
+                    println!("{:#06X}", address);
+                }
+            }
+            ["break", address] | ["b", address] => {
+                self.breakpoints.insert(parse_limited(address, 0xFFFF)? as u16);
+            }
+            ["delete", address] | ["d", address] => {
+                if !self.breakpoints.remove(&(parse_limited(address, 0xFFFF)? as u16)) {
+                    return Err(format!("No breakpoint at {}", address));
+                }
+            }
+            ["step"] | ["s"] => return Ok(Some(self.resume(Some(1)))),
+            ["step", n] | ["s", n] => return Ok(Some(self.resume(Some(parse_number(n)?)))),
+            ["continue"] | ["c"] => return Ok(Some(self.resume(None))),
+            ["regs"] | ["r"] => print_registers(chip),
+            ["stack"] => {
+                for (depth, address) in chip.stack().iter().enumerate().rev() {
+                    println!("#{} {:#06X}", depth, address);
+                }
+            }
+            ["x", address] => hexdump(chip, parse_number(address)?, 64)?,
+            ["x", address, len] => hexdump(chip, parse_number(address)?, parse_number(len)?)?,
+            ["dis"] => {
+                let start = (chip.pc() as usize).saturating_sub(8);
+                self.disassemble(chip, start, 10);
+            }
+            ["dis", address] => self.disassemble(chip, parse_number(address)?, 10),
+            ["dis", address, n] => self.disassemble(chip, parse_number(address)?, parse_number(n)?),
+            ["set", target, value] => set(chip, target, parse_number(value)?)?,
+            ["poke", address, bytes @ ..] if !bytes.is_empty() => {
+                let address = parse_number(address)?;
+                let end = address.checked_add(bytes.len());
+                if end.filter(|&end| end <= chip.memory().len()).is_none() {
+                    return Err("Address out of range".to_string());
+                }
+                let bytes = bytes.iter()
+                    .map(|byte| parse_limited(byte, 0xFF))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    chip.memory_mut()[address + i] = byte as u8;
+                }
+            }
+            ["quit"] | ["q"] => return Ok(Some(Action::Quit)),
+            _ => return Err(format!("Unknown command: {} (try help)", words.join(" "))),
+        }
+        Ok(None)
+    }
+
+    /// Carries on running, stopping after the given number of steps.
+    fn resume(&mut self, steps: Option<usize>) -> Action {
+        self.steps = steps;
+        self.resuming = true;
+        Action::Run
+    }
+
+    /// Prints `count` instructions starting at `start`, marking the program
+    /// counter and breakpoints.
+    fn disassemble(&self, chip: &Chip8, start: usize, count: usize) {
+        let memory = chip.memory();
+        for address in (start..memory.len() - 1).step_by(2).take(count) {
+            let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+            let marker = if address == chip.pc() as usize { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&(address as u16)) { "*" } else { " " };
+            println!("{}{}{:#06X}: {:04X}  {}", marker, breakpoint, address, opcode, disasm::decode(opcode));
+        }
+    }
+}
+
+/// Parses a decimal number, or a hex number with a 0x prefix.
+fn parse_number(text: &str) -> Result<usize, String> {
+    let result = if text.starts_with("0x") || text.starts_with("0X") {
+        usize::from_str_radix(&text[2..], 16)
+    } else {
+        text.parse()
+    };
+    result.map_err(|_| format!("Not a number: {}", text))
+}
+
+/// Parses a number as `parse_number` does, making sure it's no more than
+/// `max`.
+fn parse_limited(text: &str, max: usize) -> Result<usize, String> {
+    let value = parse_number(text)?;
+    check_limit(value, max)
+}
+
+/// Makes sure a value is no more than `max`, rather than cutting it down.
+fn check_limit(value: usize, max: usize) -> Result<usize, String> {
+    if value > max {
+        Err(format!("{:#X} is too big (at most {:#X})", value, max))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Prints the registers, index, program counter and timers.
+fn print_registers(chip: &Chip8) {
+    for (row, regs) in chip.registers().chunks(8).enumerate() {
+        let line: Vec<String> = regs.iter()
+            .enumerate()
+            .map(|(i, value)| format!("v{:X}={:02X}", row * 8 + i, value))
+            .collect();
+        println!("{}", line.join(" "));
+    }
+    println!("pc={:04X} i={:04X} sp={} dt={:02X} st={:02X}",
+             chip.pc(), chip.index(), chip.stack().len(), chip.delay_timer(), chip.sound_timer());
+}
+
+/// Prints `len` bytes of memory starting at `start`, 16 bytes to a line.
+fn hexdump(chip: &Chip8, start: usize, len: usize) -> Result<(), String> {
+    let memory = chip.memory();
+    let end = start.checked_add(len)
+        .ok_or_else(|| "Address out of range".to_string())?
+        .min(memory.len());
+    for line_start in (start..end).step_by(16) {
+        let bytes: Vec<String> = memory[line_start..(line_start + 16).min(end)]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        println!("{:#06X}: {}", line_start, bytes.join(" "));
+    }
+    Ok(())
+}
+
+/// Changes a register, the index, the program counter or a timer.
+fn set(chip: &mut Chip8, target: &str, value: usize) -> Result<(), String> {
+    let target = target.to_lowercase();
+    match target.as_str() {
+        "i" => chip.set_index(check_limit(value, 0xFFFF)? as u16),
+        "pc" => chip.set_pc(check_limit(value, 0xFFFF)? as u16),
+        "dt" => chip.set_delay_timer(check_limit(value, 0xFF)? as u8),
+        "st" => chip.set_sound_timer(check_limit(value, 0xFF)? as u8),
+        _ if target.len() == 2 && target.starts_with('v') => {
+            let x = usize::from_str_radix(&target[1..], 16)
+                .map_err(|_| format!("Unknown register: {}", target))?;
+            chip.set_register(x, check_limit(value, 0xFF)? as u8);
+        }
+        _ => return Err(format!("Unknown register: {}", target)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_number, set, Action, Debugger};
+    use chip8::Chip8;
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("0x2A"), Ok(42));
+        assert_eq!(parse_number("0X2a"), Ok(42));
+        assert!(parse_number("2A").is_err());
+        assert!(parse_number("0x").is_err());
+        assert!(parse_number("-1").is_err());
+        assert!(parse_number("99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn set_registers() {
+        let mut chip = Chip8::default();
+        set(&mut chip, "vA", 0x12).unwrap();
+        set(&mut chip, "I", 0xABC).unwrap();
+        set(&mut chip, "pc", 0x300).unwrap();
+        set(&mut chip, "dt", 200).unwrap();
+        set(&mut chip, "st", 255).unwrap();
+        assert_eq!(chip.registers()[0xA], 0x12);
+        assert_eq!(chip.index(), 0xABC);
+        assert_eq!(chip.pc(), 0x300);
+        assert_eq!(chip.delay_timer(), 200);
+        assert_eq!(chip.sound_timer(), 255);
+
+        // Values too big for the register are refused, not cut down
+        assert!(set(&mut chip, "v0", 0x1FF).is_err());
+        assert!(set(&mut chip, "dt", 300).is_err());
+        assert!(set(&mut chip, "pc", 0x10000).is_err());
+        assert_eq!(chip.registers()[0], 0);
+        assert_eq!(chip.delay_timer(), 200);
+        assert!(set(&mut chip, "vG", 1).is_err());
+        assert!(set(&mut chip, "sp", 1).is_err());
+    }
+
+    #[test]
+    fn commands() {
+        let mut debugger = Debugger::new();
+        let mut chip = Chip8::default();
+
+        debugger.command(&mut chip, &["b", "0x300"]).unwrap();
+        debugger.command(&mut chip, &["break", "0x302"]).unwrap();
+        assert!(debugger.breakpoints.contains(&0x300));
+        debugger.command(&mut chip, &["d", "0x300"]).unwrap();
+        assert!(debugger.command(&mut chip, &["d", "0x300"]).is_err());
+        assert!(debugger.command(&mut chip, &["b", "0x10000"]).is_err());
+        assert_eq!(debugger.breakpoints.iter().collect::<Vec<_>>(), [&0x302]);
+
+        debugger.command(&mut chip, &["poke", "0x300", "1", "0xFF"]).unwrap();
+        assert_eq!(&chip.memory()[0x300..0x302], &[1, 0xFF]);
+        assert!(debugger.command(&mut chip, &["poke", "0x300", "0x100"]).is_err());
+        assert!(debugger.command(&mut chip, &["poke", "0xFFFF", "1", "2"]).is_err());
+        let huge = usize::MAX.to_string();
+        assert!(debugger.command(&mut chip, &["poke", &huge, "1"]).is_err());
+        assert!(debugger.command(&mut chip, &["x", "1", &huge]).is_err());
+        assert_eq!(&chip.memory()[0x300..0x302], &[1, 0xFF]);
+
+        debugger.command(&mut chip, &["set", "v3", "7"]).unwrap();
+        assert_eq!(chip.registers()[3], 7);
+
+        match debugger.command(&mut chip, &["s", "3"]) {
+            Ok(Some(Action::Run)) => assert_eq!(debugger.steps, Some(3)),
+            _ => panic!("step should carry on running"),
+        }
+        match debugger.command(&mut chip, &["c"]) {
+            Ok(Some(Action::Run)) => assert_eq!(debugger.steps, None),
+            _ => panic!("continue should carry on running"),
+        }
+        match debugger.command(&mut chip, &["q"]) {
+            Ok(Some(Action::Quit)) => {}
+            _ => panic!("quit should quit"),
+        }
+        assert!(debugger.command(&mut chip, &["frobnicate"]).is_err());
+        assert!(debugger.command(&mut chip, &["s", "many"]).is_err());
+    }
+}
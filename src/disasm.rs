@@ -0,0 +1,485 @@
+//! Decoding opcodes into instructions, and listing whole ROMs in assembly.
+//!
+//! The mnemonics are the widely used ones from Cowgod's CHIP-8 reference,
+//! extended with the SUPER-CHIP and XO-CHIP instructions.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction.  Registers are
+/// given by number, so `LoadByte(1, 0x2F)` is `LD V1, 0x2F`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0x0NNN: Call a machine code routine (not supported by the emulator)
+    Sys(u16),
+    /// 0x00CN: Scroll the display down by N pixels
+    ScrollDown(u8),
+    /// 0x00DN: Scroll the display up by N pixels
+    ScrollUp(u8),
+    /// 0x00E0: Clear the screen
+    Clear,
+    /// 0x00EE: Return from subroutine
+    Return,
+    /// 0x00FB: Scroll the display right by 4 pixels
+    ScrollRight,
+    /// 0x00FC: Scroll the display left by 4 pixels
+    ScrollLeft,
+    /// 0x00FD: Exit the interpreter
+    Exit,
+    /// 0x00FE: Switch to low resolution mode
+    Low,
+    /// 0x00FF: Switch to high resolution mode
+    High,
+    /// 0x1NNN: Jump to address NNN
+    Jump(u16),
+    /// 0x2NNN: Call subroutine at NNN
+    Call(u16),
+    /// 0x3XNN: Skip next instruction if regX equals NN
+    SkipEqualByte(u8, u8),
+    /// 0x4XNN: Skip next instruction if regX does not equal NN
+    SkipNotEqualByte(u8, u8),
+    /// 0x5XY0: Skip next instruction if regX equals regY
+    SkipEqual(u8, u8),
+    /// 0x5XY2: Store regX through regY in memory starting at index
+    SaveRange(u8, u8),
+    /// 0x5XY3: Fill regX through regY from memory starting at index
+    LoadRange(u8, u8),
+    /// 0x6XNN: Set regX to NN
+    LoadByte(u8, u8),
+    /// 0x7XNN: Add NN to regX
+    AddByte(u8, u8),
+    /// 0x8XY0: Set regX to regY
+    Load(u8, u8),
+    /// 0x8XY1: Set regX to regX | regY
+    Or(u8, u8),
+    /// 0x8XY2: Set regX to regX & regY
+    And(u8, u8),
+    /// 0x8XY3: Set regX to regX ^ regY
+    Xor(u8, u8),
+    /// 0x8XY4: Add regY to regX, setting carry
+    Add(u8, u8),
+    /// 0x8XY5: regX -= regY, setting not borrow
+    Sub(u8, u8),
+    /// 0x8XY6: Shift right by one
+    ShiftRight(u8, u8),
+    /// 0x8XY7: regX = regY - regX, setting not borrow
+    SubFrom(u8, u8),
+    /// 0x8XYE: Shift left by one
+    ShiftLeft(u8, u8),
+    /// 0x9XY0: Skip next instruction if regX does not equal regY
+    SkipNotEqual(u8, u8),
+    /// 0xANNN: Set index to NNN
+    LoadIndex(u16),
+    /// 0xBNNN: Jump to NNN + reg0
+    JumpOffset(u16),
+    /// 0xCXNN: regX = random number & NN
+    Random(u8, u8),
+    /// 0xDXYN: Draw an N row sprite at regX,regY (16x16 when N is 0)
+    Draw(u8, u8, u8),
+    /// 0xEX9E: Skip next instruction if the key in regX is pressed
+    SkipKey(u8),
+    /// 0xEXA1: Skip next instruction if the key in regX is not pressed
+    SkipNotKey(u8),
+    /// 0xF000 NNNN: Set index to the 16 bit address in the next two bytes
+    LoadIndexLong,
+    /// 0xFN01: Select the planes in bitmask N for drawing
+    Plane(u8),
+    /// 0xF002: Load the audio pattern from memory starting at index
+    Audio,
+    /// 0xFX07: Set regX to the delay timer
+    LoadDelay(u8),
+    /// 0xFX0A: Wait for a key press and store it in regX
+    WaitKey(u8),
+    /// 0xFX15: Set the delay timer to regX
+    SetDelay(u8),
+    /// 0xFX18: Set the sound timer to regX
+    SetSound(u8),
+    /// 0xFX1E: Add regX to index
+    AddIndex(u8),
+    /// 0xFX29: Set index to the small font character in regX
+    Font(u8),
+    /// 0xFX30: Set index to the large font character in regX
+    BigFont(u8),
+    /// 0xFX33: Store the binary coded decimal of regX at index
+    Bcd(u8),
+    /// 0xFX3A: Set the audio pitch to regX
+    Pitch(u8),
+    /// 0xFX55: Store reg0 through regX in memory starting at index
+    Save(u8),
+    /// 0xFX65: Fill reg0 through regX from memory starting at index
+    Restore(u8),
+    /// 0xFX75: Store reg0 through regX in the RPL user flags
+    SaveFlags(u8),
+    /// 0xFX85: Fill reg0 through regX from the RPL user flags
+    RestoreFlags(u8),
+    /// Not an instruction
+    Unknown(u16),
+}
+
+/// Decodes a single opcode.
+pub fn decode(opcode: u16) -> Instruction {
+    use self::Instruction::*;
+
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let nn = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00C0..=0x00CF => ScrollDown(n),
+            0x00D0..=0x00DF => ScrollUp(n),
+            0x00E0 => Clear,
+            0x00EE => Return,
+            0x00FB => ScrollRight,
+            0x00FC => ScrollLeft,
+            0x00FD => Exit,
+            0x00FE => Low,
+            0x00FF => High,
+            _ => Sys(nnn),
+        },
+        0x1000 => Jump(nnn),
+        0x2000 => Call(nnn),
+        0x3000 => SkipEqualByte(x, nn),
+        0x4000 => SkipNotEqualByte(x, nn),
+        0x5000 => match n {
+            0x0 => SkipEqual(x, y),
+            0x2 => SaveRange(x, y),
+            0x3 => LoadRange(x, y),
+            _ => Unknown(opcode),
+        },
+        0x6000 => LoadByte(x, nn),
+        0x7000 => AddByte(x, nn),
+        0x8000 => match n {
+            0x0 => Load(x, y),
+            0x1 => Or(x, y),
+            0x2 => And(x, y),
+            0x3 => Xor(x, y),
+            0x4 => Add(x, y),
+            0x5 => Sub(x, y),
+            0x6 => ShiftRight(x, y),
+            0x7 => SubFrom(x, y),
+            0xE => ShiftLeft(x, y),
+            _ => Unknown(opcode),
+        },
+        0x9000 if n == 0 => SkipNotEqual(x, y),
+        0xA000 => LoadIndex(nnn),
+        0xB000 => JumpOffset(nnn),
+        0xC000 => Random(x, nn),
+        0xD000 => Draw(x, y, n),
+        0xE000 => match nn {
+            0x9E => SkipKey(x),
+            0xA1 => SkipNotKey(x),
+            _ => Unknown(opcode),
+        },
+        0xF000 => match nn {
+            0x00 if x == 0 => LoadIndexLong,
+            0x01 => Plane(x),
+            0x02 if x == 0 => Audio,
+            0x07 => LoadDelay(x),
+            0x0A => WaitKey(x),
+            0x15 => SetDelay(x),
+            0x18 => SetSound(x),
+            0x1E => AddIndex(x),
+            0x29 => Font(x),
+            0x30 => BigFont(x),
+            0x33 => Bcd(x),
+            0x3A => Pitch(x),
+            0x55 => Save(x),
+            0x65 => Restore(x),
+            0x75 => SaveFlags(x),
+            0x85 => RestoreFlags(x),
+            _ => Unknown(opcode),
+        },
+        _ => Unknown(opcode),
+    }
+}
+
+impl Instruction {
+    /// The number of bytes the instruction takes up, which is 4 for the long
+    /// index load and 2 for everything else.
+    pub fn size(&self) -> usize {
+        match *self {
+            Instruction::LoadIndexLong => 4,
+            _ => 2,
+        }
+    }
+
+    /// The address this instruction jumps or calls to, if it's fixed.
+    pub fn target(&self) -> Option<u16> {
+        match *self {
+            Instruction::Jump(address) | Instruction::Call(address) => Some(address),
+            _ => None,
+        }
+    }
+
+    /// Whether this instruction may skip over the next one.
+    pub fn is_skip(&self) -> bool {
+        use self::Instruction::*;
+        matches!(*self, SkipEqualByte(..) | SkipNotEqualByte(..) | SkipEqual(..) |
+                        SkipNotEqual(..) | SkipKey(_) | SkipNotKey(_))
+    }
+
+    /// Whether running carries on to the next instruction afterwards (or the
+    /// one after that, for skips).
+    pub fn falls_through(&self) -> bool {
+        use self::Instruction::*;
+        !matches!(*self, Return | Exit | Jump(_) | JumpOffset(_) | Unknown(_))
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+        match *self {
+            Sys(address) => write!(f, "SYS {:#05X}", address),
+            ScrollDown(n) => write!(f, "SCD {}", n),
+            ScrollUp(n) => write!(f, "SCU {}", n),
+            Clear => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Jump(address) => write!(f, "JP {:#05X}", address),
+            Call(address) => write!(f, "CALL {:#05X}", address),
+            SkipEqualByte(x, nn) => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            SkipNotEqualByte(x, nn) => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            SkipEqual(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveRange(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            LoadRange(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            LoadByte(x, nn) => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            AddByte(x, nn) => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Load(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubFrom(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipNotEqual(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadIndex(address) => write!(f, "LD I, {:#05X}", address),
+            JumpOffset(address) => write!(f, "JP V0, {:#05X}", address),
+            Random(x, nn) => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKey(x) => write!(f, "SKP V{:X}", x),
+            SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            LoadIndexLong => write!(f, "LD I, LONG"),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            LoadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            AddIndex(x) => write!(f, "ADD I, V{:X}", x),
+            Font(x) => write!(f, "LD F, V{:X}", x),
+            BigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Pitch(x) => write!(f, "LD PITCH, V{:X}", x),
+            Save(x) => write!(f, "LD [I], V{:X}", x),
+            Restore(x) => write!(f, "LD V{:X}, [I]", x),
+            SaveFlags(x) => write!(f, "LD R, V{:X}", x),
+            RestoreFlags(x) => write!(f, "LD V{:X}, R", x),
+            Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+/// Reads the big-endian word at `address`, if there's a whole one there.
+fn word_at(rom: &[u8], address: usize) -> Option<u16> {
+    if address + 1 < rom.len() {
+        Some((rom[address] as u16) << 8 | rom[address + 1] as u16)
+    } else {
+        None
+    }
+}
+
+/// Finds the offsets into the ROM of every instruction that can be reached
+/// by running it from the start, and the addresses that are jumped or called
+/// to.
+///
+/// Jumps through `JP V0` can't be followed, so code only reached that way is
+/// listed as data.
+fn trace(rom: &[u8], origin: u16) -> (BTreeSet<usize>, BTreeSet<u16>) {
+    let mut code = BTreeSet::new();
+    let mut targets = BTreeSet::new();
+    let mut pending = vec![0];
+    while let Some(offset) = pending.pop() {
+        if code.contains(&offset) {
+            continue;
+        }
+        let instruction = match word_at(rom, offset) {
+            Some(opcode) => decode(opcode),
+            None => continue,
+        };
+        if let Instruction::Unknown(_) = instruction {
+            continue;
+        }
+        if offset + instruction.size() > rom.len() {
+            continue;
+        }
+        code.insert(offset);
+
+        let next = offset + instruction.size();
+        if instruction.falls_through() {
+            pending.push(next);
+        }
+        if instruction.is_skip() {
+            // The skipped instruction might be a long one
+            let skipped = match word_at(rom, next) {
+                Some(0xF000) => 4,
+                _ => 2,
+            };
+            pending.push(next + skipped);
+        }
+        if let Some(target) = instruction.target() {
+            targets.insert(target);
+            if target >= origin {
+                pending.push((target - origin) as usize);
+            }
+        }
+    }
+    (code, targets)
+}
+
+/// The label given to a jump or call target.
+fn label(address: u16) -> String {
+    format!("L{:03X}", address)
+}
+
+/// Lists a ROM that is loaded at `origin` as assembly.  Code that can be
+/// reached from the start is listed as instructions, with labels on every
+/// jump and call target, and everything else is listed as `DB` data.  The
+/// address and raw bytes of each line follow in a comment.  Anything past
+/// the end of the 16 bit address space is left out, since it can't be
+/// loaded.
+pub fn listing(rom: &[u8], origin: u16) -> String {
+    let rom = &rom[..rom.len().min(0x10000 - origin as usize)];
+    let (code, targets) = trace(rom, origin);
+    let labels: BTreeMap<u16, String> = targets.iter()
+        .filter(|&&target| target >= origin && ((target - origin) as usize) < rom.len())
+        .map(|&target| (target, label(target)))
+        .collect();
+
+    let mut out = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = origin + offset as u16;
+        if let Some(name) = labels.get(&address) {
+            out.push_str(&format!("{}:\n", name));
+        }
+
+        let (text, len) = if code.contains(&offset) {
+            let instruction = decode(word_at(rom, offset).unwrap());
+            let text = match instruction {
+                Instruction::Jump(target) if labels.contains_key(&target) =>
+                    format!("JP {}", labels[&target]),
+                Instruction::Call(target) if labels.contains_key(&target) =>
+                    format!("CALL {}", labels[&target]),
+                Instruction::LoadIndexLong =>
+                    format!("LD I, LONG {:#06X}", word_at(rom, offset + 2).unwrap()),
+                _ => instruction.to_string(),
+            };
+            (text, instruction.size())
+        } else {
+            // Data runs up to the next code or label, eight bytes a line
+            let len = (offset..rom.len())
+                .take(8)
+                .take_while(|&o| o == offset || !(code.contains(&o) ||
+                                                   labels.contains_key(&(origin + o as u16))))
+                .count();
+            let bytes: Vec<String> = rom[offset..offset + len]
+                .iter()
+                .map(|byte| format!("{:#04X}", byte))
+                .collect();
+            (format!("DB {}", bytes.join(", ")), len)
+        };
+
+        let raw: Vec<String> = rom[offset..offset + len]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        out.push_str(&format!("    {:<28} ; {:04X}  {}\n", text, address, raw.join("")));
+        offset += len;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, listing, Instruction};
+
+    #[test]
+    fn decodes_mnemonics() {
+        assert_eq!(decode(0x612F).to_string(), "LD V1, 0x2F");
+        assert_eq!(decode(0xD015).to_string(), "DRW V0, V1, 5");
+        assert_eq!(decode(0x00E0).to_string(), "CLS");
+        assert_eq!(decode(0x2ABC).to_string(), "CALL 0xABC");
+        assert_eq!(decode(0xA200).to_string(), "LD I, 0x200");
+        assert_eq!(decode(0xFA65).to_string(), "LD VA, [I]");
+        assert_eq!(decode(0x8126), Instruction::ShiftRight(1, 2));
+        assert_eq!(decode(0x5121), Instruction::Unknown(0x5121));
+        assert_eq!(decode(0xF000).size(), 4);
+    }
+
+    #[test]
+    fn matches_the_interpreter() {
+        // Every opcode the interpreter runs decodes, and every opcode it
+        // rejects doesn't (apart from SYS, which is decoded but never run)
+        let mut chip = ::Chip8::default();
+        chip.load(&[0x00, 0x00, 0x00, 0x00]);
+        for opcode in 0..=0xFFFFu32 {
+            let opcode = opcode as u16;
+            chip.memory_mut()[0x200] = (opcode >> 8) as u8;
+            chip.memory_mut()[0x201] = opcode as u8;
+            chip.set_pc(0x200);
+            chip.set_index(0x300);
+            let rejected = matches!(chip.step_instruction(),
+                                    Err(::Chip8Error::InvalidOpcode { .. }));
+            let decoded = !matches!(decode(opcode),
+                                    Instruction::Unknown(_) | Instruction::Sys(_));
+            assert!(rejected != decoded, "{:#06X} decoded as {}", opcode, decode(opcode));
+            // Don't let calls fill the stack up
+            while !chip.stack().is_empty() {
+                chip.memory_mut()[0x200] = 0x00;
+                chip.memory_mut()[0x201] = 0xEE;
+                chip.set_pc(0x200);
+                chip.step_instruction().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn lists_code_and_data() {
+        let rom = [
+            0x22, 0x06, // CALL L206
+            0x12, 0x02, // JP L202
+            0xFF, 0x80, // data
+            0xA2, 0x04, // LD I, 0x204
+            0x00, 0xEE, // RET
+        ];
+        let text = listing(&rom, 0x200);
+        let lines: Vec<&str> = text.lines().map(|line| line.trim_end()).collect();
+        assert_eq!(lines, vec![
+            "    CALL L206                    ; 0200  2206",
+            "L202:",
+            "    JP L202                      ; 0202  1202",
+            "    DB 0xFF, 0x80                ; 0204  FF80",
+            "L206:",
+            "    LD I, 0x204                  ; 0206  A204",
+            "    RET                          ; 0208  00EE",
+        ]);
+    }
+
+    #[test]
+    fn stops_at_the_end_of_memory() {
+        let rom = vec![0xFF; 0x10000];
+        let text = listing(&rom, 0x200);
+        assert_eq!(text.lines().count(), (0x10000 - 0x200) / 8);
+        let last = text.lines().last().unwrap();
+        assert!(last.ends_with("; FFF8  FFFFFFFFFFFFFFFF"), "{}", last);
+    }
+}
@@ -0,0 +1,197 @@
+//! Ways of hiding the flicker of XOR drawn sprites.
+//!
+//! Games move a sprite by drawing it again to erase it and then drawing it
+//! somewhere else, so a frame that ends between the two shows it missing.
+//! Frontends already draw only once a frame, as the original's 60 Hz
+//! display did, but that still catches sprites half erased.  Blending the
+//! last few frames together, or letting pixels fade out slowly like the
+//! phosphor of an old monitor, fills those gaps in.
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use palette::{Color, Palette};
+use Chip8;
+
+/// How many frames are blended if the mode doesn't say.
+const DEFAULT_BLEND: usize = 2;
+
+/// How much brightness a pixel keeps each frame while fading, if the mode
+/// doesn't say.
+const DEFAULT_FADE: f32 = 0.6;
+
+/// How to turn the frames the chip draws into frames to show.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Show the display as it is at the end of each 60 Hz frame.
+    Vblank,
+    /// Light each pixel that was lit in any of the last N frames.
+    Blend(usize),
+    /// Fade pixels out after they're turned off, keeping this fraction of
+    /// their brightness each frame.
+    Fade(f32),
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    /// Parses `vblank`, `blend` or `blend:FRAMES`, or `fade` or
+    /// `fade:KEEP` where KEEP is between 0 and 1.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts = text.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let value = parts.next();
+        let bad = || format!("Bad anti-flicker mode {}, expected vblank, blend[:FRAMES] or fade[:KEEP]", text);
+        match (name, value) {
+            ("vblank", None) => Ok(Mode::Vblank),
+            ("blend", None) => Ok(Mode::Blend(DEFAULT_BLEND)),
+            ("blend", Some(frames)) => frames.parse().ok()
+                .filter(|&frames| frames > 0)
+                .map(Mode::Blend)
+                .ok_or_else(bad),
+            ("fade", None) => Ok(Mode::Fade(DEFAULT_FADE)),
+            ("fade", Some(keep)) => keep.parse().ok()
+                .filter(|keep| (0.0..1.0).contains(keep))
+                .map(Mode::Fade)
+                .ok_or_else(bad),
+            _ => Err(bad()),
+        }
+    }
+}
+
+/// Keeps the recent frames a mode needs, and renders them in a palette.
+pub struct Filter {
+    pub palette: Palette,
+    mode: Mode,
+    /// The last frames drawn, oldest first
+    frames: VecDeque<Vec<u8>>,
+    /// The planes each pixel was last lit on, and how bright it still is
+    glow: Vec<(u8, f32)>,
+}
+
+impl Filter {
+    pub fn new(mode: Mode, palette: Palette) -> Self {
+        Filter {
+            palette,
+            mode,
+            frames: VecDeque::new(),
+            glow: Vec::new(),
+        }
+    }
+
+    /// Whether the shown frame can change when the chip's display hasn't, so
+    /// that every frame needs to be drawn.
+    pub fn is_active(&self) -> bool {
+        self.mode != Mode::Vblank
+    }
+
+    /// Takes the chip's display at the end of a frame.
+    pub fn push(&mut self, chip: &Chip8) {
+        let graphics = &chip.graphics;
+        match self.mode {
+            Mode::Vblank | Mode::Blend(_) => {
+                // Frames of another resolution can't be blended
+                if self.frames.back().map(Vec::len) != Some(graphics.len()) {
+                    self.frames.clear();
+                }
+                self.frames.push_back(graphics.clone());
+                let keep = match self.mode {
+                    Mode::Blend(frames) => frames,
+                    _ => 1,
+                };
+                while self.frames.len() > keep {
+                    self.frames.pop_front();
+                }
+            }
+            Mode::Fade(keep) => {
+                if self.glow.len() != graphics.len() {
+                    self.glow = vec![(0, 0.0); graphics.len()];
+                }
+                for (glow, &planes) in self.glow.iter_mut().zip(graphics.iter()) {
+                    *glow = if planes != 0 { (planes, 1.0) } else { (glow.0, glow.1 * keep) };
+                }
+            }
+        }
+    }
+
+    /// The colour of each pixel of the last frame pushed, row by row.
+    pub fn render(&self) -> Vec<Color> {
+        let palette = &self.palette;
+        match self.mode {
+            Mode::Vblank | Mode::Blend(_) => {
+                let len = self.frames.back().map_or(0, Vec::len);
+                (0..len).map(|pixel| {
+                    palette.color(self.frames.iter().fold(0, |planes, frame| planes | frame[pixel]))
+                }).collect()
+            }
+            Mode::Fade(_) => {
+                let background = palette.color(0);
+                self.glow.iter().map(|&(planes, level)| {
+                    let lit = palette.color(planes);
+                    let mut color = background;
+                    for (channel, (&from, &to)) in color.iter_mut().zip(background.iter().zip(lit.iter())) {
+                        *channel = (from as f32 + (to as f32 - from as f32) * level).round() as u8;
+                    }
+                    color
+                }).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Filter, Mode};
+    use palette::Palette;
+    use Chip8;
+
+    #[test]
+    fn parse_modes() {
+        assert_eq!("vblank".parse(), Ok(Mode::Vblank));
+        assert_eq!("blend".parse(), Ok(Mode::Blend(2)));
+        assert_eq!("blend:4".parse(), Ok(Mode::Blend(4)));
+        assert_eq!("fade:0.25".parse(), Ok(Mode::Fade(0.25)));
+        assert!("blend:0".parse::<Mode>().is_err());
+        assert!("fade:1.5".parse::<Mode>().is_err());
+        assert!("smear".parse::<Mode>().is_err());
+    }
+
+    #[test]
+    fn vblank_and_blend() {
+        let palette = Palette::high_contrast();
+        let (black, white) = (palette.color(0), palette.color(1));
+        let mut chip = Chip8::default();
+        let mut vblank = Filter::new(Mode::Vblank, palette);
+        let mut blend = Filter::new(Mode::Blend(2), palette);
+
+        chip.graphics[0] = 1;
+        vblank.push(&chip);
+        blend.push(&chip);
+        chip.graphics[0] = 0;
+        vblank.push(&chip);
+        blend.push(&chip);
+        assert_eq!(vblank.render()[0], black);
+        assert_eq!(blend.render()[0], white);
+
+        blend.push(&chip);
+        assert_eq!(blend.render()[0], black);
+        assert_eq!(blend.render().len(), chip.graphics.len());
+    }
+
+    #[test]
+    fn fade() {
+        let palette = Palette { colors: [[0, 0, 0], [200, 100, 0], [0, 0, 0], [0, 0, 0]] };
+        let mut chip = Chip8::default();
+        let mut filter = Filter::new(Mode::Fade(0.5), palette);
+
+        chip.graphics[1] = 1;
+        filter.push(&chip);
+        assert_eq!(filter.render()[1], [200, 100, 0]);
+        chip.graphics[1] = 0;
+        filter.push(&chip);
+        assert_eq!(filter.render()[1], [100, 50, 0]);
+        filter.push(&chip);
+        assert_eq!(filter.render()[1], [50, 25, 0]);
+        assert_eq!(filter.render()[0], [0, 0, 0]);
+    }
+}
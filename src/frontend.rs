@@ -0,0 +1,447 @@
+//! The pieces a frontend plugs into the emulator, and the loop that runs a
+//! game through them.
+//!
+//! A frontend is a `VideoSink` to draw the display, an `AudioSink` to make
+//! the chip's sound and an `InputSource` to read the keypad.  They needn't
+//! be the same type, so a terminal display can be paired with silence, or a
+//! test can record frames while feeding in scripted keys.  An `Emulator`
+//! drives them a frame at a time, and a `Controller` layers hotkeys such as
+//! save states on top.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use palette::Palette;
+use {Chip8, Chip8Error, CycleOutcome, PATTERN_SIZE};
+
+/// The rate (in Hz) that frames are drawn and the chip's timers count down.
+pub const FRAME_RATE: u32 = 60;
+
+/// This enum says whether the user is trying to quit or has pressed one of
+/// the emulator's hotkeys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// The caller should quit.
+    Quit,
+    /// The caller should continue running.
+    Continue,
+    /// The caller should save the chip's state to the current slot (F5).
+    SaveState,
+    /// The caller should load the chip's state from the current slot (F9).
+    LoadState,
+    /// The caller should switch to the previous save slot (F6).
+    PrevSlot,
+    /// The caller should switch to the next save slot (F7).
+    NextSlot,
+    /// The caller should run the chip backwards by a frame, for as long as
+    /// Backspace is held.
+    Rewind,
+    /// The caller should break into the debugger (F12).
+    Break,
+    /// The display should switch to the next colour theme (F8).
+    NextTheme,
+    /// The caller should save a screenshot of the display (F10).
+    Screenshot,
+    /// The display should start or stop recording (F11).
+    Record,
+}
+
+/// Somewhere to show the chip's display.
+pub trait VideoSink {
+    /// Draws the chip's display.  This is called whenever the display has
+    /// changed, or every frame if `needs_redraw` says so.
+    fn draw_frame(&mut self, chip: &Chip8);
+
+    /// Shows the player a message, such as where a state was saved.  The
+    /// message is dropped by default.
+    fn show_status(&mut self, _status: &str) {}
+
+    /// Switches to the next of the built-in colour themes.  Sinks without
+    /// colour ignore this.
+    fn next_theme(&mut self) {}
+
+    /// Starts or stops recording what's shown.  Sinks that can't record
+    /// ignore this.
+    fn toggle_recording(&mut self) {}
+
+    /// The colours the display is being drawn in, for screenshots.
+    fn palette(&self) -> Palette {
+        Palette::default()
+    }
+
+    /// Whether to draw the next frame even if the display hasn't changed,
+    /// for sinks that show more than the display.
+    fn needs_redraw(&self) -> bool {
+        false
+    }
+
+    /// Why the display can't be shown any more, if it can't.  The emulator
+    /// stops after the frame that fails.  Sinks that can't fail never do.
+    fn failure(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Somewhere to play the chip's sound.
+pub trait AudioSink {
+    /// Starts or stops the sound.  This is called every frame, with whether
+    /// the sound timer is running.
+    fn set_beep(&mut self, enable: bool);
+
+    /// Changes the XO-CHIP audio pattern and the rate (in bits per second)
+    /// it plays at.  `None` means the plain beep.  Sinks that can only beep
+    /// ignore this.
+    fn set_pattern(&mut self, _pattern: Option<[u8; PATTERN_SIZE]>, _rate: f32) {}
+}
+
+/// Sound played in two places at once, such as the speakers and a file.
+impl<A: AudioSink, B: AudioSink> AudioSink for (A, B) {
+    fn set_beep(&mut self, enable: bool) {
+        self.0.set_beep(enable);
+        self.1.set_beep(enable);
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; PATTERN_SIZE]>, rate: f32) {
+        self.0.set_pattern(pattern, rate);
+        self.1.set_pattern(pattern, rate);
+    }
+}
+
+/// Sound that may be switched off, such as when muted.
+impl<A: AudioSink> AudioSink for Option<A> {
+    fn set_beep(&mut self, enable: bool) {
+        if let Some(ref mut sink) = *self {
+            sink.set_beep(enable);
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; PATTERN_SIZE]>, rate: f32) {
+        if let Some(ref mut sink) = *self {
+            sink.set_pattern(pattern, rate);
+        }
+    }
+}
+
+/// Somewhere to read the keypad from.
+pub trait InputSource {
+    /// Marks which keys are held in the given key state array, and returns
+    /// the hotkey pressed since the last check, if any.
+    fn check(&mut self, keys: &mut [u8; 16]) -> Command;
+}
+
+/// Two inputs used together, such as a keyboard and a gamepad.  A key is
+/// held if it's held on either, and the first input's hotkeys win.
+impl<A: InputSource, B: InputSource> InputSource for (A, B) {
+    fn check(&mut self, keys: &mut [u8; 16]) -> Command {
+        let mut other = [0; 16];
+        let first = self.0.check(keys);
+        let second = self.1.check(&mut other);
+        for (key, other) in keys.iter_mut().zip(other.iter()) {
+            *key |= *other;
+        }
+        match first {
+            Command::Continue => second,
+            _ => first,
+        }
+    }
+}
+
+/// What happens around each frame: acting on hotkeys, and running the chip.
+/// The defaults quit on `Command::Quit`, ignore other hotkeys and run the
+/// chip at full speed.
+pub trait Controller {
+    /// Acts on a command from the input, returning false to quit.
+    fn command(&mut self, _chip: &mut Chip8, command: Command) -> bool {
+        command != Command::Quit
+    }
+
+    /// Runs a frame of `ipf` instructions, returning false once the game
+    /// exits.
+    /// # Errors
+    /// Returns the fault if the game crashes.
+    fn run_frame(&mut self, chip: &mut Chip8, ipf: usize) -> Result<bool, Chip8Error> {
+        Ok(chip.run_frame(ipf)? != CycleOutcome::Exit)
+    }
+
+    /// Saves a screenshot of the chip's display in the given colours.
+    /// Screenshots are dropped by default.
+    fn screenshot(&mut self, _chip: &Chip8, _palette: Palette) {}
+
+    /// Takes a message for the player, if there's a new one.
+    fn take_status(&mut self) -> Option<String> {
+        None
+    }
+}
+
+/// The controller with no hotkeys beyond quitting.
+impl Controller for () {}
+
+/// A chip wired up to a frontend.
+///
+/// The parts are public so that a frontend can be taken apart again, for
+/// example to restore the terminal before reporting a fault.
+pub struct Emulator<V, A, I> {
+    pub chip: Chip8,
+    pub video: V,
+    pub audio: A,
+    pub input: I,
+    ipf: usize,
+    next_frame: Instant,
+}
+
+impl<V: VideoSink, A: AudioSink, I: InputSource> Emulator<V, A, I> {
+    /// Constructs an Emulator running `ipf` instructions a frame on the
+    /// given chip.
+    pub fn new(chip: Chip8, ipf: usize, video: V, audio: A, input: I) -> Self {
+        Emulator {
+            chip,
+            video,
+            audio,
+            input,
+            ipf,
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// Runs a single frame without waiting for it: reads the input, runs
+    /// the chip and passes on the display and sound.  Returns false once
+    /// the game exits, the player quits or the display fails.
+    /// # Errors
+    /// Returns the fault if the game crashes.
+    pub fn frame<C: Controller>(&mut self, controller: &mut C) -> Result<bool, Chip8Error> {
+        // Check the input and store it on the chip
+        let command = self.input.check(&mut self.chip.key);
+        match command {
+            Command::NextTheme => {
+                self.video.next_theme();
+                self.chip.draw_flag = true;
+            }
+            Command::Screenshot => controller.screenshot(&self.chip, self.video.palette()),
+            Command::Record => self.video.toggle_recording(),
+            _ => {}
+        }
+        if !controller.command(&mut self.chip, command) {
+            return Ok(false);
+        }
+        if let Some(status) = controller.take_status() {
+            self.video.show_status(&status);
+        }
+
+        // Run a frame on the chip, stopping if the game exits or faults
+        if !controller.run_frame(&mut self.chip, self.ipf)? {
+            return Ok(false);
+        }
+
+        // Render the frame if needed
+        if self.chip.draw_flag || self.video.needs_redraw() {
+            self.chip.draw_flag = false;
+            self.video.draw_frame(&self.chip);
+            if self.video.failure().is_some() {
+                return Ok(false);
+            }
+        }
+
+        // Make sound if needed
+        self.audio.set_pattern(self.chip.audio_pattern(), self.chip.pattern_rate());
+        self.audio.set_beep(self.chip.sound_active());
+        Ok(true)
+    }
+
+    /// Runs frames at `FRAME_RATE` until the game exits or the player quits.
+    /// # Errors
+    /// Returns the fault if the game crashes.
+    pub fn run<C: Controller>(&mut self, controller: &mut C) -> Result<(), Chip8Error> {
+        self.next_frame = Instant::now();
+        while self.frame(controller)? {
+            self.wait();
+        }
+        Ok(())
+    }
+
+    /// Waits until it's time for the next frame, catching up if we've
+    /// fallen behind.
+    fn wait(&mut self) {
+        self.next_frame += Duration::new(0, 1_000_000_000 / FRAME_RATE);
+        let now = Instant::now();
+        if self.next_frame > now {
+            sleep(self.next_frame - now);
+        } else {
+            self.next_frame = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AudioSink, Command, Controller, Emulator, InputSource, VideoSink};
+    use palette::Palette;
+    use {Chip8, Chip8Error};
+
+    /// Keeps a copy of every frame drawn.
+    struct Frames(Vec<Vec<u8>>);
+
+    impl VideoSink for Frames {
+        fn draw_frame(&mut self, chip: &Chip8) {
+            self.0.push(chip.graphics.clone());
+        }
+    }
+
+    /// Keeps whether the sound was on each frame.
+    struct Beeps(Vec<bool>);
+
+    impl AudioSink for Beeps {
+        fn set_beep(&mut self, enable: bool) {
+            self.0.push(enable);
+        }
+    }
+
+    /// Plays back key states and commands, one per frame, quitting after
+    /// the last.
+    struct Script(Vec<([u8; 16], Command)>);
+
+    impl InputSource for Script {
+        fn check(&mut self, keys: &mut [u8; 16]) -> Command {
+            if self.0.is_empty() {
+                return Command::Quit;
+            }
+            let (state, command) = self.0.remove(0);
+            *keys = state;
+            command
+        }
+    }
+
+    fn scripted(game: &[u8], script: Vec<([u8; 16], Command)>) -> Emulator<Frames, Beeps, Script> {
+        let mut chip = Chip8::default();
+        chip.load(game);
+        Emulator::new(chip, 10, Frames(Vec::new()), Beeps(Vec::new()), Script(script))
+    }
+
+    #[test]
+    fn runs_until_quit() {
+        // Wait for a key, draw its digit and beep for that many frames, then
+        // loop
+        let game = [0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0xF0, 0x18, 0x12, 0x08];
+        let mut key = [0; 16];
+        key[3] = 1;
+        let mut script = vec![([0; 16], Command::Continue), (key, Command::Continue)];
+        script.extend(vec![([0; 16], Command::Continue); 3]);
+        let mut emulator = scripted(&game, script);
+
+        // The blank display is drawn once to begin with
+        assert!(emulator.frame(&mut ()).unwrap());
+        assert_eq!(emulator.video.0.len(), 1);
+        assert_eq!(emulator.audio.0, vec![false]);
+
+        assert!(emulator.frame(&mut ()).unwrap());
+        assert_eq!(emulator.chip.registers()[0], 3);
+        assert_eq!(emulator.video.0.len(), 2);
+        assert_eq!(emulator.video.0[1][2], 1);
+        assert_eq!(emulator.audio.0, vec![false, true]);
+
+        for _ in 0..3 {
+            assert!(emulator.frame(&mut ()).unwrap());
+        }
+        assert_eq!(emulator.audio.0, vec![false, true, true, true, false]);
+
+        assert!(!emulator.frame(&mut ()).unwrap());
+    }
+
+    #[test]
+    fn stops_on_exit_and_fault() {
+        let mut emulator = scripted(&[0x00, 0xFD], vec![([0; 16], Command::Continue)]);
+        emulator.run(&mut ()).unwrap();
+        assert_eq!(emulator.input.0.len(), 0);
+
+        let mut emulator = scripted(&[0x00, 0xEE], vec![([0; 16], Command::Continue)]);
+        assert_eq!(emulator.run(&mut ()), Err(Chip8Error::StackUnderflow));
+    }
+
+    /// Fails on the given frame.
+    struct Fails(usize);
+
+    impl VideoSink for Fails {
+        fn draw_frame(&mut self, _chip: &Chip8) {
+            self.0 -= 1;
+        }
+
+        fn needs_redraw(&self) -> bool {
+            true
+        }
+
+        fn failure(&self) -> Option<String> {
+            if self.0 == 0 { Some("Broken".to_string()) } else { None }
+        }
+    }
+
+    #[test]
+    fn stops_on_failed_display() {
+        let mut chip = Chip8::default();
+        chip.load(&[0x12, 0x00]);
+        let script = vec![([0; 16], Command::Continue); 5];
+        let mut emulator = Emulator::new(chip, 10, Fails(3), Beeps(Vec::new()), Script(script));
+        emulator.run(&mut ()).unwrap();
+        assert_eq!(emulator.input.0.len(), 2);
+        assert_eq!(emulator.video.failure(), Some("Broken".to_string()));
+    }
+
+    #[test]
+    fn inputs_combine() {
+        let mut a = [0; 16];
+        a[1] = 1;
+        let mut b = [0; 16];
+        b[2] = 1;
+        let mut both = (Script(vec![(a, Command::Continue), (a, Command::SaveState)]),
+                        Script(vec![(b, Command::LoadState), (b, Command::LoadState)]));
+        let mut keys = [0; 16];
+        assert_eq!(both.check(&mut keys), Command::LoadState);
+        assert_eq!(&keys[..3], &[0, 1, 1]);
+        assert_eq!(both.check(&mut keys), Command::SaveState);
+    }
+
+    /// Counts hotkeys and reports each one.
+    struct Hotkeys(usize, Option<String>);
+
+    impl Controller for Hotkeys {
+        fn command(&mut self, _chip: &mut Chip8, command: Command) -> bool {
+            if command == Command::SaveState {
+                self.0 += 1;
+                self.1 = Some(format!("Saved {}", self.0));
+            }
+            command != Command::Quit
+        }
+
+        fn screenshot(&mut self, _chip: &Chip8, palette: Palette) {
+            self.1 = Some(format!("Screenshot in {:?}", palette.color(1)));
+        }
+
+        fn take_status(&mut self) -> Option<String> {
+            self.1.take()
+        }
+    }
+
+    /// Keeps the statuses shown.
+    struct Statuses(Vec<String>);
+
+    impl VideoSink for Statuses {
+        fn draw_frame(&mut self, _chip: &Chip8) {}
+
+        fn show_status(&mut self, status: &str) {
+            self.0.push(status.to_string());
+        }
+    }
+
+    #[test]
+    fn controller_handles_commands() {
+        let mut chip = Chip8::default();
+        chip.load(&[0x12, 0x00]);
+        let script = vec![([0; 16], Command::SaveState),
+                          ([0; 16], Command::Continue),
+                          ([0; 16], Command::SaveState),
+                          ([0; 16], Command::Screenshot)];
+        let mut emulator = Emulator::new(chip, 10, Statuses(Vec::new()), Beeps(Vec::new()),
+                                         Script(script));
+        let mut hotkeys = Hotkeys(0, None);
+        while emulator.frame(&mut hotkeys).unwrap() {}
+        assert_eq!(emulator.video.0, vec!["Saved 1", "Saved 2", "Screenshot in [255, 255, 0]"]);
+    }
+}
@@ -0,0 +1,150 @@
+extern crate sdl2;
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::{GameControllerSubsystem, Sdl};
+
+use chip8::frontend::{Command, InputSource};
+use chip8::keymap::Keymap;
+
+/// How far a stick or trigger has to move before it counts as pressed, out
+/// of 32767.  This keeps worn sticks that don't quite centre from holding
+/// keys down.
+const DEADZONE : i32 = 8000;
+
+/// The prefix that marks a keymap name as a game controller binding.
+const PAD_PREFIX : &str = "Pad ";
+
+/// Splits the direction off an axis binding like `leftx-`, returning the
+/// axis and whether it's pushed the positive way.
+fn split_sign(name: &str) -> Option<(&str, bool)> {
+    let (axis, sign) = name.split_at(name.len().saturating_sub(1));
+    match sign {
+        "+" => Some((axis, true)),
+        "-" => Some((axis, false)),
+        _ => None,
+    }
+}
+
+/// An axis bound to a hex key, and which way it has to be pushed.
+struct AxisBinding {
+    axis: Axis,
+    positive: bool,
+    key: usize,
+}
+
+/// Reads the hex keys from every connected SDL game controller, opening
+/// controllers as they're plugged in and dropping them when they're
+/// unplugged.
+pub struct Gamepad {
+    subsystem: Option<GameControllerSubsystem>,
+    controllers: Vec<GameController>,
+    joysticks: u32,
+    buttons: Vec<(Button, usize)>,
+    axes: Vec<AxisBinding>,
+}
+
+impl Gamepad {
+    /// Constructs a new Gamepad from the given SDL context, pressing hex
+    /// keys as the keymap's `Pad` bindings say.  Without a game controller
+    /// subsystem no keys are ever pressed.
+    pub fn new(context: &Sdl, keymap: &Keymap) -> Self {
+        let mut buttons = Vec::new();
+        let mut axes = Vec::new();
+        for key in 0..16 {
+            for name in keymap.names(key) {
+                let name = match name.get(..PAD_PREFIX.len()) {
+                    Some(prefix) if prefix.eq_ignore_ascii_case(PAD_PREFIX) => {
+                        name[PAD_PREFIX.len()..].to_lowercase()
+                    }
+                    _ => continue,
+                };
+                if let Some(button) = Button::from_string(&name) {
+                    buttons.push((button, key));
+                    continue;
+                }
+                let (axis, positive) = match split_sign(&name) {
+                    Some((axis, positive)) => (Axis::from_string(axis), positive),
+                    None => (Axis::from_string(&name), true),
+                };
+                match axis {
+                    Some(axis) => axes.push(AxisBinding { axis, positive, key }),
+                    None => warn!("Unknown game controller binding {} in the keymap", name),
+                }
+            }
+        }
+
+        let subsystem = context.game_controller().map_err(|e| {
+            warn!("Game controllers are unavailable: {}", e);
+        }).ok();
+        Gamepad {
+            subsystem,
+            controllers: Vec::new(),
+            joysticks: 0,
+            buttons,
+            axes,
+        }
+    }
+
+    /// Opens any controllers that have been plugged in since the last check,
+    /// and drops any that have been unplugged.
+    fn connect(&mut self) {
+        let subsystem = match self.subsystem {
+            Some(ref subsystem) => subsystem,
+            None => return,
+        };
+        let joysticks = subsystem.num_joysticks().unwrap_or(0);
+        let unplugged = self.controllers.iter().any(|controller| !controller.attached());
+        if joysticks == self.joysticks && !unplugged {
+            return;
+        }
+        self.joysticks = joysticks;
+
+        self.controllers.retain(|controller| {
+            if !controller.attached() {
+                info!("Disconnected {}", controller.name());
+            }
+            controller.attached()
+        });
+        for index in (0..joysticks).filter(|&index| subsystem.is_game_controller(index)) {
+            // Opening a controller that's already open hands back the same
+            // one, which is dropped again here
+            match subsystem.open(index) {
+                Ok(controller) => {
+                    let id = controller.instance_id();
+                    if self.controllers.iter().all(|open| open.instance_id() != id) {
+                        info!("Connected {}", controller.name());
+                        self.controllers.push(controller);
+                    }
+                }
+                Err(e) => warn!("Couldn't open game controller {}: {:?}", index, e),
+            }
+        }
+    }
+}
+
+impl InputSource for Gamepad {
+    /// Marks the keys held on any controller.  Controllers have no hotkeys.
+    fn check(&mut self, keys: &mut [u8; 16]) -> Command {
+        self.connect();
+        if let Some(ref subsystem) = self.subsystem {
+            subsystem.update();
+        }
+
+        *keys = [0; 16];
+        for controller in &self.controllers {
+            for &(button, key) in &self.buttons {
+                if controller.button(button) {
+                    keys[key] = 1;
+                }
+            }
+            for binding in &self.axes {
+                let value = controller.axis(binding.axis) as i32;
+                let pushed = if binding.positive { value } else { -value };
+                if pushed > DEADZONE {
+                    keys[binding.key] = 1;
+                }
+            }
+        }
+        Command::Continue
+    }
+}
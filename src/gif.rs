@@ -0,0 +1,372 @@
+//! Recordings of the display as animated GIFs.
+//!
+//! Frames are given at the chip's 60 Hz.  A frame that's the same as the
+//! one before only makes that one last longer, so a game that sits still
+//! costs nothing.  GIF delays are in hundredths of a second, and viewers
+//! slow down anything shorter than two, so a frame that would be over
+//! sooner than that is dropped and the next one shown in its place.  Each
+//! frame has its own colour table, since anti-flicker blending can use more
+//! colours than the palette has.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use frontend::FRAME_RATE;
+use palette::Color;
+use png::BitWriter;
+
+/// The most colours a GIF frame can have.
+const MAX_COLORS: usize = 256;
+
+/// The widest LZW codes can get.
+const MAX_CODE_BITS: u32 = 12;
+
+/// The shortest delay viewers show as it is, in hundredths of a second:
+/// most of them take anything shorter to mean a tenth of a second.
+const MIN_DELAY: u64 = 2;
+
+/// Compresses colour indexes with GIF's flavour of LZW.
+fn lzw(indexes: &[u8], min_bits: u32) -> Vec<u8> {
+    let clear = 1 << min_bits;
+    let end = clear + 1;
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut bits = BitWriter::default();
+    let mut size = min_bits + 1;
+    let mut next = end + 1;
+    bits.write(clear as u32, size);
+
+    let mut prefix = None;
+    for &index in indexes {
+        let current = match prefix {
+            None => {
+                prefix = Some(index as u16);
+                continue;
+            }
+            Some(current) => current,
+        };
+        if let Some(&code) = codes.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+        bits.write(current as u32, size);
+        if next < 1 << MAX_CODE_BITS {
+            codes.insert((current, index), next);
+            next += 1;
+            // The decoder adds each code a step later, so it widens its
+            // codes once the one after the widest so far is added
+            if next > 1 << size && size < MAX_CODE_BITS {
+                size += 1;
+            }
+        } else {
+            bits.write(clear as u32, size);
+            codes.clear();
+            size = min_bits + 1;
+            next = end + 1;
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(current) = prefix {
+        bits.write(current as u32, size);
+    }
+    bits.write(end as u32, size);
+    bits.finish()
+}
+
+/// Builds a colour table for the pixels and looks each one up in it.  If
+/// there are too many colours, they're made coarser until they fit.
+fn index_colors(pixels: &[Color]) -> (Vec<Color>, Vec<u8>) {
+    for shift in 0..8 {
+        let coarse = |color: &Color| {
+            let mut coarse = *color;
+            for channel in coarse.iter_mut() {
+                *channel = *channel >> shift << shift;
+            }
+            coarse
+        };
+        let mut table = Vec::new();
+        let mut lookup = HashMap::new();
+        let mut indexes = Vec::with_capacity(pixels.len());
+        for color in pixels.iter().map(coarse) {
+            let index = *lookup.entry(color).or_insert_with(|| {
+                table.push(color);
+                table.len() - 1
+            });
+            if index >= MAX_COLORS {
+                break;
+            }
+            indexes.push(index as u8);
+        }
+        if indexes.len() == pixels.len() {
+            return (table, indexes);
+        }
+    }
+    unreachable!("Colours with one bit a channel always fit")
+}
+
+/// Writes an animated GIF a frame at a time.
+pub struct Encoder<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    /// The last frame given, and how many frames it has lasted so far
+    pending: Option<(Vec<Color>, u64)>,
+    /// How many frames have been written out
+    written: u64,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Starts a GIF of the given size, which loops forever.
+    pub fn new(mut out: W, width: usize, height: usize) -> io::Result<Self> {
+        out.write_all(b"GIF89a")?;
+        out.write_all(&[width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8])?;
+        // No global colour table, background colour 0, square pixels
+        out.write_all(&[0, 0, 0])?;
+        // The Netscape extension, to loop forever
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(Encoder { out, width, height, pending: None, written: 0 })
+    }
+
+    /// Adds a 60 Hz frame, with the colour of each pixel given row by row.
+    pub fn frame(&mut self, pixels: Vec<Color>) -> io::Result<()> {
+        assert_eq!(pixels.len(), self.width * self.height,
+                   "The frame should be {}x{}", self.width, self.height);
+        if let Some((ref last, ref mut frames)) = self.pending {
+            if *last == pixels {
+                *frames += 1;
+                return Ok(());
+            }
+        }
+        // A frame too short to show gives its time to this one
+        let short = self.pending.as_ref()
+            .map(|&(_, frames)| frames)
+            .filter(|&frames| self.delay(frames) < MIN_DELAY);
+        let frames = match short {
+            Some(frames) => frames + 1,
+            None => {
+                self.flush()?;
+                1
+            }
+        };
+        self.pending = Some((pixels, frames));
+        Ok(())
+    }
+
+    /// How long the next frame to be written out lasts if it lasts the
+    /// given number of frames, in hundredths of a second.  Delays are
+    /// whole hundredths, so this works out when the frame ends rather than
+    /// how long it lasts to keep the total right.
+    fn delay(&self, frames: u64) -> u64 {
+        let time = |frames: u64| frames * 100 / FRAME_RATE as u64;
+        time(self.written + frames) - time(self.written)
+    }
+
+    /// Writes out the pending frame, if there is one.
+    fn flush(&mut self) -> io::Result<()> {
+        let (pixels, frames) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        // Only the last frame can be too short by now
+        let delay = self.delay(frames).max(MIN_DELAY);
+        self.written += frames;
+
+        let (mut table, indexes) = index_colors(&pixels);
+        let bits = (1..8).find(|&bits| table.len() <= 1 << bits).unwrap_or(8);
+        table.resize(1 << bits, [0; 3]);
+
+        // Graphic control: leave the frame in place, with the delay
+        let delay = delay.min(0xFFFF) as u16;
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x04, delay as u8, (delay >> 8) as u8, 0, 0])?;
+        // The image fills the screen and has a local colour table
+        let (width, height) = (self.width, self.height);
+        self.out.write_all(&[0x2C, 0, 0, 0, 0, width as u8, (width >> 8) as u8,
+                             height as u8, (height >> 8) as u8, 0x80 | (bits - 1) as u8])?;
+        self.out.write_all(&table.concat())?;
+
+        let min_bits = bits.max(2);
+        self.out.write_all(&[min_bits as u8])?;
+        for block in lzw(&indexes, min_bits).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+
+    /// Writes out the last frame and ends the GIF, handing back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lzw, Encoder};
+    use palette::Color;
+
+    /// Decompresses GIF LZW data, as a decoder would.
+    fn unlzw(data: &[u8], min_bits: u32) -> Vec<u8> {
+        let clear = 1 << min_bits;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = min_bits + 1;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        let (mut buffer, mut count, mut bytes) = (0u32, 0, data.iter());
+        loop {
+            while count < size {
+                buffer |= (*bytes.next().expect("no end code") as u32) << count;
+                count += 8;
+            }
+            let code = (buffer & ((1 << size) - 1)) as usize;
+            buffer >>= size;
+            count -= size;
+            if code == clear {
+                table = (0..clear).map(|index| vec![index as u8]).collect();
+                table.push(Vec::new());
+                table.push(Vec::new());
+                size = min_bits + 1;
+                prev = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match prev {
+                Some(ref prev) if code == table.len() => {
+                    let mut entry = prev.clone();
+                    entry.push(prev[0]);
+                    entry
+                }
+                _ => table[code].clone(),
+            };
+            if let Some(mut prev) = prev.take() {
+                if table.len() < 4096 {
+                    prev.push(entry[0]);
+                    table.push(prev);
+                    if table.len() == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                }
+            }
+            out.extend_from_slice(&entry);
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        let flat = vec![1; 10000];
+        assert_eq!(unlzw(&lzw(&flat, 2), 2), flat);
+        assert!(lzw(&flat, 2).len() < 200);
+
+        // Enough variety to fill the table and start it again
+        let mut state = 1u32;
+        let noisy: Vec<u8> = (0..50000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        assert_eq!(unlzw(&lzw(&noisy, 8), 8), noisy);
+    }
+
+    /// Decodes a GIF the encoder wrote into each frame's delay and pixels.
+    fn decode(gif: &[u8]) -> Vec<(u16, Vec<Color>)> {
+        assert_eq!(&gif[..6], b"GIF89a");
+        let size = &gif[6..10];
+        // Skip the rest of the screen descriptor and the loop extension
+        let mut at = 13 + 19;
+        let mut frames = Vec::new();
+        let mut delay = 0;
+        loop {
+            match gif[at] {
+                0x21 => {
+                    assert_eq!(&gif[at + 1..at + 3], &[0xF9, 0x04]);
+                    delay = gif[at + 4] as u16 | (gif[at + 5] as u16) << 8;
+                    at += 8;
+                }
+                0x2C => {
+                    assert_eq!(&gif[at + 5..at + 9], size);
+                    let table_size = 3 << ((gif[at + 9] & 0x07) + 1);
+                    let table: Vec<Color> = gif[at + 10..at + 10 + table_size].chunks(3)
+                        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                        .collect();
+                    at += 10 + table_size;
+                    let min_bits = gif[at] as u32;
+                    at += 1;
+                    let mut data = Vec::new();
+                    while gif[at] != 0 {
+                        let len = gif[at] as usize;
+                        data.extend_from_slice(&gif[at + 1..at + 1 + len]);
+                        at += 1 + len;
+                    }
+                    at += 1;
+                    let pixels = unlzw(&data, min_bits).iter().map(|&index| table[index as usize]).collect();
+                    frames.push((delay, pixels));
+                }
+                0x3B => return frames,
+                block => panic!("Unexpected block {:#X}", block),
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_whole_frames() {
+        let (width, height) = (128, 64);
+        let frame = |shift: usize| -> Vec<Color> {
+            (0..width * height).map(|i| {
+                let value = ((i % width) * 7 + (i / width) * 3 + shift) % 200;
+                [value as u8, 255 - value as u8, (value / 2) as u8]
+            }).collect()
+        };
+        let mut encoder = Encoder::new(Vec::new(), width, height).unwrap();
+        for shift in 0..3 {
+            for _ in 0..6 {
+                encoder.frame(frame(shift)).unwrap();
+            }
+        }
+        let gif = encoder.finish().unwrap();
+        assert_eq!(&gif[6..10], &[128, 0, 64, 0]);
+        assert_eq!(gif.last(), Some(&0x3B));
+        assert_eq!(decode(&gif), vec![(10, frame(0)), (10, frame(1)), (10, frame(2))]);
+    }
+
+    #[test]
+    fn deduplicates_frames() {
+        let black = vec![[0, 0, 0]; 6];
+        let mut white = black.clone();
+        white[4] = [255, 255, 255];
+        let mut encoder = Encoder::new(Vec::new(), 3, 2).unwrap();
+        for frame in &[&black, &black, &black, &white, &white, &black] {
+            encoder.frame(frame.to_vec()).unwrap();
+        }
+        let gif = encoder.finish().unwrap();
+        // Three frames, lasting 3, 2 and 1 frames of 1/60th of a second,
+        // with the last one stretched to the shortest delay
+        assert_eq!(decode(&gif), vec![(5, black.clone()), (3, white), (2, black)]);
+    }
+
+    #[test]
+    fn drops_short_frames() {
+        let black = vec![[0, 0, 0]; 6];
+        let mut white = black.clone();
+        white[4] = [255, 255, 255];
+        let mut encoder = Encoder::new(Vec::new(), 3, 2).unwrap();
+        for frame in &[&black, &black, &black, &white, &black] {
+            encoder.frame(frame.to_vec()).unwrap();
+        }
+        // The white frame would last a hundredth of a second, so it's
+        // dropped and the black one after it starts then instead
+        let gif = encoder.finish().unwrap();
+        assert_eq!(decode(&gif), vec![(5, black.clone()), (3, black.clone())]);
+
+        // Flickering every frame still takes as long as it should
+        let mut encoder = Encoder::new(Vec::new(), 3, 2).unwrap();
+        for frame in 0..60 {
+            encoder.frame(if frame % 2 == 0 { black.clone() } else { white.clone() }).unwrap();
+        }
+        let delays: Vec<u16> = decode(&encoder.finish().unwrap()).iter().map(|frame| frame.0).collect();
+        assert!(delays.iter().all(|&delay| delay >= 2), "{:?}", delays);
+        assert_eq!(delays.iter().sum::<u16>(), 100);
+    }
+}
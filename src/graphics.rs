@@ -1,11 +1,13 @@
 extern crate chip8;
 extern crate sdl2;
 
+use chip8::Chip8;
+use chip8::flicker::Filter;
+use chip8::frontend::VideoSink;
+use chip8::palette::Palette;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
 use sdl2::Sdl;
-use std::time::{Duration, Instant};
-use std::thread::sleep;
 
 /// Represents a display.  In this case, it represents an SDL window.
 pub struct Display<'a> {
@@ -13,23 +15,18 @@ pub struct Display<'a> {
     height: u32,
     renderer: sdl2::render::Renderer<'a>,
     texture: sdl2::render::Texture,
-    frame_duration: Duration,
-    frame_last: Instant,
+    filter: Filter,
 }
 
 impl<'a> Display<'a> {
     /// Constructs a new SDL window with the given SDL context,
-    /// the given title, the given width (in pixels, the given height
-    /// (in pixels), and the given frame duration.
-    ///
-    /// The frame duration is used to set how long each frame should last.
-    /// Higher values lead to slower emulator performance but also help
-    /// smooth the framerate.
+    /// the given title, the given width (in pixels), the given height
+    /// (in pixels) and the given filter, which picks the colours.
     pub fn new(context: &Sdl,
                title: &str,
                width: u32,
                height: u32,
-               duration: Duration) -> Self {
+               filter: Filter) -> Self {
         let video_subsystem = context.video().unwrap();
         let window = video_subsystem.window(title, width, height)
             .position_centered()
@@ -38,40 +35,40 @@ impl<'a> Display<'a> {
             .unwrap();
         let renderer = window.renderer().build().unwrap();
         let texture = renderer.create_texture_streaming(
-            PixelFormatEnum::RGB24, 64, 32).unwrap();
+            PixelFormatEnum::RGB24, chip8::WIDTH, chip8::HEIGHT).unwrap();
 
         Display {
             width: width,
             height: height,
             renderer: renderer,
             texture: texture,
-            frame_duration: duration,
-            frame_last: Instant::now(),
+            filter,
         }
     }
 
-    /// The window draws the give bitmap image on the Display's frame duration.
-    ///
-    /// If there is time left over, that time is spent sleeping.
-    pub fn draw_frame(&mut self, bitmap: &[u8; chip8::NPIXELS]) {
-        // Keep timing okay
-        let prev_duration = Instant::now().duration_since(self.frame_last);
-        debug!("Last frame duration: {:?}", prev_duration);
-        if prev_duration < self.frame_duration {
-            let diff = self.frame_duration - prev_duration;
-            debug!("Sleeping for: {:?}", diff);
-            sleep(diff);
+}
+
+impl<'a> VideoSink for Display<'a> {
+    /// The window draws the chip's display, stretched to fill the window
+    /// whatever its resolution, in the colours the filter renders it in.
+    fn draw_frame(&mut self, chip: &Chip8) {
+        debug!("{:?}", chip);
+        let (width, height) = (chip.width(), chip.height());
+        self.filter.push(chip);
+        let colors = self.filter.render();
+
+        // Match the texture to the chip's current resolution
+        let query = self.texture.query();
+        if query.width as usize != width || query.height as usize != height {
+            self.texture = self.renderer.create_texture_streaming(
+                PixelFormatEnum::RGB24, width as u32, height as u32).unwrap();
         }
-        self.frame_last = Instant::now();
 
         self.texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-            for y in 0..32 {
-                for x in 0..64 {
+            for y in 0..height {
+                for x in 0..width {
                     let offset = y*pitch + x*3;
-                    let value = if 0 != bitmap[y * 64 + x] { 255 } else { 0 };
-                    buffer[offset + 0] = value as u8;
-                    buffer[offset + 1] = value as u8;
-                    buffer[offset + 2] = 0;
+                    buffer[offset..offset + 3].copy_from_slice(&colors[y * width + x]);
                 }
             }
 
@@ -82,5 +79,25 @@ impl<'a> Display<'a> {
                            Some(Rect::new(0, 0, self.width, self.height))).unwrap();
         self.renderer.present();
     }
+
+    fn next_theme(&mut self) {
+        let (name, palette) = self.filter.palette.next_theme();
+        self.filter.palette = palette;
+        self.show_status(&format!("Theme {}", name));
+    }
+
+    /// Blended or fading frames change even when the chip's display doesn't.
+    fn needs_redraw(&self) -> bool {
+        self.filter.is_active()
+    }
+
+    fn palette(&self) -> Palette {
+        self.filter.palette
+    }
+
+    /// Messages go to the console, since the window has no room for them.
+    fn show_status(&mut self, status: &str) {
+        println!("{}", status);
+    }
 }
 
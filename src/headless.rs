@@ -0,0 +1,256 @@
+use chip8::{Chip8, Chip8Error, CycleOutcome};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// How long a scripted key is held if the script doesn't say (in frames).
+const DEFAULT_HOLD : u64 = 5;
+
+/// When a headless run should stop before running out of frames.
+#[derive(Debug, PartialEq)]
+pub enum Condition {
+    /// The game exits with 0x00FD
+    Exit,
+    /// The game waits for a key press with 0xFX0A
+    WaitingForKey,
+    /// The program counter reaches an address
+    Pc(u16),
+    /// A register holds a value
+    Register(usize, u8),
+}
+
+/// Parses a number in hex (with 0x) or decimal.
+fn parse_number(text: &str) -> Result<usize, String> {
+    let result = if text.starts_with("0x") || text.starts_with("0X") {
+        usize::from_str_radix(&text[2..], 16)
+    } else {
+        text.parse()
+    };
+    result.map_err(|_| format!("Bad number: {}", text))
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    /// Parses `exit`, `key`, `pc=ADDRESS` or `vX=VALUE`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let lower = text.to_lowercase();
+        match lower.as_str() {
+            "exit" => return Ok(Condition::Exit),
+            "key" => return Ok(Condition::WaitingForKey),
+            _ => {}
+        }
+        let mut parts = lower.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name, parse_number(value)?),
+            _ => return Err(format!("Unknown condition: {}", text)),
+        };
+        if name == "pc" && value <= 0xFFFF {
+            return Ok(Condition::Pc(value as u16));
+        }
+        if name.len() == 2 && name.starts_with('v') && value <= 0xFF {
+            if let Ok(x) = usize::from_str_radix(&name[1..], 16) {
+                return Ok(Condition::Register(x, value as u8));
+            }
+        }
+        Err(format!("Unknown condition: {}", text))
+    }
+}
+
+impl Condition {
+    fn is_met(&self, chip: &Chip8, outcome: CycleOutcome) -> bool {
+        match *self {
+            Condition::Exit => outcome == CycleOutcome::Exit,
+            Condition::WaitingForKey => outcome == CycleOutcome::WaitingForKey,
+            Condition::Pc(address) => chip.pc() == address,
+            Condition::Register(x, value) => chip.registers()[x] == value,
+        }
+    }
+}
+
+/// A key held down by the input script.
+pub struct KeyPress {
+    key: usize,
+    start: u64,
+    frames: u64,
+}
+
+/// Parses an input script: a comma separated list of `KEY@FRAME`, which
+/// holds down the hex key from that frame for a few frames, or
+/// `KEY@FRAME+FRAMES` to say for how many.
+pub fn parse_script(text: &str) -> Result<Vec<KeyPress>, String> {
+    let mut presses = Vec::new();
+    for press in text.split(',').map(str::trim).filter(|press| !press.is_empty()) {
+        let bad = || format!("Bad key press {}, expected KEY@FRAME or KEY@FRAME+FRAMES", press);
+        let mut parts = press.splitn(2, '@');
+        let key = parts.next()
+            .and_then(|key| usize::from_str_radix(key, 16).ok())
+            .filter(|&key| key < 16)
+            .ok_or_else(bad)?;
+        let mut timing = parts.next().ok_or_else(bad)?.splitn(2, '+');
+        let start = timing.next().and_then(|start| start.parse().ok()).ok_or_else(bad)?;
+        let frames = match timing.next() {
+            Some(frames) => frames.parse().map_err(|_| bad())?,
+            None => DEFAULT_HOLD,
+        };
+        presses.push(KeyPress { key, start, frames });
+    }
+    Ok(presses)
+}
+
+/// How a headless run ended.
+pub struct Ending {
+    /// The number of frames that ran
+    pub frames: u64,
+    /// Whether the stop condition was met
+    pub met: bool,
+}
+
+/// Runs the chip for up to `frames` frames of `ipf` instructions, pressing
+/// keys as the script says, until the condition is met or the game exits.
+/// `after_frame` is given the chip and the number of frames run so far at
+/// the end of each frame, including the one the run stops partway through.
+pub fn run(chip: &mut Chip8, ipf: usize, frames: u64, until: Option<&Condition>,
+           script: &[KeyPress], after_frame: &mut dyn FnMut(&Chip8, u64))
+           -> Result<Ending, Chip8Error> {
+    for frame in 0..frames {
+        for (key, state) in chip.key.iter_mut().enumerate() {
+            *state = script.iter()
+                .any(|press| press.key == key && press.start <= frame &&
+                             frame < press.start.saturating_add(press.frames)) as u8;
+        }
+
+        // Step by hand rather than with run_frame, so that the condition
+        // is checked after every instruction
+        for _ in 0..ipf {
+            let outcome = chip.step_instruction()?;
+            if until.is_some_and(|condition| condition.is_met(chip, outcome)) {
+                after_frame(chip, frame + 1);
+                return Ok(Ending { frames: frame + 1, met: true });
+            }
+            match outcome {
+                CycleOutcome::Continue => {}
+                CycleOutcome::WaitingForKey => break,
+                CycleOutcome::Exit => {
+                    after_frame(chip, frame + 1);
+                    return Ok(Ending { frames: frame + 1, met: false });
+                }
+            }
+        }
+        chip.tick_timers();
+        after_frame(chip, frame + 1);
+    }
+    Ok(Ending { frames, met: false })
+}
+
+/// Writes the registers and the display as text, one character per pixel
+/// giving its planes (`.` when it's off).
+pub fn dump(chip: &Chip8, frames: u64, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "frames: {}", frames)?;
+    writeln!(out, "pc: {:#06X}", chip.pc())?;
+    writeln!(out, "i: {:#06X}", chip.index())?;
+    let registers: Vec<String> = chip.registers().iter().map(|v| format!("{:02X}", v)).collect();
+    writeln!(out, "v: {}", registers.join(" "))?;
+    writeln!(out, "dt: {}", chip.delay_timer())?;
+    writeln!(out, "st: {}", chip.sound_timer())?;
+    let stack: Vec<String> = chip.stack().iter().map(|a| format!("{:#06X}", a)).collect();
+    writeln!(out, "stack: {}", stack.join(" "))?;
+    writeln!(out, "screen: {}x{}", chip.width(), chip.height())?;
+    for row in chip.graphics.chunks(chip.width()) {
+        let line: String = row.iter()
+            .map(|&pixel| if pixel == 0 { '.' } else { (b'0' + pixel) as char })
+            .collect();
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_script, run, Condition};
+    use chip8::{Chip8, Chip8Error};
+
+    /// Runs a game headless, returning how many frames ran and whether the
+    /// condition was met.
+    fn run_game(game: &[u8], frames: u64, until: Option<&str>) -> Result<(u64, bool), Chip8Error> {
+        let mut chip = Chip8::default();
+        chip.load(game);
+        let until = until.map(|until| until.parse::<Condition>().unwrap());
+        run(&mut chip, 10, frames, until.as_ref(), &[], &mut |_, _| {})
+            .map(|ending| (ending.frames, ending.met))
+    }
+
+    #[test]
+    fn parse_conditions() {
+        assert_eq!("exit".parse(), Ok(Condition::Exit));
+        assert_eq!("KEY".parse(), Ok(Condition::WaitingForKey));
+        assert_eq!("pc=0x2A0".parse(), Ok(Condition::Pc(0x2A0)));
+        assert_eq!("vA=255".parse(), Ok(Condition::Register(0xA, 255)));
+        assert_eq!("vf=0x0".parse(), Ok(Condition::Register(0xF, 0)));
+        for bad in &["", "pc", "pc=", "pc=0x10000", "v0=256", "vG=1", "v10=1", "i=3", "x=y"] {
+            assert!(bad.parse::<Condition>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_scripts() {
+        let script = parse_script("1@5, f@10+2,,").unwrap();
+        assert_eq!(script.len(), 2);
+        assert_eq!((script[0].key, script[0].start, script[0].frames), (1, 5, 5));
+        assert_eq!((script[1].key, script[1].start, script[1].frames), (0xF, 10, 2));
+        assert!(parse_script("").unwrap().is_empty());
+        for bad in &["1", "10@5", "g@1", "1@", "1@x", "1@5+", "1@5+-1", "@5"] {
+            assert!(parse_script(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn script_presses_keys() {
+        // Count up in v2 while the key in v1 (key 0) is held
+        let game = [0xE1, 0x9E, 0x12, 0x06, 0x72, 0x01, 0x12, 0x00];
+        let mut chip = Chip8::default();
+        chip.load(&game);
+        let script = parse_script("0@2+18446744073709551615").unwrap();
+        let mut held = Vec::new();
+        let until = Condition::Register(2, 5);
+        let ending = run(&mut chip, 1, 100, Some(&until), &script,
+                         &mut |chip, _| held.push(chip.key[0])).unwrap();
+        assert!(ending.met);
+        assert_eq!(&held[..4], &[0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn stops_when_met() {
+        // Count up in v0 forever
+        let counter = [0x70, 0x01, 0x12, 0x00];
+        assert_eq!(run_game(&counter, 100, Some("v0=25")), Ok((5, true)));
+        assert_eq!(run_game(&counter, 100, Some("pc=0x202")), Ok((1, true)));
+        assert_eq!(run_game(&counter, 100, Some("exit")), Ok((100, false)));
+        assert_eq!(run_game(&counter, 3, None), Ok((3, false)));
+
+        // The frame the run stops in is still handed on
+        let mut chip = Chip8::default();
+        chip.load(&counter);
+        let until = Condition::Register(0, 25);
+        let mut seen = Vec::new();
+        run(&mut chip, 10, 100, Some(&until), &[], &mut |chip, frame| {
+            seen.push((frame, chip.registers()[0]));
+        }).unwrap();
+        assert_eq!(seen.last(), Some(&(5, 25)));
+        assert_eq!(seen.len(), 5);
+
+        let exits = [0x00, 0xFD];
+        assert_eq!(run_game(&exits, 100, Some("exit")), Ok((1, true)));
+        let mut chip = Chip8::default();
+        chip.load(&exits);
+        let mut seen = Vec::new();
+        run(&mut chip, 10, 100, None, &[], &mut |_, frame| seen.push(frame)).unwrap();
+        assert_eq!(seen, [1]);
+        assert_eq!(run_game(&exits, 100, Some("pc=0x300")), Ok((1, false)));
+
+        let waits = [0xF0, 0x0A];
+        assert_eq!(run_game(&waits, 100, Some("key")), Ok((1, true)));
+
+        let faults = [0x00, 0xEE];
+        assert_eq!(run_game(&faults, 100, None), Err(Chip8Error::StackUnderflow));
+    }
+}
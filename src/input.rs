@@ -5,60 +5,79 @@ use sdl2::EventPump;
 use sdl2::Sdl;
 use sdl2::keyboard::{Keycode, KeyboardState, Scancode};
 
+use chip8::frontend::{Command, InputSource};
+use chip8::keymap::Keymap;
+
 /// This struct keeps track of the SDL EventPump, which is used for scanning
 /// the keyboard for key presses.
 pub struct Keyboard {
     event_pump: EventPump,
-}
-
-/// This enum says whether or not the user is trying to quit.
-pub enum Command {
-    /// The caller should quit.
-    Quit,
-    /// The caller should continue running.
-    Continue,
+    bindings: Vec<(Scancode, usize)>,
 }
 
 impl Keyboard {
-    /// Constructs a new Keyboard from the given SDL context.
-    pub fn new(context: &Sdl) -> Self {
+    /// Constructs a new Keyboard from the given SDL context, pressing hex
+    /// keys as the keymap says.
+    pub fn new(context: &Sdl, keymap: &Keymap) -> Self {
+        let mut bindings = Vec::new();
+        for key in 0..16 {
+            for name in keymap.names(key) {
+                // Game controller bindings are read by the gamepad
+                if name.to_lowercase().starts_with("pad ") {
+                    continue;
+                }
+                match Scancode::from_name(name) {
+                    Some(scancode) => bindings.push((scancode, key)),
+                    None => warn!("Unknown key {} in the keymap", name),
+                }
+            }
+        }
         Keyboard {
             event_pump: context.event_pump().unwrap(),
+            bindings,
         }
     }
+}
 
-    /// Checks the keyboard's keys, looking for quit events and which keys
-    /// should be marked as pressed in the given key state array.
-    pub fn check(&mut self, keys: &mut [u8; 16]) -> Command {
-        // Handle quit event
+impl InputSource for Keyboard {
+    /// Checks the keyboard's keys, looking for quit events, hotkeys and which
+    /// keys should be marked as pressed in the given key state array.
+    ///
+    /// Only one command is returned per check; any further events are left
+    /// for the next one.
+    fn check(&mut self, keys: &mut [u8; 16]) -> Command {
+        // Handle quit event and hotkeys
+        let mut command = Command::Continue;
         for event in self.event_pump.poll_iter() {
-            match event {
+            command = match event {
                 Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    return Command::Quit;
+                    Command::Quit
                 },
-                _ => {}
-            }
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => Command::SaveState,
+                Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } => Command::LoadState,
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => Command::PrevSlot,
+                Event::KeyDown { keycode: Some(Keycode::F7), repeat: false, .. } => Command::NextSlot,
+                Event::KeyDown { keycode: Some(Keycode::F8), repeat: false, .. } => Command::NextTheme,
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => Command::Screenshot,
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => Command::Record,
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => Command::Break,
+                _ => continue,
+            };
+            break;
         }
 
         // Record the keyboard state
         let kb = KeyboardState::new(&self.event_pump);
-        keys[0x0] = kb.is_scancode_pressed(Scancode::Num0) as u8;
-        keys[0x1] = kb.is_scancode_pressed(Scancode::Num1) as u8;
-        keys[0x2] = kb.is_scancode_pressed(Scancode::Num2) as u8;
-        keys[0x3] = kb.is_scancode_pressed(Scancode::Num3) as u8;
-        keys[0x4] = kb.is_scancode_pressed(Scancode::Num4) as u8;
-        keys[0x5] = kb.is_scancode_pressed(Scancode::Num5) as u8;
-        keys[0x6] = kb.is_scancode_pressed(Scancode::Num6) as u8;
-        keys[0x7] = kb.is_scancode_pressed(Scancode::Num7) as u8;
-        keys[0x8] = kb.is_scancode_pressed(Scancode::Num8) as u8;
-        keys[0x9] = kb.is_scancode_pressed(Scancode::Num9) as u8;
-        keys[0xA] = kb.is_scancode_pressed(Scancode::A) as u8;
-        keys[0xB] = kb.is_scancode_pressed(Scancode::B) as u8;
-        keys[0xC] = kb.is_scancode_pressed(Scancode::C) as u8;
-        keys[0xD] = kb.is_scancode_pressed(Scancode::D) as u8;
-        keys[0xE] = kb.is_scancode_pressed(Scancode::E) as u8;
-        keys[0xF] = kb.is_scancode_pressed(Scancode::F) as u8;
+        *keys = [0; 16];
+        for &(scancode, key) in &self.bindings {
+            if kb.is_scancode_pressed(scancode) {
+                keys[key] = 1;
+            }
+        }
 
-        Command::Continue
+        match command {
+            Command::Continue if kb.is_scancode_pressed(Scancode::Backspace) => Command::Rewind,
+            _ => command,
+        }
     }
 }
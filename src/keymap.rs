@@ -0,0 +1,244 @@
+//! Which keyboard keys press which of the chip's 16 hex keys.
+//!
+//! Keys are named the way SDL names them ("W", "Up", "Keypad 8", ...) and
+//! compared ignoring case, so each frontend can translate the names into
+//! whatever its keyboard reports.  Game controller buttons are `Pad `
+//! followed by SDL's name for the button ("Pad A", "Pad DPUp", ...), and
+//! sticks and triggers are `Pad ` followed by the axis and a direction
+//! ("Pad LeftX-", "Pad RightTrigger+").
+//!
+//! A keymap file is TOML.  It starts from a preset, binds hex keys to one or
+//! more key names in `[keys]`, and can override those bindings for
+//! particular ROMs, keyed by the SHA-1 of the ROM as in the config file:
+//!
+//! ```toml
+//! preset = "cosmac"
+//!
+//! [keys]
+//! 5 = ["W", "Up"]
+//!
+//! [roms.0123456789abcdef0123456789abcdef01234567]
+//! 1 = "W"
+//! 4 = "S"
+//! C = "Up"
+//! D = "Down"
+//! ```
+
+use std::str::FromStr;
+
+use toml::Value;
+
+/// A key name for each hex key, in the order 0 to F.
+type Layout = [&'static str; 16];
+
+/// The COSMAC VIP's keypad, laid out on the left of a QWERTY keyboard:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D      Q W E R
+/// 7 8 9 E  =>  A S D F
+/// A 0 B F      Z X C V
+/// ```
+const COSMAC: Layout = ["X", "1", "2", "3", "Q", "W", "E", "A",
+                        "S", "D", "Z", "C", "4", "R", "F", "V"];
+
+/// The COSMAC layout on an AZERTY keyboard.
+const AZERTY: Layout = ["X", "1", "2", "3", "A", "Z", "E", "Q",
+                        "S", "D", "W", "C", "4", "R", "F", "V"];
+
+/// Each hex key on the key with the same name.
+const HEX: Layout = ["0", "1", "2", "3", "4", "5", "6", "7",
+                     "8", "9", "A", "B", "C", "D", "E", "F"];
+
+/// The game controller bindings every preset has: the D-pad and left stick
+/// on the 2/4/6/8 directions most games use, and A on 5.
+const PAD: [(&str, usize); 9] = [
+    ("Pad DPUp", 0x2), ("Pad DPDown", 0x8), ("Pad DPLeft", 0x4), ("Pad DPRight", 0x6),
+    ("Pad LeftY-", 0x2), ("Pad LeftY+", 0x8), ("Pad LeftX-", 0x4), ("Pad LeftX+", 0x6),
+    ("Pad A", 0x5),
+];
+
+/// The names of the keyboard keys bound to each hex key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<String>; 16],
+}
+
+impl Default for Keymap {
+    /// The COSMAC layout, which most games are designed around.
+    fn default() -> Self {
+        Keymap::cosmac()
+    }
+}
+
+impl Keymap {
+    fn from_layout(layout: &Layout) -> Self {
+        let mut keymap = Keymap { keys: Default::default() };
+        for (names, name) in keymap.keys.iter_mut().zip(layout.iter()) {
+            names.push(name.to_string());
+        }
+        for &(name, key) in PAD.iter() {
+            keymap.keys[key].push(name.to_string());
+        }
+        keymap
+    }
+
+    /// The COSMAC VIP's keypad on 1234/QWER/ASDF/ZXCV.
+    pub fn cosmac() -> Self {
+        Keymap::from_layout(&COSMAC)
+    }
+
+    /// The COSMAC VIP's keypad on 1234/AZER/QSDF/WXCV.
+    pub fn azerty() -> Self {
+        Keymap::from_layout(&AZERTY)
+    }
+
+    /// Each hex key on the keyboard key with the same name, as this
+    /// emulator used to have it.
+    pub fn hex() -> Self {
+        Keymap::from_layout(&HEX)
+    }
+
+    /// The names of the keyboard keys bound to a hex key.
+    pub fn names(&self, key: usize) -> &[String] {
+        &self.keys[key]
+    }
+
+    /// The hex key a keyboard key is bound to, if any.
+    pub fn key(&self, name: &str) -> Option<usize> {
+        self.keys.iter()
+            .position(|names| names.iter().any(|bound| bound.eq_ignore_ascii_case(name)))
+    }
+
+    /// Binds a hex key to the given keyboard keys, replacing its old
+    /// bindings.  The keyboard keys are unbound from any other hex key
+    /// first, so that one press never presses two hex keys.
+    pub fn bind(&mut self, key: usize, names: &[&str]) {
+        for bound in self.keys.iter_mut() {
+            bound.retain(|old| !names.iter().any(|name| old.eq_ignore_ascii_case(name)));
+        }
+        self.keys[key] = names.iter().map(|name| name.to_string()).collect();
+    }
+
+    /// Reads a keymap file, applying the overrides for the ROM with the
+    /// given key (see `config::rom_key`).
+    pub fn parse(text: &str, rom_key: &str) -> Result<Self, String> {
+        let file = text.parse::<Value>().map_err(|e| format!("Bad keymap: {}", e))?;
+        let mut keymap = match file.get("preset") {
+            Some(preset) => preset.as_str()
+                .ok_or_else(|| "The keymap's preset must be a string".to_string())?
+                .parse()?,
+            None => Keymap::default(),
+        };
+        if let Some(keys) = file.get("keys") {
+            keymap.bind_all(keys)?;
+        }
+        let roms = file.get("roms");
+        if let Some(overrides) = roms.and_then(|roms| roms.get(rom_key.to_lowercase())) {
+            keymap.bind_all(overrides)?;
+        }
+        Ok(keymap)
+    }
+
+    /// Binds every hex key in a table like `[keys]`.
+    fn bind_all(&mut self, table: &Value) -> Result<(), String> {
+        let table = table.as_table()
+            .ok_or_else(|| "Keymap bindings must be a table".to_string())?;
+        for (key, names) in table {
+            let key = usize::from_str_radix(key, 16).ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(|| format!("Not a hex key: {}", key))?;
+            let bad = || format!("Key {:X} must be bound to a key name or a list of them", key);
+            let names = match *names {
+                Value::String(ref name) => vec![name.as_str()],
+                Value::Array(ref names) => names.iter()
+                    .map(|name| name.as_str().ok_or_else(bad))
+                    .collect::<Result<_, _>>()?,
+                _ => return Err(bad()),
+            };
+            self.bind(key, &names);
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Keymap {
+    type Err = String;
+
+    /// Looks up a preset by name: `cosmac`, `azerty` or `hex`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "cosmac" => Ok(Keymap::cosmac()),
+            "azerty" => Ok(Keymap::azerty()),
+            "hex" => Ok(Keymap::hex()),
+            _ => Err(format!("Unknown keymap preset: {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Keymap;
+    use config::rom_key;
+
+    #[test]
+    fn cosmac_layout() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.key("1"), Some(0x1));
+        assert_eq!(keymap.key("4"), Some(0xC));
+        assert_eq!(keymap.key("w"), Some(0x5));
+        assert_eq!(keymap.key("X"), Some(0x0));
+        assert_eq!(keymap.key("V"), Some(0xF));
+        assert_eq!(keymap.key("Up"), None);
+        assert_eq!(keymap.names(0xA), ["Z"]);
+        assert_eq!(keymap.key("pad dpup"), Some(0x2));
+        assert_eq!(keymap.key("Pad A"), Some(0x5));
+    }
+
+    #[test]
+    fn bind_moves_keys() {
+        let mut keymap = Keymap::cosmac();
+        keymap.bind(0x1, &["w", "Up"]);
+        assert_eq!(keymap.key("W"), Some(0x1));
+        assert_eq!(keymap.key("up"), Some(0x1));
+        assert_eq!(keymap.names(0x5), ["Pad A"]);
+    }
+
+    #[test]
+    fn parse_file() {
+        let text = r#"
+            preset = "hex"
+
+            [keys]
+            5 = ["Space", "Return"]
+
+            [roms.a9993e364706816aba3e25717850c26c9cd0d89d]
+            1 = "W"
+            4 = "S"
+            c = "Up"
+            D = "Down"
+        "#;
+        let keymap = Keymap::parse(text, &rom_key(b"tetris")).unwrap();
+        assert_eq!(keymap.key("4"), Some(0x4));
+        assert_eq!(keymap.key("space"), Some(0x5));
+        assert_eq!(keymap.key("5"), None);
+        assert_eq!(keymap.key("Up"), None);
+
+        let keymap = Keymap::parse(text, &rom_key(b"abc")).unwrap();
+        assert_eq!(keymap.key("W"), Some(0x1));
+        assert_eq!(keymap.key("S"), Some(0x4));
+        assert_eq!(keymap.key("Up"), Some(0xC));
+        assert_eq!(keymap.key("Down"), Some(0xD));
+        assert_eq!(keymap.key("C"), None);
+        assert_eq!(Keymap::parse(text, "A9993E364706816ABA3E25717850C26C9CD0D89D").unwrap(), keymap);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Keymap::parse("preset = \"dvorak\"", "").is_err());
+        assert!(Keymap::parse("[keys]\nG = \"W\"", "").is_err());
+        assert!(Keymap::parse("[keys]\n1 = 2", "").is_err());
+        assert!(Keymap::parse("[keys\n", "").is_err());
+        assert_eq!(Keymap::parse("", "").unwrap(), Keymap::cosmac());
+    }
+}
@@ -1,25 +1,67 @@
 extern crate bit_vec;
 extern crate rand;
+extern crate toml;
+
+pub mod asm;
+pub mod checksum;
+pub mod config;
+pub mod disasm;
+pub mod flicker;
+pub mod frontend;
+pub mod gif;
+pub mod keymap;
+pub mod movie;
+pub mod palette;
+pub mod png;
+pub mod octo;
+mod quirks;
+mod random;
+mod state;
+pub mod tone;
+pub mod wav;
 
 use bit_vec::BitVec;
-use rand::Rng;
+use std::error::Error;
 use std::fmt;
 use std::num::Wrapping;
 
-/// The size of the chip's memory (RAM and ROM storage).
-const NMEM: usize = 4096;
+pub use quirks::Quirks;
+pub use random::Random;
+pub use state::{StateError, STATE_VERSION};
+
+/// The size of the chip's memory (RAM and ROM storage).  This is the full
+/// 64 KiB XO-CHIP address space.
+const NMEM: usize = 0x10000;
 
 /// The number of registers.
 const NREG: usize = 16;
 
-/// The width of the display (in pixels)
+/// The width of the display in low resolution mode (in pixels)
 pub const WIDTH: u32 = 64;
 
-/// The height of the display (in pixels)
+/// The height of the display in low resolution mode (in pixels)
 pub const HEIGHT: u32 = 32;
 
-/// The total number of pixels.
-pub const NPIXELS: usize = (WIDTH * HEIGHT) as usize;
+/// The width of the display in SUPER-CHIP high resolution mode (in pixels)
+pub const HIRES_WIDTH: u32 = 128;
+
+/// The height of the display in SUPER-CHIP high resolution mode (in pixels)
+pub const HIRES_HEIGHT: u32 = 64;
+
+/// The number of RPL user flags that 0xFX75 and 0xFX85 can use.
+const NFLAGS: usize = 16;
+
+/// The number of XO-CHIP drawing planes.
+pub const NPLANES: usize = 2;
+
+/// The size of the XO-CHIP audio pattern buffer (in bytes).
+pub const PATTERN_SIZE: usize = 16;
+
+/// Where the large SUPER-CHIP font starts in memory.
+const BIG_FONT_START: usize = 0x50;
+
+/// Where games are loaded into memory and start running.
+pub const PROGRAM_START: u16 = 0x200;
 
 /*
  * From http://www.multigesture.net/articles/how-to-write-an-emulator-chip-8-interpreter/
@@ -27,6 +69,10 @@ pub const NPIXELS: usize = (WIDTH * HEIGHT) as usize;
  * 0x000-0x1FF: Chip 8 interpreter
  * 0x050-0x0A0: 4x5 pixel font set (0-F)
  * 0x200-0xFFF: Program ROM and RAM
+ *
+ * This emulator puts the 4x5 font at 0x000 and the SUPER-CHIP 8x10 font
+ * right after it at 0x050.  XO-CHIP programs can use the rest of the 64 KiB
+ * up to 0xFFFF.
  */
 /// The built in fonts that are loaded into memory during initialization.
 static FONTSET: [u8;80] = [
@@ -48,6 +94,71 @@ static FONTSET: [u8;80] = [
   0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+/// The large SUPER-CHIP fonts that are loaded into memory during
+/// initialization.
+static BIG_FONTSET: [u8;160] = [
+  0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+  0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+  0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+  0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+  0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+  0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+  0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+  0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+  0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+  0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+  0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+  0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+/// The result of successfully running a single cycle on the chip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CycleOutcome {
+    /// The instruction ran and the program counter moved on.
+    Continue,
+    /// The chip is blocked on 0xFX0A until a key is pressed.
+    WaitingForKey,
+    /// The game asked the interpreter to exit with 0x00FD.
+    Exit,
+}
+
+/// The faults the chip can run into while running a cycle.
+///
+/// When a cycle fails the chip is left as it was before the faulting
+/// instruction, so the program counter still points at it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The opcode at `pc` is not a known instruction.
+    InvalidOpcode { pc: u16, opcode: u16 },
+    /// A subroutine was called while the stack was already full.
+    StackOverflow,
+    /// A subroutine returned while the stack was empty.
+    StackUnderflow,
+    /// An instruction tried to access memory past the end of RAM.
+    MemoryOutOfBounds { address: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::InvalidOpcode { pc, opcode } =>
+                write!(f, "Opcode {:#X} at {:#X} is bad", opcode, pc),
+            Chip8Error::StackOverflow =>
+                write!(f, "Stack overflow"),
+            Chip8Error::StackUnderflow =>
+                write!(f, "Stack underflow"),
+            Chip8Error::MemoryOutOfBounds { address } =>
+                write!(f, "Memory access out of bounds at {:#X}", address),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+
 /// Returns a string for the version of the library.
 pub fn version() -> &'static str {
     concat!(env!("CARGO_PKG_VERSION_MAJOR"),
@@ -63,17 +174,29 @@ pub fn version() -> &'static str {
 pub struct Chip8 {
     pub draw_flag: bool,
     opcode: u16,
-    memory: [u8; NMEM],
+    memory: Vec<u8>,
     reg: [u8; NREG],
     index: u16,
     pc: u16,
-    pub graphics: [bool; NPIXELS],
+    hires: bool,
+    /// The pixels on the display, row by row.  This is `width() * height()`
+    /// pixels long.  Each pixel is a bitmask of the XO-CHIP planes it is lit
+    /// on, so plain CHIP-8 games only ever use 0 and 1.
+    pub graphics: Vec<u8>,
+    planes: u8,
     timer_delay: u8,
     timer_sound: u8,
     stack: [u16; 16],
     sp: u16,
+    rpl: [u8; NFLAGS],
     pub key: [u8; 16],
-    pub make_sound: bool,
+    /// Whether the sound timer was running during the last frame
+    sound_active: bool,
+    audio_pattern: Option<[u8; PATTERN_SIZE]>,
+    pitch: u8,
+    quirks: Quirks,
+    rng: Random,
+    rom_hash: u32,
 }
 
 impl fmt::Debug for Chip8 {
@@ -117,97 +240,369 @@ impl fmt::Debug for Chip8 {
                self.stack,
                ).unwrap();
         write!(f, "  graphics:\n").unwrap();
-        let mut row = String::with_capacity(64);
-        write!(f, "+----------------------------------------------------------------+\n").unwrap();
-        for column_index in 0..32 {
-            for row_index in 0..64 {
-                row.push(if self.graphics[column_index * 64 + row_index] { '#' } else { ' ' });
+        let mut row = String::with_capacity(self.width());
+        let border = format!("+{}+\n", "-".repeat(self.width()));
+        write!(f, "{}", border).unwrap();
+        for column_index in 0..self.height() {
+            for row_index in 0..self.width() {
+                row.push([' ', '#', '+', '@'][self.graphics[column_index * self.width() + row_index] as usize & 0x3]);
             }
             write!(f, "|{}|\n", row).unwrap();
             row.clear();
         }
-        write!(f, "+----------------------------------------------------------------+\n")
+        write!(f, "{}", border)
     }
 }
 
 impl Default for Chip8 {
-    /// Constructs a new Chip8 emulator.
+    /// Constructs a new Chip8 emulator with the default quirks and a random
+    /// seed.
     fn default() -> Self {
+        Chip8::new(Quirks::default(), rand::random())
+    }
+}
+
+impl Chip8 {
+    /// Constructs a new Chip8 emulator that follows the given quirks.  Its
+    /// random numbers come from the given seed, so two chips with the same
+    /// seed, ROM and input play out exactly the same.
+    pub fn new(quirks: Quirks, seed: u64) -> Self {
         let mut chip = Chip8 {
             draw_flag: true,
             opcode: 0,
-            memory: [0; NMEM],
+            memory: vec![0; NMEM],
             reg: [0; NREG],
             index: 0,
-            pc: 0x200,
-            graphics: [false; NPIXELS],
+            pc: PROGRAM_START,
+            hires: false,
+            graphics: vec![0; (WIDTH * HEIGHT) as usize],
+            planes: 0x1,
             timer_delay: 0,
             timer_sound: 0,
             stack: [0; 16],
             sp: 0,
+            rpl: [0; NFLAGS],
             key: [0; 16],
-            make_sound: false,
+            sound_active: false,
+            audio_pattern: None,
+            pitch: 64,
+            quirks,
+            rng: Random::new(seed),
+            rom_hash: checksum::crc32(&[]),
         };
 
         // Initialize the font set
         for (i, item) in FONTSET.iter().enumerate().take(80) {
             chip.memory[i] = *item;
         }
+        for (i, item) in BIG_FONTSET.iter().enumerate() {
+            chip.memory[BIG_FONT_START + i] = *item;
+        }
 
         chip
     }
-}
 
-impl Chip8 {
+    /// The quirks this chip follows.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Whether the display is in SUPER-CHIP high resolution mode.
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /// The width of the display in its current resolution (in pixels).
+    pub fn width(&self) -> usize {
+        (if self.hires { HIRES_WIDTH } else { WIDTH }) as usize
+    }
+
+    /// The height of the display in its current resolution (in pixels).
+    pub fn height(&self) -> usize {
+        (if self.hires { HIRES_HEIGHT } else { HEIGHT }) as usize
+    }
+
+    /// The XO-CHIP audio pattern set by 0xF002, if the game has set one.
+    /// Each bit is one sample of a 1-bit waveform, most significant bit
+    /// first, that loops while the sound timer is running.
+    pub fn audio_pattern(&self) -> Option<[u8; PATTERN_SIZE]> {
+        self.audio_pattern
+    }
+
+    /// The XO-CHIP pitch register set by 0xFX3A.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// The rate (in bits per second) the audio pattern plays back at, given
+    /// the current pitch.
+    pub fn pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// Switches the display resolution, which also clears it.
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.graphics = vec![0; self.width() * self.height()];
+        self.draw_flag = true;
+    }
+
+    /// Moves everything on the selected planes by the given number of pixels,
+    /// filling the uncovered pixels with blanks.
+    fn scroll(&mut self, dx: i32, dy: i32) {
+        let (width, height) = (self.width() as i32, self.height() as i32);
+        let old = self.graphics.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let moved = if 0 <= src_x && src_x < width && 0 <= src_y && src_y < height {
+                    old[(src_y * width + src_x) as usize]
+                } else {
+                    0
+                };
+                let pixel = &mut self.graphics[(y * width + x) as usize];
+                *pixel = (*pixel & !self.planes) | (moved & self.planes);
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    /// Moves the program counter past the next instruction, which is four
+    /// bytes long if it is the XO-CHIP 0xF000 long load.
+    fn skip_next(&mut self) {
+        let next = self.pc as usize + 2;
+        let long = next + 1 < NMEM && self.memory[next] == 0xF0 && self.memory[next + 1] == 0x00;
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
     /// Loads the given bytes into the chip's memory.
     pub fn load(&mut self, game: &[u8]) {
         for (i, byte) in game.iter().enumerate() {
-            self.memory[i + 0x200] = *byte;
+            self.memory[i + PROGRAM_START as usize] = *byte;
         }
+        self.rom_hash = checksum::crc32(game);
+    }
+
+    /// The CRC-32 of the ROM that was loaded, which identifies the game.
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    /// The address of the next instruction to run.
+    pub fn pc(&self) -> u16 {
+        self.pc
     }
 
-    /// Run the emulator through a single cycle.
+    /// Moves the program counter, so the next instruction is run from the
+    /// given address.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// The index register, I.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Sets the index register, I.
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
+
+    /// The general purpose registers, reg0 through regF.
+    pub fn registers(&self) -> &[u8; NREG] {
+        &self.reg
+    }
+
+    /// Sets general purpose register regX to the given value.
     /// # Panics
-    /// If the emulator comes across an invalid opcode, it will panic with a
-    /// description of the error (including the invalid opcode).
-    pub fn emulate_cycle(&mut self) {
+    /// If `x` isn't between 0x0 and 0xF.
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.reg[x] = value;
+    }
+
+    /// The return addresses on the stack, oldest first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    /// The delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.timer_delay
+    }
+
+    /// Sets the delay timer.
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.timer_delay = value;
+    }
+
+    /// The sound timer.
+    pub fn sound_timer(&self) -> u8 {
+        self.timer_sound
+    }
+
+    /// Whether the chip should be sounding: the sound timer was above zero
+    /// for the frame just run, so a timer set to N sounds for N frames.
+    pub fn sound_active(&self) -> bool {
+        self.sound_active
+    }
+
+    /// Whether the sound timer ran out in the frame just run, which is when
+    /// this emulator used to beep.
+    #[deprecated(note = "beeps should last while the sound timer runs; use `sound_active`")]
+    pub fn make_sound(&self) -> bool {
+        self.sound_active && self.timer_sound == 0
+    }
+
+    /// Sets the sound timer.
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.timer_sound = value;
+    }
+
+    /// The chip's whole memory.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The chip's whole memory, for changing it directly.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// Run a single instruction.  This doesn't touch the timers, which run
+    /// at 60 Hz however fast instructions are run; see `tick_timers`.
+    /// # Errors
+    /// If the emulator comes across an invalid opcode, a stack fault or an
+    /// out of range memory access, the instruction is aborted and the error
+    /// is returned.  The chip is left at the faulting instruction.
+    pub fn step_instruction(&mut self) -> Result<CycleOutcome, Chip8Error> {
         // Fetch opcode
-        self.fetch_opcode();
+        self.fetch_opcode()?;
 
         // Decode and Execute opcode
-        self.execute_opcode();
+        self.execute_opcode()
+    }
 
-        // Update timers
-        self.update_timers();
+    /// Run the emulator through a single cycle: one instruction, then a tick
+    /// of the timers, as this emulator used to run.
+    /// # Errors
+    /// As `step_instruction`; the timers aren't ticked if it fails.
+    #[deprecated(note = "ties the timers to the instruction rate; use `step_instruction` \
+                         with `tick_timers`, or `run_frame`")]
+    pub fn emulate_cycle(&mut self) -> Result<CycleOutcome, Chip8Error> {
+        let outcome = self.step_instruction()?;
+        self.tick_timers();
+        Ok(outcome)
+    }
+
+    /// Run one 60 Hz frame: up to `instructions` instructions, followed by a
+    /// tick of the timers.
+    ///
+    /// The frame ends early if the chip starts waiting for a key, since it
+    /// can't get one until the next frame, or if the game exits, in which
+    /// case the timers aren't ticked.
+    /// # Errors
+    /// Stops at the first instruction that fails, without ticking the timers.
+    pub fn run_frame(&mut self, instructions: usize) -> Result<CycleOutcome, Chip8Error> {
+        let mut outcome = CycleOutcome::Continue;
+        for _ in 0..instructions {
+            outcome = self.step_instruction()?;
+            match outcome {
+                CycleOutcome::Continue => {}
+                CycleOutcome::WaitingForKey => break,
+                CycleOutcome::Exit => return Ok(outcome),
+            }
+        }
+
+        self.tick_timers();
+
+        Ok(outcome)
     }
 
     /// Read the next opcode from memory.
-    fn fetch_opcode(&mut self) {
+    fn fetch_opcode(&mut self) -> Result<(), Chip8Error> {
+        self.check_memory(self.pc as usize, 2)?;
         self.opcode = (self.memory[self.pc as usize] as u16) << 8 |
             self.memory[(self.pc + 1) as usize] as u16;
+        Ok(())
+    }
+
+    /// Make sure that `len` bytes starting at `start` are all within memory.
+    fn check_memory(&self, start: usize, len: usize) -> Result<(), Chip8Error> {
+        if start + len > NMEM {
+            let address = if start < NMEM { NMEM } else { start };
+            Err(Chip8Error::MemoryOutOfBounds { address })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Builds the error for the current opcode being invalid.
+    fn invalid_opcode(&self) -> Chip8Error {
+        Chip8Error::InvalidOpcode { pc: self.pc, opcode: self.opcode }
     }
 
     /// Run the current opcode, storing the results in the chip.
-    /// # Panics
-    fn execute_opcode(&mut self) {
+    /// # Errors
+    /// Returns an error without changing the chip if the opcode can't be run.
+    fn execute_opcode(&mut self) -> Result<CycleOutcome, Chip8Error> {
         match self.opcode & 0xF000 {
             0x0000 => {
                 match self.opcode {
+                    0x00C0..=0x00CF => {
+                        // 0x00CN: Scroll the display down by N pixels
+                        let n = (self.opcode & 0x000F) as i32;
+                        self.scroll(0, n);
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x00D0..=0x00DF => {
+                        // 0x00DN: Scroll the display up by N pixels
+                        let n = (self.opcode & 0x000F) as i32;
+                        self.scroll(0, -n);
+                        self.pc = self.pc.wrapping_add(2);
+                    }
                     0x00E0 => {
-                        // 0x00E0: Clears the screen
-                        for i in 0..NPIXELS {
-                            self.graphics[i] = false;
+                        // 0x00E0: Clears the selected planes of the screen
+                        for pixel in self.graphics.iter_mut() {
+                            *pixel &= !self.planes;
                         }
                         self.draw_flag = true;
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x00EE => {
                         // 0x00EE: Return from subroutine
+                        if self.sp == 0 {
+                            return Err(Chip8Error::StackUnderflow);
+                        }
                         self.sp -= 1;
                         self.pc = self.stack[self.sp as usize];
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x00FB => {
+                        // 0x00FB: Scroll the display right by 4 pixels
+                        self.scroll(4, 0);
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x00FC => {
+                        // 0x00FC: Scroll the display left by 4 pixels
+                        self.scroll(-4, 0);
+                        self.pc = self.pc.wrapping_add(2);
                     }
-                    _ => panic!("Opcode {:#X} is bad", self.opcode),
+                    0x00FD => {
+                        // 0x00FD: Exit the interpreter, staying on this instruction
+                        return Ok(CycleOutcome::Exit);
+                    }
+                    0x00FE => {
+                        // 0x00FE: Switch to low resolution mode
+                        self.set_hires(false);
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x00FF => {
+                        // 0x00FF: Switch to high resolution mode
+                        self.set_hires(true);
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    _ => return Err(self.invalid_opcode()),
                 }
             }
             0x1000 => {
@@ -216,6 +611,9 @@ impl Chip8 {
             }
             0x2000 => {
                 // 0x2NNN: Call subroutine at NNN
+                if self.sp as usize >= self.stack.len() {
+                    return Err(Chip8Error::StackOverflow);
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
                 self.pc = self.opcode & 0x0FFF;
@@ -225,34 +623,61 @@ impl Chip8 {
                 let x = (self.opcode & 0x0F00) >> 8;
                 let nn = (self.opcode & 0x00FF) as u8;
                 if self.reg[x as usize] == nn {
-                    self.pc += 2;
+                    self.skip_next();
                 }
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             },
             0x4000 => {
                 // 0x4XNN: Skip next instruction if regX does not equal NN
                 let x = (self.opcode & 0x0F00) >> 8;
                 let nn = (self.opcode & 0x00FF) as u8;
                 if self.reg[x as usize] != nn {
-                    self.pc += 2;
+                    self.skip_next();
                 }
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             },
             0x5000 => {
-                // 0x5XY0: Skip next instruction if regX equals regY
-                let x = (self.opcode & 0x0F00) >> 8;
-                let y = (self.opcode & 0x00F0) >> 4;
-                if self.reg[x as usize] == self.reg[y as usize] {
-                    self.pc += 2;
+                let x = ((self.opcode & 0x0F00) >> 8) as usize;
+                let y = ((self.opcode & 0x00F0) >> 4) as usize;
+                match self.opcode & 0x000F {
+                    0x0000 => {
+                        // 0x5XY0: Skip next instruction if regX equals regY
+                        if self.reg[x] == self.reg[y] {
+                            self.skip_next();
+                        }
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x0002 => {
+                        // 0x5XY2: Stores regX through regY (inclusive, in either order)
+                        // in memory starting at index
+                        let count = if x <= y { y - x + 1 } else { x - y + 1 };
+                        self.check_memory(self.index as usize, count)?;
+                        for i in 0..count {
+                            let r = if x <= y { x + i } else { x - i };
+                            self.memory[self.index as usize + i] = self.reg[r];
+                        }
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x0003 => {
+                        // 0x5XY3: Fills regX through regY (inclusive, in either order)
+                        // from memory starting at index
+                        let count = if x <= y { y - x + 1 } else { x - y + 1 };
+                        self.check_memory(self.index as usize, count)?;
+                        for i in 0..count {
+                            let r = if x <= y { x + i } else { x - i };
+                            self.reg[r] = self.memory[self.index as usize + i];
+                        }
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    _ => return Err(self.invalid_opcode()),
                 }
-                self.pc += 2;
             },
             0x6000 => {
                 // 0x6XNN: Set regX to NN
                 let x = (self.opcode & 0x0F00) >> 8;
                 let nn = (self.opcode & 0x00FF) as u8;
                 self.reg[x as usize] = nn;
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             },
             0x7000 => {
                 // 0x7XNN: Add NN to regX
@@ -260,7 +685,7 @@ impl Chip8 {
                 let nn = Wrapping((self.opcode & 0x00FF) as u8);
                 let x_val = Wrapping(self.reg[x as usize]);
                 self.reg[x as usize] = (x_val + nn).0;
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             },
             0x8000 => {
                 match self.opcode & 0x000F {
@@ -269,175 +694,220 @@ impl Chip8 {
                         let x = (self.opcode & 0x0F00) >> 8;
                         let y = (self.opcode & 0x00F0) >> 4;
                         self.reg[x as usize] = self.reg[y as usize];
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0001 => {
                         // 0x8XY1: Set regX to regX | regY
                         let x = (self.opcode & 0x0F00) >> 8;
                         let y = (self.opcode & 0x00F0) >> 4;
                         self.reg[x as usize] |= self.reg[y as usize];
-                        self.pc += 2;
+                        if self.quirks.logic_resets_vf {
+                            self.reg[0xF] = 0;
+                        }
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0002 => {
                         // 0x8XY2: Set regX to regX & regY
                         let x = (self.opcode & 0x0F00) >> 8;
                         let y = (self.opcode & 0x00F0) >> 4;
                         self.reg[x as usize] &= self.reg[y as usize];
-                        self.pc += 2;
+                        if self.quirks.logic_resets_vf {
+                            self.reg[0xF] = 0;
+                        }
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0003 => {
                         // 0x8XY3: Set regX to regX ^ regY
                         let x = (self.opcode & 0x0F00) >> 8;
                         let y = (self.opcode & 0x00F0) >> 4;
                         self.reg[x as usize] ^= self.reg[y as usize];
-                        self.pc += 2;
-                    }
-                    0x0004 => {
-                        // 0x8XY4: Add regY to regX, set carry if needed
-                        if self.reg[((self.opcode & 0x00F0) >> 4) as usize] >
-                            (0xFF - self.reg[((self.opcode & 0x0F00) >> 8) as usize]) {
-                            self.reg[0xF] = 1;
-                        } else {
+                        if self.quirks.logic_resets_vf {
                             self.reg[0xF] = 0;
                         }
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x0004 => {
+                        // 0x8XY4: Add regY to regX, set carry if needed.  The
+                        // COSMAC VIP stores the sum in regX before the carry
+                        // in regF, so when X is F the carry is what's left.
+                        // 8XY5 and 8XY7 do the same with their flag.
+                        let carry = self.reg[((self.opcode & 0x00F0) >> 4) as usize] >
+                            (0xFF - self.reg[((self.opcode & 0x0F00) >> 8) as usize]);
                         let x = Wrapping(self.reg[((self.opcode & 0x0F00) >> 8) as usize]);
                         let y = Wrapping(self.reg[((self.opcode & 0x00F0) >> 4) as usize]);
                         self.reg[((self.opcode & 0x0F00) >> 8) as usize] = (x + y).0;
-                        self.pc += 2;
+                        self.reg[0xF] = carry as u8;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0005 => {
                         // 0x8XY5: regX -= regY, regF = 0 if borrow, else 1
                         let x_index = ((self.opcode & 0x0F00) >> 8) as usize;
                         let y_index = ((self.opcode & 0x00F0) >> 4) as usize;
-                        if self.reg[y_index] > self.reg[x_index] {
-                            self.reg[0xF] = 0;
-                        } else {
-                            self.reg[0xF] = 1;
-                        }
+                        let not_borrow = self.reg[x_index] >= self.reg[y_index];
                         let x = Wrapping(self.reg[x_index]);
                         let y = Wrapping(self.reg[y_index]);
                         self.reg[x_index] = (x - y).0;
-                        self.pc += 2;
+                        self.reg[0xF] = not_borrow as u8;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0006 => {
-                        // 0x8X_6: Shifts regX right by one, setting regF to lsb of regX before
-                        let x = (self.opcode & 0x0F00) >> 8;
-                        let lsb = (self.reg[x as usize] & 0x0001) as u8;
-                        self.reg[0xF] = lsb;
-                        self.reg[x as usize] >>= 1;
-                        self.pc += 2;
+                        // 0x8XY6: Shifts regX (or regY, depending on quirks) right by one
+                        // into regX, setting regF to lsb of the value before
+                        let x = ((self.opcode & 0x0F00) >> 8) as usize;
+                        let y = ((self.opcode & 0x00F0) >> 4) as usize;
+                        let value = if self.quirks.shift_uses_vy { self.reg[y] } else { self.reg[x] };
+                        self.reg[x] = value >> 1;
+                        self.reg[0xF] = value & 0x01;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0007 => {
                         // 0x8XY7: regX = regY - regX, regF = 0 if borrow, else 1
                         let x_index = ((self.opcode & 0x0F00) >> 8) as usize;
                         let y_index = ((self.opcode & 0x00F0) >> 4) as usize;
-                        if self.reg[x_index] > self.reg[y_index] {
-                            self.reg[0xF] = 0;
-                        } else {
-                            self.reg[0xF] = 1;
-                        }
+                        let not_borrow = self.reg[y_index] >= self.reg[x_index];
                         let x = Wrapping(self.reg[x_index]);
                         let y = Wrapping(self.reg[y_index]);
                         self.reg[x_index] = (y - x).0;
-                        self.pc += 2;
+                        self.reg[0xF] = not_borrow as u8;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x000E => {
-                        // 0x8X_E: Shifts regX left by one, setting regF to msb of regX before
-                        let x = (self.opcode & 0x0F00) >> 8;
-                        let msb = ((self.reg[x as usize] & 0x80) as u8) >> 7;
-                        self.reg[0xF] = msb;
-                        self.reg[x as usize] <<= 1;
-                        self.pc += 2;
+                        // 0x8XYE: Shifts regX (or regY, depending on quirks) left by one
+                        // into regX, setting regF to msb of the value before
+                        let x = ((self.opcode & 0x0F00) >> 8) as usize;
+                        let y = ((self.opcode & 0x00F0) >> 4) as usize;
+                        let value = if self.quirks.shift_uses_vy { self.reg[y] } else { self.reg[x] };
+                        self.reg[x] = value << 1;
+                        self.reg[0xF] = value >> 7;
+                        self.pc = self.pc.wrapping_add(2);
                     }
-                    _ => panic!("Opcode {:#X} is bad", self.opcode),
+                    _ => return Err(self.invalid_opcode()),
                 }
             },
-            0x9000 => {
+            0x9000 if self.opcode & 0x000F == 0 => {
                 // 0x9XY0: Skip next instruction if regX does not equal regY
                 let x = (self.opcode & 0x0F00) >> 8;
                 let y = (self.opcode & 0x00F0) >> 4;
                 if self.reg[x as usize] != self.reg[y as usize] {
-                    self.pc += 2;
+                    self.skip_next();
                 }
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             },
             0xA000 => {
                 // 0xANNN: Sets I to the address NNN
                 self.index = self.opcode & 0x0FFF;
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             },
             0xB000 => {
-                // 0xBNNN: Jump to address NNN + reg0
+                // 0xBNNN: Jump to address NNN + reg0 (or regX, depending on quirks)
                 let address = self.opcode & 0x0FFF;
-                self.pc = address + self.reg[0] as u16;
+                let x = if self.quirks.jump_uses_vx { (address >> 8) as usize } else { 0 };
+                self.pc = address + self.reg[x] as u16;
             }
             0xC000 => {
                 // 0xCXNN: regX = random number & NN
                 let x = (self.opcode & 0x0F00) >> 8;
                 let nn = (self.opcode & 0x00FF) as u8;
-                self.reg[x as usize] = nn & (rand::thread_rng().gen_range(0,255) as u8);
-                self.pc += 2;
+                self.reg[x as usize] = nn & self.rng.next_u8();
+                self.pc = self.pc.wrapping_add(2);
             }
             0xD000 => {
                 // 0xDXYN: Draw sprint at regX,regY with N bytes of data, start at index
-                // TODO: Fix wrapping of stuff
-                let x = self.reg[((self.opcode & 0x0F00) >> 8) as usize] as i32;
-                let y = self.reg[((self.opcode & 0x00F0) >> 4) as usize] as i32;
-                let n = (self.opcode & 0x000F) as u16;
-                let start = self.index;
+                // 0xDXY0: Draw a 16x16 SUPER-CHIP sprite of 32 bytes instead
+                // Each selected XO-CHIP plane is drawn in turn with its own sprite data
+                // The sprite's position wraps, its pixels clip or wrap depending on quirks
+                let (width, height) = (self.width() as i32, self.height() as i32);
+                let x = self.reg[((self.opcode & 0x0F00) >> 8) as usize] as i32 % width;
+                let y = self.reg[((self.opcode & 0x00F0) >> 4) as usize] as i32 % height;
+                let n = (self.opcode & 0x000F) as usize;
+                let (rows, bytes_per_row) = if n == 0 { (16, 2) } else { (n, 1) };
+                let planes: Vec<u8> = (0..NPLANES)
+                    .map(|plane| 1 << plane)
+                    .filter(|&mask| self.planes & mask != 0)
+                    .collect();
+                let sprite_len = rows * bytes_per_row;
+                self.check_memory(self.index as usize, sprite_len * planes.len())?;
 
                 self.reg[0xF] = 0;
-                for i in start..(start+n) {
-                    let row_num = (i - start) as u8;
-                    let bits = BitVec::from_bytes(&[self.memory[i as usize]]);
-                    for j in 0..8 {
-                        let x_s = x + j;
-                        let y_s = y + row_num as i32;
-                        if 0 <= x_s &&
-                           x_s < 64 &&
-                           0 <= y_s &&
-                           y_s < 32 {
-                               let address = (64 * y_s) + x_s;
-                               if bits[j as usize] {
-                                   if self.graphics[address as usize] {
-                                       self.reg[0xF] = 1;
+                for (plane_num, &mask) in planes.iter().enumerate() {
+                    let start = self.index as usize + plane_num * sprite_len;
+                    for row_num in 0..rows {
+                        let row_start = start + row_num * bytes_per_row;
+                        let bits = BitVec::from_bytes(&self.memory[row_start..row_start + bytes_per_row]);
+                        for j in 0..(bytes_per_row * 8) as i32 {
+                            let mut x_s = x + j;
+                            let mut y_s = y + row_num as i32;
+                            if self.quirks.wrap_sprites {
+                                x_s %= width;
+                                y_s %= height;
+                            }
+                            if 0 <= x_s &&
+                               x_s < width &&
+                               0 <= y_s &&
+                               y_s < height {
+                                   let address = (width * y_s) + x_s;
+                                   if bits[j as usize] {
+                                       if self.graphics[address as usize] & mask != 0 {
+                                           self.reg[0xF] = 1;
+                                       }
+                                       self.graphics[address as usize] ^= mask;
                                    }
-                                   self.graphics[address as usize] ^= true;
                                }
-                           }
+                        }
                     }
                 }
                 self.draw_flag = true;
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             }
             0xE000 => {
                 match self.opcode & 0x00FF {
                     0x009E => {
                         // 0xEX9E: Skips next instruction if key store in regX is pressed
                         let x = (self.opcode & 0x0F00) >> 8;
-                        if self.key[self.reg[x as usize] as usize] != 0 {
-                            self.pc += 2;
+                        if self.key[(self.reg[x as usize] & 0xF) as usize] != 0 {
+                            self.skip_next();
                         }
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x00A1 => {
                         // 0xEXA1: Skips next instruction if key store in regX is not pressed
                         let x = (self.opcode & 0x0F00) >> 8;
-                        if self.key[self.reg[x as usize] as usize] == 0 {
-                            self.pc += 2;
+                        if self.key[(self.reg[x as usize] & 0xF) as usize] == 0 {
+                            self.skip_next();
                         }
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
-                    _ => panic!("Opcode {:#X} is bad", self.opcode),
+                    _ => return Err(self.invalid_opcode()),
                 }
             },
             0xF000 => {
                 match self.opcode & 0x00FF {
+                    0x0000 if self.opcode == 0xF000 => {
+                        // 0xF000 NNNN: Sets index to the 16 bit address NNNN
+                        self.check_memory(self.pc as usize + 2, 2)?;
+                        self.index = (self.memory[self.pc as usize + 2] as u16) << 8 |
+                            self.memory[self.pc as usize + 3] as u16;
+                        self.pc = self.pc.wrapping_add(4);
+                    }
+                    0x0001 => {
+                        // 0xFN01: Selects the planes in bitmask N for drawing
+                        self.planes = ((self.opcode & 0x0F00) >> 8) as u8 & 0x3;
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x0002 if self.opcode == 0xF002 => {
+                        // 0xF002: Loads the audio pattern from memory starting at index
+                        self.check_memory(self.index as usize, PATTERN_SIZE)?;
+                        let mut pattern = [0; PATTERN_SIZE];
+                        let start = self.index as usize;
+                        pattern.copy_from_slice(&self.memory[start..start + PATTERN_SIZE]);
+                        self.audio_pattern = Some(pattern);
+                        self.pc = self.pc.wrapping_add(2);
+                    }
                     0x0007 => {
                         // 0xFX07: Sets regX to the value of the delay timer
                         let x = (self.opcode & 0x0F00) >> 8;
                         self.reg[x as usize] = self.timer_delay;
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x000A => {
                         // 0xFX0A: Wait for a keypress, halting operation, store in regX
@@ -445,78 +915,113 @@ impl Chip8 {
                         for k in 0..0xF {
                             if self.key[k as usize] != 0 {
                                 self.reg[x] = k as u8;
-                                self.pc += 2;
-                                break;
+                                self.pc = self.pc.wrapping_add(2);
+                                return Ok(CycleOutcome::Continue);
                             }
                         }
+                        return Ok(CycleOutcome::WaitingForKey);
                     }
                     0x0015 => {
                         // 0xFX15: Sets delay timer to regX
                         let x = (self.opcode & 0x0F00) >> 8;
                         self.timer_delay = self.reg[x as usize];
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0018 => {
                         // 0xFX18: Sets sound timer to regX
                         let x = (self.opcode & 0x0F00) >> 8;
                         self.timer_sound = self.reg[x as usize];
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x001E => {
                         // 0xFX1E: Add regX to index
                         let x = (self.opcode & 0x0F00) >> 8;
-                        self.index += self.reg[x as usize] as u16;
-                        self.pc += 2;
+                        self.index = self.index.wrapping_add(self.reg[x as usize] as u16);
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0029 => {
                         // 0xFX29: Sets index to location of character in regX
                         let x = (self.opcode & 0x0F00) >> 8;
                         self.index = 5 * self.reg[x as usize] as u16;
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x0030 => {
+                        // 0xFX30: Sets index to location of large character in regX
+                        let x = (self.opcode & 0x0F00) >> 8;
+                        let digit = (self.reg[x as usize] & 0xF) as usize;
+                        self.index = (BIG_FONT_START + 10 * digit) as u16;
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x003A => {
+                        // 0xFX3A: Sets the audio pitch to regX
+                        let x = (self.opcode & 0x0F00) >> 8;
+                        self.pitch = self.reg[x as usize];
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0033 => {
                         // 0xFX33: Store binary coded decimal of regX
                         // http://www.multigesture.net/wp-content/uploads/mirror/goldroad/chip8.shtml
                         let x = ((self.opcode & 0x0F00) >> 8) as usize;
+                        self.check_memory(self.index as usize, 3)?;
                         self.memory[self.index as usize] = self.reg[x] / 100;
                         self.memory[(self.index + 1) as usize] = (self.reg[x] / 10) % 10;
                         self.memory[(self.index + 2) as usize] = (self.reg[x] % 100) % 10;
-                        self.pc += 2;
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0055 => {
                         // 0xFX55: Stores reg0 through regX (inclusive) in memory starting at index
                         let x = ((self.opcode & 0x0F00) >> 8) as usize;
+                        self.check_memory(self.index as usize, x + 1)?;
                         for i in 0..(x+1) {
                             self.memory[self.index as usize + i] = self.reg[i];
                         }
-                        self.pc += 2;
+                        if self.quirks.load_store_increments_index {
+                            self.index = self.index.wrapping_add(x as u16 + 1);
+                        }
+                        self.pc = self.pc.wrapping_add(2);
                     }
                     0x0065 => {
                         // 0xFX65: Fills reg0 through regX (inclusive) from memory starting at index
                         let x = ((self.opcode & 0x0F00) >> 8) as usize;
+                        self.check_memory(self.index as usize, x + 1)?;
                         for i in 0..(x+1) {
                             self.reg[i] = self.memory[self.index as usize + i];
                         }
-                        self.pc += 2;
+                        if self.quirks.load_store_increments_index {
+                            self.index = self.index.wrapping_add(x as u16 + 1);
+                        }
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x0075 => {
+                        // 0xFX75: Stores reg0 through regX (inclusive) in the RPL user flags
+                        let x = ((self.opcode & 0x0F00) >> 8) as usize;
+                        self.rpl[..(x+1)].copy_from_slice(&self.reg[..(x+1)]);
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+                    0x0085 => {
+                        // 0xFX85: Fills reg0 through regX (inclusive) from the RPL user flags
+                        let x = ((self.opcode & 0x0F00) >> 8) as usize;
+                        self.reg[..(x+1)].copy_from_slice(&self.rpl[..(x+1)]);
+                        self.pc = self.pc.wrapping_add(2);
                     }
-                    _ => panic!("Opcode {:#X} is bad", self.opcode),
+                    _ => return Err(self.invalid_opcode()),
                 }
             },
-            _ => panic!("Opcode {:#X} is bad", self.opcode),
+            _ => return Err(self.invalid_opcode()),
         }
+
+        Ok(CycleOutcome::Continue)
     }
 
-    /// Update the chip's internal timers for delay and sound.
-    fn update_timers(&mut self) {
+    /// Count the chip's internal timers for delay and sound down by one.
+    /// This should be called 60 times a second.
+    pub fn tick_timers(&mut self) {
         if self.timer_delay > 0 {
             self.timer_delay -= 1;
         }
 
-        self.make_sound = false;
+        self.sound_active = self.timer_sound > 0;
         if self.timer_sound > 0 {
-            if self.timer_sound == 1 {
-                self.make_sound = true;
-            }
             self.timer_sound -= 1;
         }
     }
@@ -524,18 +1029,18 @@ impl Chip8 {
 
 #[cfg(test)]
 mod test {
-    use super::Chip8;
+    use super::{Chip8, Chip8Error, CycleOutcome, Quirks, BIG_FONT_START};
 
     #[test]
     fn op_00e0() {
         let mut chip = Chip8::default();
         chip.load(&vec![0x00, 0xE0]);
-        chip.graphics[1] = true;
+        chip.graphics[1] = 1;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
-        assert_eq!(chip.graphics[1], false);
+        assert_eq!(chip.graphics[1], 0);
     }
 
     #[test]
@@ -546,7 +1051,7 @@ mod test {
         chip.sp = 1;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 0x44);
         assert_eq!(chip.sp, 0);
     }
@@ -556,7 +1061,7 @@ mod test {
         let mut chip = Chip8::default();
         chip.load(&vec![0x16, 0x66]);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 0x666);
     }
 
@@ -565,7 +1070,7 @@ mod test {
         let mut chip = Chip8::default();
         chip.load(&vec![0x26, 0x66]);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 0x666);
         assert_eq!(chip.stack[0], 512);
         assert_eq!(chip.sp, 1);
@@ -577,9 +1082,9 @@ mod test {
         chip.load(&vec![0x31, 0x66, 0x31, 0x67]);
         chip.reg[1] = 0x67;
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 518);
     }
 
@@ -589,9 +1094,9 @@ mod test {
         chip.load(&vec![0x41, 0x66, 0x41, 0x67]);
         chip.reg[1] = 0x66;
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 518);
     }
 
@@ -603,9 +1108,9 @@ mod test {
         chip.reg[2] = 0x22;
         chip.reg[3] = 0x66;
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 518);
     }
 
@@ -615,7 +1120,7 @@ mod test {
         chip.load(&vec![0x6A, 0x2F]);
         assert_eq!(chip.reg[0xA], 0);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.reg[0xA], 0x2F);
         assert_eq!(chip.pc, 514);
     }
@@ -627,7 +1132,7 @@ mod test {
         chip.reg[0xA] = 0xB;
         assert_eq!(chip.reg[0xA], 0xB);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.reg[0xA], 0x2F + 0xB);
         assert_eq!(chip.pc, 514);
     }
@@ -641,7 +1146,7 @@ mod test {
         assert_eq!(chip.reg[0xA], 0xB);
         assert_eq!(chip.reg[0x2], 0xC);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.reg[0xA], 0xC);
         assert_eq!(chip.reg[0x2], 0xC);
         assert_eq!(chip.pc, 514);
@@ -656,7 +1161,7 @@ mod test {
         assert_eq!(chip.reg[0xA], 0xB);
         assert_eq!(chip.reg[0x2], 0xC);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.reg[0xA], 0xB | 0xC);
         assert_eq!(chip.reg[0x2], 0xC);
         assert_eq!(chip.pc, 514);
@@ -671,7 +1176,7 @@ mod test {
         assert_eq!(chip.reg[0xA], 0xB);
         assert_eq!(chip.reg[0x2], 0xC);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.reg[0xA], 0xB & 0xC);
         assert_eq!(chip.reg[0x2], 0xC);
         assert_eq!(chip.pc, 514);
@@ -686,7 +1191,7 @@ mod test {
         assert_eq!(chip.reg[0xA], 0xB);
         assert_eq!(chip.reg[0x2], 0xC);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.reg[0xA], 0xB ^ 0xC);
         assert_eq!(chip.reg[0x2], 0xC);
         assert_eq!(chip.pc, 514);
@@ -701,13 +1206,13 @@ mod test {
         chip.reg[0xC] = 0x01;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.reg[0xA], 0xFF);
         assert_eq!(chip.reg[0xB], 0xFF);
         assert_eq!(chip.reg[0xF], 0x00);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 516);
         assert_eq!(chip.reg[0xB], 0x0);
         assert_eq!(chip.reg[0xC], 0x1);
@@ -722,7 +1227,7 @@ mod test {
         chip.reg[0xB] = 0x02;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.reg[0xA], 0xFF);
         assert_eq!(chip.reg[0xB], 0x02);
@@ -730,7 +1235,7 @@ mod test {
         chip.reg[0xA] = 0x02;
         chip.reg[0xB] = 0x01;
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 516);
         assert_eq!(chip.reg[0xA], 0x1);
         assert_eq!(chip.reg[0xB], 0x1);
@@ -744,7 +1249,7 @@ mod test {
         chip.reg[0x1] = 0b011;
         assert_eq!(chip.pc, 512);
         assert_eq!(chip.reg[0xF], 0);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.reg[0x1], 0b01);
         assert_eq!(chip.reg[0xF], 0x1);
@@ -758,7 +1263,7 @@ mod test {
         chip.reg[0xB] = 0x02;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.reg[0xA], 0x01);
         assert_eq!(chip.reg[0xB], 0x02);
@@ -766,13 +1271,30 @@ mod test {
         chip.reg[0xA] = 0x02;
         chip.reg[0xB] = 0x01;
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 516);
         assert_eq!(chip.reg[0xA], 0xFF);
         assert_eq!(chip.reg[0xB], 0x01);
         assert_eq!(chip.reg[0xF], 0x0);
     }
 
+    #[test]
+    fn arithmetic_sets_vf_last() {
+        // With X as F, the flag overwrites the result
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x8F, 0x04, 0x8F, 0x05, 0x8F, 0x07]);
+        chip.reg[0x0] = 0x01;
+        chip.reg[0xF] = 0xFF;
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.reg[0xF], 0x01);
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.reg[0xF], 0x01);
+        chip.reg[0xF] = 0x02;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.reg[0xF], 0x00);
+    }
+
     #[test]
     fn op_8x0e() {
         let mut chip = Chip8::default();
@@ -780,7 +1302,7 @@ mod test {
         chip.reg[0x1] = 0x81;
         assert_eq!(chip.pc, 512);
         assert_eq!(chip.reg[0xF], 0);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.reg[0x1], 0x81 << 1);
         assert_eq!(chip.reg[0xF], 0x1);
@@ -795,10 +1317,10 @@ mod test {
         chip.reg[0x3] = 0x82;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 518);
     }
 
@@ -808,7 +1330,7 @@ mod test {
         chip.load(&vec![0xA6, 0x66]);
         assert_eq!(chip.index, 0);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.index, 0x666);
         assert_eq!(chip.pc, 514);
     }
@@ -820,7 +1342,7 @@ mod test {
         chip.reg[0] = 0x5;
         assert_eq!(chip.index, 0);
         assert_eq!(chip.pc, 512);
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.index, 0);
         assert_eq!(chip.pc, 0x666 + 0x5);
     }
@@ -833,11 +1355,11 @@ mod test {
         chip.key[1] = 0;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         chip.key[1] = 1;
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 518);
     }
 
@@ -849,11 +1371,11 @@ mod test {
         chip.key[1] = 1;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         chip.key[1] = 0;
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 518);
     }
 
@@ -864,7 +1386,7 @@ mod test {
         chip.timer_delay = 10;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.reg[1], 10);
     }
@@ -877,11 +1399,11 @@ mod test {
         assert_eq!(chip.key[1], 0);
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        assert_eq!(chip.step_instruction(), Ok(CycleOutcome::WaitingForKey));
         assert_eq!(chip.pc, 512);
         chip.key[1] = 1;
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.reg[1], 1);
     }
@@ -893,9 +1415,9 @@ mod test {
         chip.reg[1] = 10;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
-        assert_eq!(chip.timer_delay, 9);
+        assert_eq!(chip.timer_delay, 10);
     }
 
     #[test]
@@ -905,9 +1427,9 @@ mod test {
         chip.reg[1] = 10;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
-        assert_eq!(chip.timer_sound, 9);
+        assert_eq!(chip.timer_sound, 10);
     }
 
     #[test]
@@ -918,7 +1440,7 @@ mod test {
         let init_index = chip.index;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.index, init_index + chip.reg[1] as u16);
         assert_eq!(chip.reg[1], 10);
@@ -931,7 +1453,7 @@ mod test {
         chip.reg[1] = 0xA;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.index, chip.reg[1] as u16 * 5);
         assert_eq!(chip.reg[1], 0xA);
@@ -946,7 +1468,7 @@ mod test {
         chip.reg[1] = 0xCD;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
         assert_eq!(chip.memory[10], 0xAB);
         assert_eq!(chip.memory[11], 0xCD);
@@ -961,9 +1483,457 @@ mod test {
         chip.index = 10;
         assert_eq!(chip.pc, 512);
 
-        chip.emulate_cycle();
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pc, 514);
+        assert_eq!(chip.reg[0], 0xAB);
+        assert_eq!(chip.reg[1], 0xCD);
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x00, 0x00]);
+        assert_eq!(chip.step_instruction(),
+                   Err(Chip8Error::InvalidOpcode { pc: 512, opcode: 0x0000 }));
+        assert_eq!(chip.pc, 512);
+    }
+
+    #[test]
+    fn stack_underflow() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x00, 0xEE]);
+        assert_eq!(chip.step_instruction(), Err(Chip8Error::StackUnderflow));
+        assert_eq!(chip.pc, 512);
+        assert_eq!(chip.sp, 0);
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x22, 0x00]);
+        for _ in 0..16 {
+            chip.step_instruction().unwrap();
+        }
+        assert_eq!(chip.step_instruction(), Err(Chip8Error::StackOverflow));
+        assert_eq!(chip.sp, 16);
+    }
+
+    #[test]
+    fn memory_out_of_bounds() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0xFF, 0x55]);
+        chip.index = 0xFFFA;
+        assert_eq!(chip.step_instruction(),
+                   Err(Chip8Error::MemoryOutOfBounds { address: 0x10000 }));
+        assert_eq!(chip.pc, 512);
+
+        chip.pc = 0xFFFF;
+        assert_eq!(chip.step_instruction(),
+                   Err(Chip8Error::MemoryOutOfBounds { address: 0x10000 }));
+    }
+
+    #[test]
+    fn pc_wraps_at_end_of_memory() {
+        // An instruction in the last two bytes runs, and the next one is at 0
+        let mut chip = Chip8::default();
+        chip.memory[0xFFFE] = 0x60;
+        chip.memory[0xFFFF] = 0x12;
+        chip.pc = 0xFFFE;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.reg[0], 0x12);
+        assert_eq!(chip.pc, 0);
+
+        // So does a long load in the last four, and a skip over one there
+        chip.memory[0xFFFC..].copy_from_slice(&[0xF0, 0x00, 0xAB, 0xCD]);
+        chip.pc = 0xFFFC;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.index, 0xABCD);
+        assert_eq!(chip.pc, 0);
+
+        chip.memory[0xFFFA..0xFFFC].copy_from_slice(&[0x30, 0x12]);
+        chip.pc = 0xFFFA;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pc, 0);
+
+        // And a return to a call made from the last two bytes
+        chip.memory[0x0300..0x0302].copy_from_slice(&[0x00, 0xEE]);
+        chip.stack[0] = 0xFFFE;
+        chip.sp = 1;
+        chip.pc = 0x0300;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pc, 0);
+    }
+
+    #[test]
+    fn load_store_index_wraps() {
+        let mut chip = Chip8::new(Quirks::cosmac_vip(), 0);
+        chip.load(&vec![0xF1, 0x55, 0xF1, 0x65]);
+        chip.index = 0xFFFE;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.index, 0);
+
+        chip.index = 0xFFFE;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.index, 0);
+    }
+
+    #[test]
+    fn quirk_shift_uses_vy() {
+        let mut chip = Chip8::new(Quirks::cosmac_vip(), 0);
+        chip.load(&vec![0x81, 0x26, 0x81, 0x2E]);
+        chip.reg[0x2] = 0b1000_0011;
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.reg[0x1], 0b0100_0001);
+        assert_eq!(chip.reg[0xF], 0x1);
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.reg[0x1], 0b0000_0110);
+        assert_eq!(chip.reg[0xF], 0x1);
+        assert_eq!(chip.reg[0x2], 0b1000_0011);
+    }
+
+    #[test]
+    fn quirk_load_store_increments_index() {
+        let mut chip = Chip8::new(Quirks::cosmac_vip(), 0);
+        chip.load(&vec![0xF1, 0x55, 0xF1, 0x65]);
+        chip.index = 10;
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.index, 12);
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.index, 14);
+    }
+
+    #[test]
+    fn quirk_jump_uses_vx() {
+        let mut chip = Chip8::new(Quirks::chip48(), 0);
+        chip.load(&vec![0xB6, 0x66]);
+        chip.reg[0] = 0x1;
+        chip.reg[6] = 0x5;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pc, 0x666 + 0x5);
+    }
+
+    #[test]
+    fn quirk_logic_resets_vf() {
+        let mut chip = Chip8::new(Quirks::cosmac_vip(), 0);
+        chip.load(&vec![0x8A, 0x21]);
+        chip.reg[0xF] = 0x1;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.reg[0xF], 0x0);
+    }
+
+    #[test]
+    fn quirk_wrap_sprites() {
+        let quirks = Quirks { wrap_sprites: true, ..Quirks::default() };
+        let mut chip = Chip8::new(quirks, 0);
+        chip.load(&vec![0xD0, 0x11, 0xD0, 0x11]);
+        chip.index = 0x300;
+        chip.memory[0x300] = 0xFF;
+        chip.reg[0] = 60;
+        chip.reg[1] = 31;
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.graphics[31 * 64 + 63], 1);
+        assert_eq!(chip.graphics[31 * 64], 1);
+        assert_eq!(chip.graphics[31 * 64 + 4], 0);
+
+        chip.reg[0] = 60 + 64;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.graphics[31 * 64 + 63], 0);
+        assert_eq!(chip.reg[0xF], 0x1);
+    }
+
+    #[test]
+    fn quirk_clip_sprites() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0xD0, 0x11]);
+        chip.index = 0x300;
+        chip.memory[0x300] = 0xFF;
+        chip.reg[0] = 60;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.graphics[63], 1);
+        assert_eq!(chip.graphics[0], 0);
+    }
+
+    #[test]
+    fn quirks_from_str() {
+        assert_eq!("vip".parse::<Quirks>(), Ok(Quirks::cosmac_vip()));
+        assert_eq!("default".parse::<Quirks>(), Ok(Quirks::default()));
+        assert!("pdp".parse::<Quirks>().is_err());
+    }
+
+    #[test]
+    fn op_00cn() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x00, 0xC2]);
+        chip.graphics[3] = 1;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pc, 514);
+        assert_eq!(chip.graphics[3], 0);
+        assert_eq!(chip.graphics[2 * 64 + 3], 1);
+    }
+
+    #[test]
+    fn op_00fb_00fc() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC]);
+        chip.graphics[2] = 1;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.graphics[2], 0);
+        assert_eq!(chip.graphics[6], 1);
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.graphics[6], 0);
+        assert_eq!(chip.graphics[2], 1);
+
+        chip.step_instruction().unwrap();
+        assert!(chip.graphics.iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn op_00fd() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x00, 0xFD]);
+        assert_eq!(chip.step_instruction(), Ok(CycleOutcome::Exit));
+        assert_eq!(chip.pc, 512);
+        assert_eq!(chip.step_instruction(), Ok(CycleOutcome::Exit));
+    }
+
+    #[test]
+    fn op_00fe_00ff() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x00, 0xFF, 0x00, 0xFE]);
+        assert_eq!(chip.width(), 64);
+        assert_eq!(chip.height(), 32);
+
+        chip.step_instruction().unwrap();
+        assert!(chip.hires());
+        assert_eq!(chip.graphics.len(), 128 * 64);
+
+        chip.step_instruction().unwrap();
+        assert!(!chip.hires());
+        assert_eq!(chip.graphics.len(), 64 * 32);
+    }
+
+    #[test]
+    fn op_dxy0() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x00, 0xFF, 0xD0, 0x10]);
+        chip.index = 0x300;
+        chip.memory[0x300] = 0x80;
+        chip.memory[0x301] = 0x01;
+        chip.memory[0x31F] = 0x01;
+        chip.reg[0] = 100;
+        chip.reg[1] = 40;
+        chip.step_instruction().unwrap();
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.graphics[40 * 128 + 100], 1);
+        assert_eq!(chip.graphics[40 * 128 + 115], 1);
+        assert_eq!(chip.graphics[55 * 128 + 115], 1);
+        assert_eq!(chip.graphics.iter().filter(|&&pixel| pixel != 0).count(), 3);
+    }
+
+    #[test]
+    fn op_fx30() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0xF1, 0x30]);
+        chip.reg[1] = 0x2;
+        chip.step_instruction().unwrap();
         assert_eq!(chip.pc, 514);
+        assert_eq!(chip.index as usize, BIG_FONT_START + 20);
+    }
+
+    #[test]
+    fn op_fx75_fx85() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0xF1, 0x75, 0xF1, 0x85]);
+        chip.reg[0] = 0xAB;
+        chip.reg[1] = 0xCD;
+        chip.step_instruction().unwrap();
+        chip.reg[0] = 0;
+        chip.reg[1] = 0;
+
+        chip.step_instruction().unwrap();
         assert_eq!(chip.reg[0], 0xAB);
         assert_eq!(chip.reg[1], 0xCD);
     }
+
+    #[test]
+    fn op_00dn() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x00, 0xD1]);
+        chip.graphics[64 + 3] = 1;
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pc, 514);
+        assert_eq!(chip.graphics[3], 1);
+        assert_eq!(chip.graphics[64 + 3], 0);
+    }
+
+    #[test]
+    fn op_5xy2_5xy3() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x51, 0x32, 0x53, 0x13]);
+        chip.index = 0x300;
+        chip.reg[1] = 0xA;
+        chip.reg[2] = 0xB;
+        chip.reg[3] = 0xC;
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pc, 514);
+        assert_eq!(chip.index, 0x300);
+        assert_eq!(&chip.memory[0x300..0x303], &[0xA, 0xB, 0xC]);
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.reg[3], 0xA);
+        assert_eq!(chip.reg[2], 0xB);
+        assert_eq!(chip.reg[1], 0xC);
+    }
+
+    #[test]
+    fn op_f000_nnnn() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0xF0, 0x00, 0xAB, 0xCD]);
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pc, 516);
+        assert_eq!(chip.index, 0xABCD);
+    }
+
+    #[test]
+    fn skip_long_load() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD]);
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pc, 518);
+    }
+
+    #[test]
+    fn op_fn01() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0xF3, 0x01, 0xD0, 0x01, 0xF2, 0x01, 0x00, 0xE0]);
+        chip.index = 0x300;
+        chip.memory[0x300] = 0x80;
+        chip.memory[0x301] = 0xC0;
+
+        chip.step_instruction().unwrap();
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.graphics[0], 0x3);
+        assert_eq!(chip.graphics[1], 0x2);
+
+        chip.step_instruction().unwrap();
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.graphics[0], 0x1);
+        assert_eq!(chip.graphics[1], 0x0);
+    }
+
+    #[test]
+    fn op_f002_fx3a() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0xF0, 0x02, 0xF1, 0x3A]);
+        chip.index = 0x300;
+        chip.memory[0x300] = 0xF0;
+        chip.reg[1] = 112;
+        assert_eq!(chip.audio_pattern(), None);
+        assert_eq!(chip.pattern_rate(), 4000.0);
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.audio_pattern().unwrap()[0], 0xF0);
+
+        chip.step_instruction().unwrap();
+        assert_eq!(chip.pitch(), 112);
+        assert_eq!(chip.pattern_rate(), 8000.0);
+    }
+
+    #[test]
+    fn tick_timers() {
+        let mut chip = Chip8::default();
+        chip.set_delay_timer(2);
+        chip.set_sound_timer(2);
+
+        chip.tick_timers();
+        assert_eq!(chip.timer_delay, 1);
+        assert_eq!(chip.timer_sound, 1);
+        assert!(chip.sound_active());
+        #[allow(deprecated)]
+        let expired = chip.make_sound();
+        assert!(!expired);
+
+        chip.tick_timers();
+        assert_eq!(chip.timer_delay, 0);
+        assert_eq!(chip.timer_sound, 0);
+        assert!(chip.sound_active());
+        #[allow(deprecated)]
+        let expired = chip.make_sound();
+        assert!(expired);
+
+        chip.tick_timers();
+        assert_eq!(chip.timer_delay, 0);
+        assert_eq!(chip.timer_sound, 0);
+        assert!(!chip.sound_active());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn emulate_cycle_ticks_timers() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x71, 0x01, 0x00, 0xEE]);
+        chip.timer_delay = 5;
+
+        assert_eq!(chip.emulate_cycle(), Ok(CycleOutcome::Continue));
+        assert_eq!(chip.reg[1], 1);
+        assert_eq!(chip.timer_delay, 4);
+
+        assert_eq!(chip.emulate_cycle(), Err(Chip8Error::StackUnderflow));
+        assert_eq!(chip.timer_delay, 4);
+    }
+
+    #[test]
+    fn run_frame() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x71, 0x01, 0x12, 0x00]);
+        chip.timer_delay = 5;
+
+        assert_eq!(chip.run_frame(10), Ok(CycleOutcome::Continue));
+        assert_eq!(chip.reg[1], 5);
+        assert_eq!(chip.timer_delay, 4);
+    }
+
+    #[test]
+    fn run_frame_waiting_for_key() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x71, 0x01, 0xF2, 0x0A]);
+        chip.timer_delay = 5;
+
+        assert_eq!(chip.run_frame(10), Ok(CycleOutcome::WaitingForKey));
+        assert_eq!(chip.reg[1], 1);
+        assert_eq!(chip.pc, 514);
+        assert_eq!(chip.timer_delay, 4);
+    }
+
+    #[test]
+    fn run_frame_exit() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x71, 0x01, 0x00, 0xFD]);
+        chip.timer_delay = 5;
+
+        assert_eq!(chip.run_frame(10), Ok(CycleOutcome::Exit));
+        assert_eq!(chip.reg[1], 1);
+        assert_eq!(chip.timer_delay, 5);
+    }
+
+    #[test]
+    fn op_cxnn() {
+        let mut a = Chip8::new(Quirks::default(), 1234);
+        let mut b = Chip8::new(Quirks::default(), 1234);
+        a.load(&vec![0xC1, 0x0F, 0x12, 0x00]);
+        b.load(&vec![0xC1, 0x0F, 0x12, 0x00]);
+        for _ in 0..20 {
+            a.step_instruction().unwrap();
+            b.step_instruction().unwrap();
+            assert_eq!(a.reg[1], b.reg[1]);
+            assert_eq!(a.reg[1] & 0xF0, 0);
+        }
+    }
 }
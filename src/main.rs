@@ -1,92 +1,998 @@
 extern crate chip8;
 extern crate clap;
-#[macro_use]
+#[cfg_attr(feature = "sdl", macro_use)]
 extern crate log;
 extern crate env_logger;
+extern crate rand;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 
+#[cfg(feature = "sdl")]
 mod audio;
+mod capture;
+mod debugger;
+#[cfg(feature = "sdl")]
+mod gamepad;
+#[cfg(feature = "sdl")]
 mod graphics;
+mod headless;
+#[cfg(feature = "sdl")]
 mod input;
 mod loader;
+mod rewind;
+mod session;
+#[cfg(unix)]
+mod terminal;
 
-use chip8::Chip8;
-use clap::{Arg, App};
-use std::time::Duration;
+use capture::{Capture, Recorder};
+use chip8::{Chip8, CycleOutcome, Quirks};
+use chip8::config::{self, Config};
+use chip8::frontend::{AudioSink, Emulator, VideoSink};
+use chip8::keymap::Keymap;
+use chip8::movie::{Movie, MovieError, Replay};
+use chip8::flicker::{self, Filter};
+use chip8::palette::{parse_color, Palette};
+use chip8::tone::{Sound, WAVEFORMS};
+use chip8::wav;
+use clap::{Arg, App, AppSettings, SubCommand};
+use session::Session;
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
-/// Adjust the scaling factor of the chip8's display.  The larger the number,
-/// the bigger the display.
-const SCALE : u32 = 8;
-
-/// The scaled width of the display.
-const WIDTH : u32 = chip8::WIDTH * SCALE;
-
-/// The scaled height of the display.
-const HEIGHT : u32 = chip8::HEIGHT * SCALE;
+/// The frontend used unless `--frontend` says otherwise.
+#[cfg(feature = "sdl")]
+const DEFAULT_FRONTEND : &str = "sdl";
+#[cfg(not(feature = "sdl"))]
+const DEFAULT_FRONTEND : &str = "terminal";
 
 fn main() {
     // Init the logger
     env_logger::init().unwrap();
 
     // Setup the commandline flags and usage/help message.
-    let matches = App::new("Chip8 Emulator")
+    let matches = app().get_matches();
+
+    if let Some(matches) = matches.subcommand_matches("disasm") {
+        let rom = loader::load_file(matches.value_of("ROM").unwrap());
+        print!("{}", chip8::disasm::listing(&rom, chip8::PROGRAM_START));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("asm") {
+        let input = Path::new(matches.value_of("INPUT").unwrap());
+        let output = matches.value_of("output")
+            .map_or(input.with_extension("ch8"), PathBuf::from);
+        let rom = match chip8::asm::assemble_file(input) {
+            Ok(rom) => rom,
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+        };
+        if let Err(error) = File::create(&output).and_then(|mut file| file.write_all(&rom)) {
+            eprintln!("Couldn't write {}: {}", output.display(), error);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let headless = matches.is_present("headless");
+    if !headless {
+        println!("Chip8 emulator starting...");
+    }
+
+    // Load the game, and the settings for it
+    let (rom, game) = match matches.subcommand_matches("run") {
+        Some(run) => {
+            let source = run.value_of("SOURCE").unwrap();
+            match chip8::octo::compile_file(Path::new(source)) {
+                Ok(game) => (source.to_string(), game),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                }
+            }
+        }
+        None => {
+            let rom = matches.value_of("ROM").unwrap();
+            (rom.to_string(), loader::load_file(rom))
+        }
+    };
+    let rom = rom.as_str();
+    let matches = apply_config(matches, &game);
+
+    // Initialize the emulator
+    let movie = matches.value_of("replay").map(|path| {
+        load_movie(path).unwrap_or_else(|e| {
+            eprintln!("Couldn't load movie {}: {}", path, e);
+            process::exit(1);
+        })
+    });
+    if let Some(ref movie) = movie {
+        if movie.version != chip8::version() {
+            eprintln!("The movie was recorded with version {}, so it may not replay exactly",
+                      movie.version);
+        }
+    }
+
+    let quirks = match movie {
+        Some(ref movie) => movie.quirks,
+        None => matches.value_of("quirks").map_or(Quirks::default(), |name| name.parse().unwrap()),
+    };
+    let seed = match (movie.as_ref(), matches.value_of("seed")) {
+        (Some(movie), _) => movie.seed,
+        (None, Some(seed)) => seed.parse::<u64>().unwrap_or_else(|_| {
+            eprintln!("--seed must be a number");
+            process::exit(1);
+        }),
+        (None, None) => rand::random(),
+    };
+    if headless {
+        eprintln!("Random seed: {}", seed);
+    } else {
+        println!("Random seed: {}", seed);
+    }
+    let mut chip = Chip8::new(quirks, seed);
+    chip.load(&game);
+    let ipf = match movie {
+        Some(ref movie) => movie.ipf as usize,
+        None => instructions_per_frame(&matches),
+    };
+    if let Some(ref movie) = movie {
+        if movie.rom_hash != chip.rom_hash() {
+            eprintln!("{}", MovieError::RomMismatch { expected: chip.rom_hash(), found: movie.rom_hash });
+            process::exit(1);
+        }
+    }
+    let replay = movie.map(Replay::new);
+    if replay.is_some() && movie_path(&matches).is_some() {
+        eprintln!("A replay can be recorded as a GIF, but not into another movie");
+        process::exit(1);
+    }
+    // A headless run can stop partway through a frame, which a replay
+    // couldn't reproduce
+    if headless && movie_path(&matches).is_some() {
+        eprintln!("Movies can only be recorded while playing, not with --headless");
+        process::exit(1);
+    }
+    // Movies keep the ipf in 16 bits
+    if movie_path(&matches).is_some() && ipf > u16::MAX as usize {
+        eprintln!("A movie can't be recorded with --ipf above {}", u16::MAX);
+        process::exit(1);
+    }
+
+    if headless {
+        match replay {
+            Some(replay) => replay_headless(&mut chip, ipf, replay, &matches),
+            None => run_headless(&mut chip, ipf, &matches),
+        }
+        return;
+    }
+
+    let rewind_seconds = matches.value_of("rewind").unwrap().parse::<usize>()
+        .unwrap_or_else(|_| {
+            eprintln!("--rewind must be a number of seconds");
+            process::exit(1);
+        });
+    let frontend = matches.value_of("frontend").unwrap();
+    let debug = matches.is_present("debug");
+    if frontend == "terminal" && debug {
+        eprintln!("The debugger needs the terminal, so it can't be used with --frontend terminal");
+        process::exit(1);
+    }
+    let keymap = load_keymap(matches.value_of("keymap"), &game).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let palette = load_palette(&matches).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let setup = Setup {
+        rom: rom.to_string(),
+        #[cfg(feature = "sdl")]
+        scale: window_scale(&matches),
+        keymap,
+        filter: Filter::new(flicker_mode(&matches), palette),
+        capture: capture_options(&matches),
+        gif: gif_path(&matches).map(str::to_string),
+        sound: load_sound(&matches),
+        mute: matches.is_present("mute"),
+        wav: matches.value_of("wav").map(str::to_string),
+    };
+    let mut session = Session::new(rom, rewind_seconds, debug, screenshot_scale(&matches));
+    if let Some(path) = movie_path(&matches) {
+        session.record(Movie::new(&chip, seed, ipf as u16), path);
+    }
+    if let Some(replay) = replay {
+        session.replay(replay, matches.is_present("verify"));
+    }
+    let session = match frontend {
+        "terminal" => {
+            play_terminal(chip, ipf, session, setup,
+                          matches.value_of("glyphs").unwrap(), matches.is_present("panel"))
+        }
+        _ => play_sdl(chip, ipf, session, setup),
+    };
+    if let Err(e) = session.finish() {
+        eprintln!("{}", e);
+        process::exit(3);
+    }
+}
+
+/// The command line flags and usage/help message.
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("Chip8 Emulator")
         .version(chip8::version())
         .author("Chris Konstad <chriskon149@gmail.com>")
         .about("Runs Chip8 games.")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("ROM")
              .help("Sets the path to the ROM to play")
              .required(true))
-        .get_matches();
+        .arg(Arg::with_name("quirks")
+             .long("quirks")
+             .takes_value(true)
+             .possible_values(&["default", "vip", "chip48", "schip"])
+             .help("Sets the interpreter whose quirks the game expects"))
+        .arg(Arg::with_name("keymap")
+             .long("keymap")
+             .takes_value(true)
+             .value_name("PRESET|FILE")
+             .help("Sets the keys for the hex keypad: cosmac (the default), azerty, hex or a TOML keymap file"))
+        .arg(Arg::with_name("ipf")
+             .long("ipf")
+             .takes_value(true)
+             .default_value("10")
+             .help("Sets how many instructions run each frame (60 frames a second)"))
+        .arg(Arg::with_name("rewind")
+             .long("rewind")
+             .takes_value(true)
+             .default_value("10")
+             .help("Sets how many seconds of history to keep for rewinding with Backspace"))
+        .arg(Arg::with_name("seed")
+             .long("seed")
+             .takes_value(true)
+             .help("Sets the seed for random numbers, to replay a session exactly"))
+        .arg(Arg::with_name("config")
+             .long("config")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Reads settings from FILE instead of ~/.config/chip8/config.toml"))
+        .arg(Arg::with_name("no-config")
+             .long("no-config")
+             .conflicts_with("config")
+             .help("Ignores the config file"))
+        .arg(Arg::with_name("debug")
+             .long("debug")
+             .help("Starts in the debugger, which takes commands on the terminal"))
+        .arg(Arg::with_name("scale")
+             .long("scale")
+             .takes_value(true)
+             .default_value("8")
+             .help("Sets how many times bigger than the display the SDL window is"))
+        .arg(Arg::with_name("theme")
+             .long("theme")
+             .takes_value(true)
+             .possible_values(&chip8::palette::THEMES)
+             .default_value("classic")
+             .help("Sets the colours to draw in (F8 switches theme while playing)"))
+        .arg(Arg::with_name("palette")
+             .long("palette")
+             .takes_value(true)
+             .value_name("COLOURS")
+             .use_delimiter(false)
+             .help("Sets all four colours as RRGGBB, separated by commas: background, plane 1, plane 2, both planes"))
+        .arg(Arg::with_name("fg")
+             .long("fg")
+             .takes_value(true)
+             .value_name("RRGGBB")
+             .help("Sets the colour of lit pixels"))
+        .arg(Arg::with_name("bg")
+             .long("bg")
+             .takes_value(true)
+             .value_name("RRGGBB")
+             .help("Sets the colour of the background"))
+        .arg(Arg::with_name("flicker")
+             .long("flicker")
+             .takes_value(true)
+             .value_name("MODE")
+             .default_value("vblank")
+             .help("Hides sprite flicker: vblank, blend[:FRAMES] to OR frames together, or fade[:KEEP] for phosphor decay"))
+        .arg(Arg::with_name("frontend")
+             .long("frontend")
+             .takes_value(true)
+             .possible_values(&["sdl", "terminal"])
+             .default_value(DEFAULT_FRONTEND)
+             .help("Sets where to play: an SDL window or this terminal"))
+        .arg(Arg::with_name("glyphs")
+             .long("glyphs")
+             .takes_value(true)
+             .possible_values(&["auto", "half", "braille"])
+             .default_value("auto")
+             .help("Sets the characters the terminal frontend draws pixels with"))
+        .arg(Arg::with_name("panel")
+             .long("panel")
+             .help("Shows the registers beside the display in the terminal frontend"))
+        .arg(Arg::with_name("tone")
+             .long("tone")
+             .takes_value(true)
+             .value_name("HZ")
+             .default_value("440")
+             .help("Sets the pitch of the beep"))
+        .arg(Arg::with_name("waveform")
+             .long("waveform")
+             .takes_value(true)
+             .possible_values(&WAVEFORMS)
+             .default_value("square")
+             .help("Sets the shape of the beep's wave"))
+        .arg(Arg::with_name("volume")
+             .long("volume")
+             .takes_value(true)
+             .default_value("0.25")
+             .help("Sets how loud the beep is, from 0 to 1"))
+        .arg(Arg::with_name("mute")
+             .long("mute")
+             .help("Plays no sound (--wav still records it)"))
+        .arg(Arg::with_name("wav")
+             .long("wav")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Records the sound into a WAV file, even with --headless"))
+        .arg(Arg::with_name("record")
+             .long("record")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Records the keys pressed on every frame into a movie file, or the display into an animated GIF if FILE ends in .gif (F11 toggles GIF recording while playing)"))
+        .arg(Arg::with_name("record-raw")
+             .long("record-raw")
+             .help("Also writes every recorded frame as raw RGB to a .rgb file, and the sound to a .wav file, beside the GIF"))
+        .arg(Arg::with_name("replay")
+             .long("replay")
+             .takes_value(true)
+             .value_name("MOVIE")
+             .help("Plays back a movie recorded with --record, using its seed, quirks and --ipf"))
+        .arg(Arg::with_name("verify")
+             .long("verify")
+             .requires("replay")
+             .help("Stops a replay with status 3 as soon as the display differs from the recording"))
+        .arg(Arg::with_name("headless")
+             .long("headless")
+             .conflicts_with("debug")
+             .help("Runs without a display or sound, then prints the final state"))
+        .arg(Arg::with_name("frames")
+             .long("frames")
+             .takes_value(true)
+             .default_value("600")
+             .help("Sets how many frames to run for with --headless"))
+        .arg(Arg::with_name("until")
+             .long("until")
+             .takes_value(true)
+             .value_name("CONDITION")
+             .help("Stops --headless early on exit, key (waiting for a key), pc=ADDRESS or vX=VALUE"))
+        .arg(Arg::with_name("keys")
+             .long("keys")
+             .takes_value(true)
+             .value_name("SCRIPT")
+             .use_delimiter(false)
+             .help("Presses keys during --headless, as KEY@FRAME or KEY@FRAME+FRAMES separated by commas"))
+        .arg(Arg::with_name("output")
+             .long("output")
+             .takes_value(true)
+             .help("Sets the file to write the final state to with --headless (stdout by default)"))
+        .arg(Arg::with_name("screenshot-at-frame")
+             .long("screenshot-at-frame")
+             .takes_value(true)
+             .number_of_values(2)
+             .value_names(&["FRAME", "PNG"])
+             .requires("headless")
+             .help("Saves a screenshot after a frame of --headless"))
+        .arg(Arg::with_name("screenshot-scale")
+             .long("screenshot-scale")
+             .takes_value(true)
+             .default_value("1")
+             .help("Sets how many times bigger than the display screenshots are, and than the high resolution display recordings are (F10 takes a screenshot while playing)"))
+        .subcommand(SubCommand::with_name("disasm")
+                    .about("Prints an assembly listing of a ROM")
+                    .arg(Arg::with_name("ROM")
+                         .help("Sets the path to the ROM to list")
+                         .required(true)))
+        .subcommand(SubCommand::with_name("asm")
+                    .about("Assembles source into a ROM")
+                    .arg(Arg::with_name("INPUT")
+                         .help("Sets the path to the source to assemble")
+                         .required(true))
+                    .arg(Arg::with_name("output")
+                         .short("o")
+                         .long("output")
+                         .takes_value(true)
+                         .help("Sets where to write the ROM (the input with a .ch8 extension by default)")))
+        .subcommand(SubCommand::with_name("run")
+                    .about("Compiles an Octo program and plays it")
+                    .arg(Arg::with_name("SOURCE")
+                         .help("Sets the path to the Octo source to play")
+                         .required(true)))
+}
+
+/// Adds the settings from the config file to the command line, for the
+/// ones it doesn't give, by parsing it again with them in front.  Exits if
+/// the config file can't be read or has bad settings.
+fn apply_config<'a>(matches: clap::ArgMatches<'a>, game: &[u8]) -> clap::ArgMatches<'a> {
+    if matches.is_present("no-config") {
+        return matches;
+    }
+    let path = match (matches.value_of("config"), config::default_path()) {
+        (Some(path), _) => PathBuf::from(path),
+        (None, Some(ref path)) if path.exists() => path.clone(),
+        (None, _) => return matches,
+    };
+    let mut text = String::new();
+    if let Err(e) = File::open(&path).and_then(|mut file| file.read_to_string(&mut text)) {
+        eprintln!("Couldn't read config {}: {}", path.display(), e);
+        process::exit(1);
+    }
+    let config = Config::parse(&text, &config::rom_key(game)).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path.display(), e);
+        process::exit(1);
+    });
+
+    let settings = config.args(|name| matches.occurrences_of(name) > 0);
+    if settings.is_empty() {
+        return matches;
+    }
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let program = if args.is_empty() { 0 } else { 1 };
+    args.splice(program..program, settings.into_iter().map(OsString::from));
+    app().get_matches_from_safe(args).unwrap_or_else(|e| {
+        eprintln!("Bad setting in {}: {}", path.display(), e.message);
+        process::exit(1);
+    })
+}
+
+/// Runs the game with no display for `--headless`, writing out the final
+/// state.  Exits with status 1 if the game faults and 2 if `--until` is
+/// never met.
+fn run_headless(chip: &mut Chip8, ipf: usize, matches: &clap::ArgMatches) {
+    let frames = matches.value_of("frames").unwrap().parse::<u64>()
+        .unwrap_or_else(|_| {
+            eprintln!("--frames must be a number");
+            process::exit(1);
+        });
+    let until = matches.value_of("until").map(|until| {
+        until.parse::<headless::Condition>().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    });
+    let script = headless::parse_script(matches.value_of("keys").unwrap_or(""))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+
+    let screenshot = screenshot_at(matches);
+    let mut taken = false;
+    let (mut capture, palette) = start_capture(matches);
+    let mut wav = matches.value_of("wav").map(|path| (path, start_wav(path, load_sound(matches))));
+    let mut after_frame = |chip: &Chip8, frame| {
+        if let Some((at, path)) = screenshot {
+            if frame == at {
+                save_screenshot(chip, path, matches);
+                taken = true;
+            }
+        }
+        if frame > 0 {
+            record_frame(capture.as_mut(), chip, palette);
+            record_sound(wav.as_mut().map(|wav| &mut wav.1), chip);
+        }
+    };
+    after_frame(chip, 0);
+    let result = headless::run(chip, ipf, frames, until.as_ref(), &script, &mut after_frame);
+    finish_capture(capture);
+    finish_wav(wav);
+    let ran = result.as_ref().map_or(0, |ending| ending.frames);
+    let written = match matches.value_of("output") {
+        Some(path) => File::create(path).and_then(|mut file| headless::dump(chip, ran, &mut file)),
+        None => headless::dump(chip, ran, &mut io::stdout()),
+    };
+    if let Err(e) = written {
+        eprintln!("Couldn't write the final state: {}", e);
+        process::exit(1);
+    }
+    match result {
+        Err(e) => {
+            eprintln!("Emulation stopped: {}", e);
+            process::exit(1);
+        }
+        Ok(ref ending) if until.is_some() && !ending.met => {
+            eprintln!("Stopped after {} frames without meeting --until", ending.frames);
+            process::exit(2);
+        }
+        Ok(_) => {}
+    }
+    if let Some((at, _)) = screenshot {
+        if !taken {
+            eprintln!("Stopped after {} frames, before the screenshot at frame {}", ran, at);
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads `--screenshot-at-frame`: the frame to take a screenshot after, and
+/// the file to save it to.
+fn screenshot_at<'a>(matches: &'a clap::ArgMatches) -> Option<(u64, &'a str)> {
+    matches.values_of("screenshot-at-frame").map(|mut values| {
+        let frame = values.next().unwrap().parse::<u64>().unwrap_or_else(|_| {
+            eprintln!("--screenshot-at-frame needs a frame number");
+            process::exit(1);
+        });
+        (frame, values.next().unwrap())
+    })
+}
+
+/// Reads `--ipf`.
+fn instructions_per_frame(matches: &clap::ArgMatches) -> usize {
+    match matches.value_of("ipf").unwrap().parse::<usize>() {
+        Ok(ipf) if ipf > 0 => ipf,
+        _ => {
+            eprintln!("--ipf must be a positive number");
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads `--scale`.
+#[cfg(feature = "sdl")]
+fn window_scale(matches: &clap::ArgMatches) -> u32 {
+    match matches.value_of("scale").unwrap().parse::<u32>() {
+        Ok(scale) if scale > 0 => scale,
+        _ => {
+            eprintln!("--scale must be a positive number");
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads `--screenshot-scale`.
+fn screenshot_scale(matches: &clap::ArgMatches) -> usize {
+    match matches.value_of("screenshot-scale").unwrap().parse::<usize>() {
+        Ok(scale) if scale > 0 => scale,
+        _ => {
+            eprintln!("--screenshot-scale must be a positive number");
+            process::exit(1);
+        }
+    }
+}
 
-    println!("Chip8 emulator starting...");
+/// Saves a screenshot of the chip's display in the colours given on the
+/// command line, exiting if it can't.
+fn save_screenshot(chip: &Chip8, path: &str, matches: &clap::ArgMatches) {
+    let palette = load_palette(matches).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let image = chip8::png::screenshot(chip, &palette, screenshot_scale(matches));
+    if let Err(e) = File::create(path).and_then(|mut file| file.write_all(&image)) {
+        eprintln!("Couldn't save screenshot to {}: {}", path, e);
+        process::exit(1);
+    }
+}
 
-    // Initialize the emulator and load the game
-    let mut chip = Chip8::default();
-    chip.load(&loader::load_file(matches.value_of("ROM").unwrap()));
+/// The path `--record` gives, if it's a GIF: GIFs record the display rather
+/// than a movie of the keys.
+fn gif_path<'a>(matches: &'a clap::ArgMatches) -> Option<&'a str> {
+    matches.value_of("record").filter(|path| path.to_lowercase().ends_with(".gif"))
+}
+
+/// The path `--record` gives, if it's a movie of the keys.
+fn movie_path<'a>(matches: &'a clap::ArgMatches) -> Option<&'a str> {
+    matches.value_of("record").filter(|_| gif_path(matches).is_none())
+}
+
+/// Reads `--flicker`, exiting if it's not a mode.
+fn flicker_mode(matches: &clap::ArgMatches) -> flicker::Mode {
+    matches.value_of("flicker").unwrap().parse().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+/// How recordings are made, from `--screenshot-scale`, `--flicker`,
+/// `--record-raw` and the sound options.
+fn capture_options(matches: &clap::ArgMatches) -> capture::Options {
+    capture::Options {
+        scale: screenshot_scale(matches),
+        flicker: flicker_mode(matches),
+        raw: matches.is_present("record-raw"),
+        sound: load_sound(matches),
+    }
+}
+
+/// Starts the GIF recording `--record` asks for without a display, if it
+/// asks for one, exiting if it can't.  It's recorded in the colours given
+/// on the command line, which are handed back too.
+fn start_capture(matches: &clap::ArgMatches) -> (Option<Capture>, Palette) {
+    let palette = load_palette(matches).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let capture = gif_path(matches).map(|path| {
+        Capture::new(path, capture_options(matches)).unwrap_or_else(|e| {
+            eprintln!("Couldn't record to {}: {}", path, e);
+            process::exit(1);
+        })
+    });
+    (capture, palette)
+}
 
+/// Records a frame of a recording started by `start_capture`, exiting if it
+/// can't.
+fn record_frame(capture: Option<&mut Capture>, chip: &Chip8, palette: Palette) {
+    if let Some(capture) = capture {
+        if let Err(e) = capture.frame(chip, palette) {
+            eprintln!("Couldn't record to {}: {}", capture.path(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Ends a recording started by `start_capture`, exiting if it can't be
+/// saved.
+fn finish_capture(capture: Option<Capture>) {
+    if let Some(capture) = capture {
+        let path = capture.path().to_string();
+        if let Err(e) = capture.finish() {
+            eprintln!("Couldn't save recording to {}: {}", path, e);
+            process::exit(1);
+        }
+        eprintln!("Saved recording to {}", path);
+    }
+}
+
+/// How the beep sounds, from `--tone`, `--waveform` and `--volume`,
+/// exiting if they don't make sense.
+fn load_sound(matches: &clap::ArgMatches) -> Sound {
+    let frequency = match matches.value_of("tone").unwrap().parse::<f32>() {
+        Ok(frequency) if frequency > 0.0 => frequency,
+        _ => {
+            eprintln!("--tone must be a positive number of Hz");
+            process::exit(1);
+        }
+    };
+    let volume = match matches.value_of("volume").unwrap().parse::<f32>() {
+        Ok(volume) if (0.0..=1.0).contains(&volume) => volume,
+        _ => {
+            eprintln!("--volume must be between 0 and 1");
+            process::exit(1);
+        }
+    };
+    Sound {
+        frequency,
+        waveform: matches.value_of("waveform").unwrap().parse().unwrap(),
+        volume,
+    }
+}
+
+/// Starts recording the sound to a WAV, exiting if it can't.
+fn start_wav(path: &str, sound: Sound) -> wav::Sink<BufWriter<File>> {
+    File::create(path)
+        .and_then(|file| wav::Sink::new(BufWriter::new(file), sound))
+        .unwrap_or_else(|e| {
+            eprintln!("Couldn't record sound to {}: {}", path, e);
+            process::exit(1);
+        })
+}
+
+/// Records a frame of the chip's sound, if it's being recorded.
+fn record_sound(sink: Option<&mut wav::Sink<BufWriter<File>>>, chip: &Chip8) {
+    if let Some(sink) = sink {
+        sink.set_pattern(chip.audio_pattern(), chip.pattern_rate());
+        sink.set_beep(chip.sound_active());
+    }
+}
+
+/// Ends a sound recording, saying where it was saved.
+/// # Errors
+/// Returns why the recording couldn't be saved.
+fn save_wav(path: &str, sink: wav::Sink<BufWriter<File>>) -> Result<String, String> {
+    sink.finish()
+        .and_then(|out| out.into_inner().map_err(|e| e.into_error()))
+        .map(|_| format!("Saved sound to {}", path))
+        .map_err(|e| format!("Couldn't save sound to {}: {}", path, e))
+}
+
+/// Ends a sound recording started by `start_wav` without a display, exiting
+/// if it can't be saved.
+fn finish_wav(wav: Option<(&str, wav::Sink<BufWriter<File>>)>) {
+    if let Some((path, sink)) = wav {
+        match save_wav(path, sink) {
+            Ok(saved) => eprintln!("{}", saved),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Reports how a sound recording made while playing ended.
+fn report_wav(path: Option<String>, sink: Option<wav::Sink<BufWriter<File>>>) {
+    if let (Some(path), Some(sink)) = (path, sink) {
+        match save_wav(&path, sink) {
+            Ok(saved) => println!("{}", saved),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+/// Reports how a recording made while playing ended.
+fn report_recording(saved: Result<Option<String>, String>) {
+    match saved {
+        Ok(Some(path)) => println!("Saved recording to {}", path),
+        Ok(None) => {}
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// Reads a movie file written by `--record`.
+fn load_movie(path: &str) -> Result<Movie, Box<dyn std::error::Error>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    Ok(Movie::load(&data)?)
+}
+
+/// Plays a movie back with no display for `--headless --replay`, writing
+/// out the final state.  Exits with status 1 if the game faults and 3 if
+/// `--verify` finds the replay diverging.
+fn replay_headless(chip: &mut Chip8, ipf: usize, mut replay: Replay, matches: &clap::ArgMatches) {
+    let verify = matches.is_present("verify");
+    let screenshot = screenshot_at(matches);
+    if let Some((0, path)) = screenshot {
+        save_screenshot(chip, path, matches);
+    }
+    let (mut capture, palette) = start_capture(matches);
+    let mut wav = matches.value_of("wav").map(|path| (path, start_wav(path, load_sound(matches))));
+    let mut result = Ok(());
+    while replay.press_keys(chip) {
+        match chip.run_frame(ipf) {
+            Ok(outcome) => {
+                let checked = replay.finish_frame(chip);
+                if let Some((at, path)) = screenshot {
+                    if replay.frame() as u64 == at {
+                        save_screenshot(chip, path, matches);
+                    }
+                }
+                record_frame(capture.as_mut(), chip, palette);
+                record_sound(wav.as_mut().map(|wav| &mut wav.1), chip);
+                if verify && checked.is_err() {
+                    result = checked.map_err(|e| (3, e.to_string()));
+                    break;
+                }
+                if outcome == CycleOutcome::Exit {
+                    break;
+                }
+            }
+            Err(e) => {
+                result = Err((1, format!("Emulation stopped: {}", e)));
+                break;
+            }
+        }
+    }
+
+    finish_capture(capture);
+    finish_wav(wav);
+    let frames = replay.frame() as u64;
+    let written = match matches.value_of("output") {
+        Some(path) => File::create(path).and_then(|mut file| headless::dump(chip, frames, &mut file)),
+        None => headless::dump(chip, frames, &mut io::stdout()),
+    };
+    if let Err(e) = written {
+        eprintln!("Couldn't write the final state: {}", e);
+        process::exit(1);
+    }
+    if let Err((status, message)) = result {
+        eprintln!("{}", message);
+        process::exit(status);
+    }
+    if let Some((at, _)) = screenshot {
+        if at > frames {
+            eprintln!("Stopped after {} frames, before the screenshot at frame {}", frames, at);
+            process::exit(1);
+        }
+    }
+}
+
+/// Loads the keymap given by `--keymap`, which names a preset or a keymap
+/// file, with the file's overrides for the game.
+fn load_keymap(keymap: Option<&str>, game: &[u8]) -> Result<Keymap, String> {
+    let keymap = match keymap {
+        Some(keymap) => keymap,
+        None => return Ok(Keymap::default()),
+    };
+    if let Ok(preset) = keymap.parse() {
+        return Ok(preset);
+    }
+    let mut text = String::new();
+    File::open(keymap)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| format!("Couldn't read keymap {}: {}", keymap, e))?;
+    Keymap::parse(&text, &config::rom_key(game)).map_err(|e| format!("{}: {}", keymap, e))
+}
+
+/// Builds the palette from `--theme`, then any colours given by
+/// `--palette`, `--fg` and `--bg`.
+fn load_palette(matches: &clap::ArgMatches) -> Result<Palette, String> {
+    let mut palette = match matches.value_of("palette") {
+        Some(colors) => Palette::parse_colors(colors)?,
+        None => matches.value_of("theme").unwrap().parse()?,
+    };
+    if let Some(color) = matches.value_of("fg") {
+        palette.colors[1] = parse_color(color)?;
+    }
+    if let Some(color) = matches.value_of("bg") {
+        palette.colors[0] = parse_color(color)?;
+    }
+    Ok(palette)
+}
+
+/// How the player has asked for the game to be played, whichever frontend
+/// it's played in.
+struct Setup {
+    rom: String,
+    /// How many times bigger than the display the SDL window is
+    #[cfg(feature = "sdl")]
+    scale: u32,
+    keymap: Keymap,
+    filter: Filter,
+    capture: capture::Options,
+    /// The GIF to start recording to straight away
+    gif: Option<String>,
+    sound: Sound,
+    /// Whether to play no sound
+    mute: bool,
+    /// The WAV to record the sound to
+    wav: Option<String>,
+}
+
+/// Reports a fault that stopped the game.
+fn report_fault(chip: &Chip8, fault: chip8::Chip8Error) {
+    eprintln!("Emulation stopped: {}", fault);
+    eprintln!("{:?}", chip);
+}
+
+/// Without SDL there's no window to play in.
+#[cfg(not(feature = "sdl"))]
+fn play_sdl(_chip: Chip8, _ipf: usize, _session: Session, _setup: Setup) -> Session {
+    eprintln!("This build has no SDL support, so try --frontend terminal or --headless");
+    process::exit(1);
+}
+
+/// Plays the game in an SDL window until it exits or the window is closed,
+/// then hands the session back.
+#[cfg(feature = "sdl")]
+fn play_sdl(chip: Chip8, ipf: usize, mut session: Session, setup: Setup) -> Session {
     // Prepare SDL for video, audio, and input
     let sdl_context = sdl2::init().unwrap();
-    let mut beeper = audio::Beeper::new(&sdl_context,
-                                        Duration::from_millis(250));
-    let mut keyboard = input::Keyboard::new(&sdl_context);
-
-    // TODO TEST AT 60frames a second!
-    // This isn't set to 60Hz because that was too slow
-    let mut window = graphics::Display::new(&sdl_context,
-                                            "Chip8 Emulator",
-                                            WIDTH,
-                                            HEIGHT,
-                                            Duration::from_millis(2));
-
-    // Emulation loop
-    'running: loop {
-        // Check the input and store it on the chip
-        match keyboard.check(&mut chip.key) {
-            input::Command::Quit => break 'running,
-            input::Command::Continue => {}
-        }
-
-        // Run a cycle on the chip
-        chip.emulate_cycle();
-
-        // Render the frame if needed
-        if chip.draw_flag {
-            debug!("{:?}", chip);
-            chip.draw_flag = false;
-            let mut graphics_as_bytes : [u8; 2048] = [0; 2048];
-            chip.graphics
-                .iter()
-                .map(|&bit|
-                    if bit { 1u8 } else { 0u8 }
-                )
-                .enumerate()
-                .for_each(|(i, val)|{
-                    graphics_as_bytes[i] = val;
-                });
-            window.draw_frame(&graphics_as_bytes);
-        }
-
-        // Make sound if needed
-        beeper.set_beep(chip.make_sound);
+    let beeper = if setup.mute {
+        None
+    } else {
+        Some(audio::Beeper::new(&sdl_context, setup.sound))
+    };
+    let wav = setup.wav.as_ref().map(|path| start_wav(path, setup.sound));
+    let keyboard = input::Keyboard::new(&sdl_context, &setup.keymap);
+    let gamepad = gamepad::Gamepad::new(&sdl_context, &setup.keymap);
+    let window = graphics::Display::new(&sdl_context,
+                                        "Chip8 Emulator",
+                                        chip8::WIDTH * setup.scale,
+                                        chip8::HEIGHT * setup.scale,
+                                        setup.filter);
+    let window = Recorder::new(window, &setup.rom, setup.capture, setup.gif.as_deref())
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+
+    let mut emulator = Emulator::new(chip, ipf, window, (beeper, wav), (keyboard, gamepad));
+    if let Err(fault) = emulator.run(&mut session) {
+        report_fault(&emulator.chip, fault);
+    }
+    report_recording(emulator.video.finish());
+    report_wav(setup.wav, emulator.audio.1);
+    session
+}
+
+/// Without a Unix terminal there's no raw mode to read keys in.
+#[cfg(not(unix))]
+fn play_terminal(_chip: Chip8, _ipf: usize, _session: Session, _setup: Setup,
+                 _glyphs: &str, _panel: bool) -> Session {
+    eprintln!("The terminal frontend needs a Unix terminal");
+    process::exit(1);
+}
+
+/// Plays the game in this terminal until it exits or the player quits, then
+/// hands the session back.
+#[cfg(unix)]
+fn play_terminal(chip: Chip8, ipf: usize, mut session: Session, setup: Setup,
+                 glyphs: &str, panel: bool) -> Session {
+    let Setup { rom, keymap, filter, capture, gif, sound, mute, wav, .. } = setup;
+    let wav_sink = wav.as_ref().map(|path| start_wav(path, sound));
+    let glyphs = glyphs.parse().unwrap();
+    let frontend = terminal::Keys::new(keymap)
+        .and_then(|keys| Ok((terminal::Screen::new(glyphs, filter, panel)?, keys)));
+    let (screen, keys) = match frontend {
+        Ok(frontend) => frontend,
+        Err(e) => {
+            eprintln!("Couldn't set up the terminal: {}", e);
+            process::exit(1);
+        }
+    };
+    // A failed recorder drops the screen, so the keys go too
+    let screen = match Recorder::new(screen, &rom, capture, gif.as_deref()) {
+        Ok(screen) => screen,
+        Err(e) => {
+            drop(keys);
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let bell = if mute { None } else { Some(terminal::Bell::default()) };
+    let mut emulator = Emulator::new(chip, ipf, screen, (bell, wav_sink), keys);
+    let result = emulator.run(&mut session);
+    let failure = emulator.video.failure();
+    let saved = emulator.video.finish();
+    // Put the terminal back before saying what went wrong
+    drop(emulator.video);
+    drop(emulator.input);
+    if let Err(fault) = result {
+        report_fault(&emulator.chip, fault);
+    }
+    if let Some(failure) = failure {
+        eprintln!("Emulation stopped: {}", failure);
+    }
+    report_recording(saved);
+    report_wav(wav, emulator.audio.1);
+    session
+}
+
+#[cfg(test)]
+mod test {
+    use super::{app, load_palette};
+    use chip8::config::Config;
+    use chip8::palette::Palette;
+
+    /// Parses the command line with a config's settings in front, as
+    /// `apply_config` does.
+    fn with_config(config: &str, given: &[&str]) -> clap::ArgMatches<'static> {
+        let matches = app().get_matches_from_safe(given).unwrap();
+        let config = Config::parse(config, "").unwrap();
+        let mut args: Vec<String> = given.iter().map(|arg| arg.to_string()).collect();
+        args.splice(1..1, config.args(|name| matches.occurrences_of(name) > 0));
+        app().get_matches_from_safe(args).unwrap()
+    }
+
+    #[test]
+    fn commas_stay_in_values() {
+        let matches = app().get_matches_from_safe(["chip8", "--headless", "--keys", "1@2,2@10",
+                                                    "--palette", "000000,ffffff,ff0000,00ff00",
+                                                    "game.ch8"]).unwrap();
+        assert_eq!(matches.value_of("keys"), Some("1@2,2@10"));
+        assert_eq!(matches.value_of("palette"), Some("000000,ffffff,ff0000,00ff00"));
+    }
+
+    #[test]
+    fn config_palettes_load() {
+        let config = "palette = \"000000,ffffff,ff0000,00ff00\"\nfg = \"#00ffff\"";
+        let palette = load_palette(&with_config(config, &["chip8", "game.ch8"])).unwrap();
+        assert_eq!(palette.colors, [[0, 0, 0], [0, 255, 255], [255, 0, 0], [0, 255, 0]]);
+
+        // A theme on the command line replaces the config's palette
+        let matches = with_config(config, &["chip8", "--theme", "amber", "game.ch8"]);
+        let mut amber = "amber".parse::<Palette>().unwrap();
+        amber.colors[1] = [0, 255, 255];
+        assert_eq!(load_palette(&matches).unwrap(), amber);
     }
 }
@@ -0,0 +1,323 @@
+//! Movies: the keys held on every frame of a game, so that it can be played
+//! back exactly as it was played.
+//!
+//! A chip's random numbers come from its seed, so a chip with the same ROM,
+//! seed, quirks and instructions per frame, given the same keys, always does
+//! the same thing.  A movie records all of those, along with a checksum of
+//! the display after every frame so that a replay can tell when it stops
+//! matching.  It's stored big-endian:
+//!
+//! ```text
+//! magic "C8MV" | version u16 | emulator version (u8 length, then UTF-8)
+//! ROM CRC-32 u32 | seed u64 | quirks u8 | instructions per frame u16
+//! frame count u32 | frames: keys u16 (bit N for key N), display CRC-32 u32
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+use checksum::crc32;
+use state::{put_u16, put_u32, put_u64, Reader, StateError};
+use {version, Chip8, Quirks};
+
+/// Marks the start of every movie.
+const MAGIC: &[u8; 4] = b"C8MV";
+
+/// The version of the format written by `Movie::save`.
+pub const MOVIE_VERSION: u16 = 1;
+
+/// The reasons a movie can't be loaded or played back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data isn't a movie at all.
+    BadMagic,
+    /// The movie was saved in a format this version can't read.
+    UnsupportedVersion(u16),
+    /// The movie ends early, has data left over, or has values that could
+    /// never have been recorded.
+    Corrupt,
+    /// The movie was recorded while playing a different ROM.
+    RomMismatch { expected: u32, found: u32 },
+    /// The display after a frame isn't what it was when the movie was
+    /// recorded.
+    Diverged { frame: usize, expected: u32, found: u32 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::BadMagic =>
+                write!(f, "Not a movie"),
+            MovieError::UnsupportedVersion(version) =>
+                write!(f, "Unsupported movie version {}", version),
+            MovieError::Corrupt =>
+                write!(f, "Movie is corrupt"),
+            MovieError::RomMismatch { expected, found } =>
+                write!(f, "Movie is for ROM {:08X}, not {:08X}", found, expected),
+            MovieError::Diverged { frame, expected, found } =>
+                write!(f, "Replay diverged at frame {}: display checksum {:08X}, recorded {:08X}",
+                       frame, found, expected),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(_: StateError) -> Self {
+        MovieError::Corrupt
+    }
+}
+
+/// The checksum of the chip's display kept for each frame.
+pub fn display_checksum(chip: &Chip8) -> u32 {
+    crc32(&chip.graphics)
+}
+
+/// Packs quirks into a byte, a bit for each.
+fn quirk_bits(quirks: Quirks) -> u8 {
+    quirks.shift_uses_vy as u8 |
+        (quirks.load_store_increments_index as u8) << 1 |
+        (quirks.jump_uses_vx as u8) << 2 |
+        (quirks.logic_resets_vf as u8) << 3 |
+        (quirks.wrap_sprites as u8) << 4
+}
+
+/// Unpacks quirks packed by `quirk_bits`.
+fn quirks_from_bits(bits: u8) -> Quirks {
+    Quirks {
+        shift_uses_vy: bits & 0x01 != 0,
+        load_store_increments_index: bits & 0x02 != 0,
+        jump_uses_vx: bits & 0x04 != 0,
+        logic_resets_vf: bits & 0x08 != 0,
+        wrap_sprites: bits & 0x10 != 0,
+    }
+}
+
+/// One recorded frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// The keys held during the frame, bit N for key N
+    pub keys: u16,
+    /// The checksum of the display after the frame
+    pub checksum: u32,
+}
+
+/// A recording of a game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    /// The version of the emulator that recorded the movie
+    pub version: String,
+    pub rom_hash: u32,
+    pub seed: u64,
+    pub quirks: Quirks,
+    /// The instructions run each frame
+    pub ipf: u16,
+    pub frames: Vec<Frame>,
+}
+
+impl Movie {
+    /// Starts a movie of the game loaded on the chip, which was constructed
+    /// with the given seed and runs `ipf` instructions a frame.
+    pub fn new(chip: &Chip8, seed: u64, ipf: u16) -> Self {
+        Movie {
+            version: version().to_string(),
+            rom_hash: chip.rom_hash(),
+            seed,
+            quirks: chip.quirks(),
+            ipf,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Records a frame that has just run on the chip.
+    pub fn record(&mut self, chip: &Chip8) {
+        let keys = chip.key.iter().enumerate()
+            .filter(|&(_, &state)| state != 0)
+            .fold(0, |keys, (key, _)| keys | 1 << key);
+        self.frames.push(Frame { keys, checksum: display_checksum(chip) });
+    }
+
+    /// Constructs a chip ready to play the movie back, with its ROM loaded.
+    /// # Errors
+    /// Fails if the ROM isn't the one the movie was recorded with.
+    pub fn chip(&self, rom: &[u8]) -> Result<Chip8, MovieError> {
+        let mut chip = Chip8::new(self.quirks, self.seed);
+        chip.load(rom);
+        if chip.rom_hash() != self.rom_hash {
+            return Err(MovieError::RomMismatch { expected: chip.rom_hash(), found: self.rom_hash });
+        }
+        Ok(chip)
+    }
+
+    /// Writes the movie out as bytes.
+    pub fn save(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64 + self.frames.len() * 6);
+        out.extend_from_slice(MAGIC);
+        put_u16(&mut out, MOVIE_VERSION);
+        let version = &self.version.as_bytes()[..self.version.len().min(255)];
+        out.push(version.len() as u8);
+        out.extend_from_slice(version);
+        put_u32(&mut out, self.rom_hash);
+        put_u64(&mut out, self.seed);
+        out.push(quirk_bits(self.quirks));
+        put_u16(&mut out, self.ipf);
+        put_u32(&mut out, self.frames.len() as u32);
+        for frame in &self.frames {
+            put_u16(&mut out, frame.keys);
+            put_u32(&mut out, frame.checksum);
+        }
+        out
+    }
+
+    /// Reads a movie written by `save`.
+    pub fn load(data: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = Reader { data };
+        if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(MovieError::BadMagic);
+        }
+        let format = reader.u16()?;
+        if format != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(format));
+        }
+        let length = reader.u8()? as usize;
+        let version = String::from_utf8(reader.bytes(length)?.to_vec())
+            .map_err(|_| MovieError::Corrupt)?;
+        let rom_hash = reader.u32()?;
+        let seed = reader.u64()?;
+        let bits = reader.u8()?;
+        if bits >= 0x20 {
+            return Err(MovieError::Corrupt);
+        }
+        let ipf = reader.u16()?;
+        let count = reader.u32()? as usize;
+        if reader.data.len() != count * 6 {
+            return Err(MovieError::Corrupt);
+        }
+        let mut frames = Vec::with_capacity(count);
+        for _ in 0..count {
+            frames.push(Frame { keys: reader.u16()?, checksum: reader.u32()? });
+        }
+        Ok(Movie { version, rom_hash, seed, quirks: quirks_from_bits(bits), ipf, frames })
+    }
+}
+
+/// Plays a movie back into a chip a frame at a time.
+pub struct Replay {
+    movie: Movie,
+    frame: usize,
+}
+
+impl Replay {
+    pub fn new(movie: Movie) -> Self {
+        Replay { movie, frame: 0 }
+    }
+
+    /// The movie being played.
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// The number of frames played so far.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Holds down the keys recorded for the next frame.  Returns false once
+    /// the movie is over.
+    pub fn press_keys(&self, chip: &mut Chip8) -> bool {
+        let frame = match self.movie.frames.get(self.frame) {
+            Some(frame) => frame,
+            None => return false,
+        };
+        for (key, state) in chip.key.iter_mut().enumerate() {
+            *state = (frame.keys >> key & 1) as u8;
+        }
+        true
+    }
+
+    /// Moves on to the next frame once the chip has run this one, checking
+    /// that the display is the same as it was when the movie was recorded.
+    /// # Errors
+    /// Returns `MovieError::Diverged` if the display is different.
+    pub fn finish_frame(&mut self, chip: &Chip8) -> Result<(), MovieError> {
+        let expected = self.movie.frames[self.frame].checksum;
+        self.frame += 1;
+        let found = display_checksum(chip);
+        if found != expected {
+            return Err(MovieError::Diverged { frame: self.frame, expected, found });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Movie, MovieError, Replay};
+    use {Chip8, Quirks};
+
+    /// Draws a random byte as a sprite, moved over while key 1 is held,
+    /// forever.
+    const GAME: [u8; 18] = [0xC0, 0xFF, 0xA3, 0x00, 0xF0, 0x55, 0x61, 0x01, 0xE1, 0xA1,
+                            0x71, 0x08, 0xA3, 0x00, 0xD1, 0x11, 0x12, 0x00];
+
+    fn record(frames: usize) -> (Movie, Vec<u8>) {
+        let mut chip = Chip8::new(Quirks::cosmac_vip(), 1234);
+        let game = GAME.to_vec();
+        chip.load(&game);
+        let mut movie = Movie::new(&chip, 1234, 20);
+        for frame in 0..frames {
+            chip.key = [0; 16];
+            chip.key[1] = (frame % 3 == 0) as u8;
+            chip.run_frame(20).unwrap();
+            movie.record(&chip);
+        }
+        (movie, game)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (movie, _) = record(30);
+        assert_eq!(movie.frames.len(), 30);
+        assert_eq!(movie.frames[0].keys, 0x0002);
+        assert_eq!(movie.frames[1].keys, 0);
+        assert_eq!(Movie::load(&movie.save()), Ok(movie.clone()));
+
+        let mut data = movie.save();
+        data.pop();
+        assert_eq!(Movie::load(&data), Err(MovieError::Corrupt));
+        data[0] = b'X';
+        assert_eq!(Movie::load(&data), Err(MovieError::BadMagic));
+    }
+
+    #[test]
+    fn replays_exactly() {
+        let (movie, game) = record(30);
+        let mut chip = movie.chip(&game).unwrap();
+        let mut replay = Replay::new(movie);
+        while replay.press_keys(&mut chip) {
+            chip.run_frame(replay.movie().ipf as usize).unwrap();
+            replay.finish_frame(&chip).unwrap();
+        }
+        assert_eq!(replay.frame(), 30);
+
+        assert!(replay.movie().chip(&[0x12, 0x00]).is_err());
+    }
+
+    #[test]
+    fn detects_divergence() {
+        let (mut movie, game) = record(10);
+        movie.frames[4].keys ^= 0x0002;
+        let mut chip = movie.chip(&game).unwrap();
+        let mut replay = Replay::new(movie);
+        let mut result = Ok(());
+        while result.is_ok() && replay.press_keys(&mut chip) {
+            chip.run_frame(20).unwrap();
+            result = replay.finish_frame(&chip);
+        }
+        match result {
+            Err(MovieError::Diverged { frame: 5, .. }) => {}
+            other => panic!("expected divergence at frame 5, got {:?}", other),
+        }
+    }
+}
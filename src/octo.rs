@@ -0,0 +1,907 @@
+//! A compiler for Octo, the high level assembly language that most modern
+//! CHIP-8, SUPER-CHIP and XO-CHIP games are written in.
+//!
+//! This covers the language described in the Octo manual: labels (`: name`),
+//! `:const`, `:alias`, `:org`, `:byte`, `:call`, `:macro` and `:calc`, every
+//! instruction statement, `if ... then`, `if ... begin ... else ... end`,
+//! `loop ... while ... again`, and bare numbers for sprite data.  Like Octo,
+//! `:calc` expressions have no operator precedence and are worked out right
+//! to left, but only in whole numbers.
+//!
+//! The program starts at its `main` label.  Unless `main` labels the first
+//! instruction, the ROM starts with a jump to it.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use asm::AsmError;
+use super::PROGRAM_START;
+
+/// The most macros that may be expanded, which stops a macro that uses
+/// itself from expanding forever.
+const MAX_EXPANSIONS: usize = 10_000;
+
+/// A word of source and the line it's on.
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// A macro's argument names and the tokens they're substituted into.
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A structured statement that hasn't been closed yet.
+enum Block {
+    /// `if ... begin`, with the jump past its body to fill in
+    Begin(usize),
+    /// `else`, with the jump past its body to fill in
+    Else(usize),
+    /// `loop`, with the address to loop back to and the jumps out of the
+    /// loop made by `while`
+    Loop(usize, Vec<usize>),
+}
+
+/// A reference to a label that hadn't been defined yet.
+struct Fixup {
+    address: usize,
+    long: bool,
+    label: Token,
+}
+
+struct Compiler {
+    /// The tokens still to compile, in reverse order
+    tokens: Vec<Token>,
+    rom: Vec<u8>,
+    here: usize,
+    line: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    /// Whether anything has been emitted yet
+    started: bool,
+    fixups: Vec<Fixup>,
+    blocks: Vec<(Block, usize)>,
+}
+
+/// Compiles Octo source into a ROM image to be loaded at `PROGRAM_START`.
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError> {
+    Compiler::new(source).compile().map_err(|(line, message)| {
+        AsmError { file: "<input>".to_string(), line, message }
+    })
+}
+
+/// Compiles the Octo source in a file into a ROM image to be loaded at
+/// `PROGRAM_START`.
+pub fn compile_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let name = path.display().to_string();
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(|err| AsmError {
+            file: name.clone(),
+            line: 0,
+            message: format!("Couldn't read {}: {}", name, err),
+        })?;
+    compile(&source).map_err(|error| AsmError { file: name, ..error })
+}
+
+/// Splits source into tokens, dropping `#` comments.
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (number, text) in source.lines().enumerate() {
+        let code = text.split('#').next().unwrap_or("");
+        for word in code.split_whitespace() {
+            tokens.push(Token { text: word.to_string(), line: number + 1 });
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = if text.starts_with('-') && text.len() > 1 {
+        (true, &text[1..])
+    } else {
+        (false, text)
+    };
+    let value = if digits.starts_with("0x") || digits.starts_with("0X") {
+        i64::from_str_radix(&digits[2..], 16).ok()
+    } else if digits.starts_with("0b") || digits.starts_with("0B") {
+        i64::from_str_radix(&digits[2..], 2).ok()
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()
+    } else {
+        None
+    };
+    value.map(|value| if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    if text.len() == 2 && (text.starts_with('v') || text.starts_with('V')) {
+        u8::from_str_radix(&text[1..], 16).ok()
+    } else {
+        None
+    }
+}
+
+/// Flips a skip instruction so that it skips when the original wouldn't.
+fn invert_skip(opcode: u16) -> u16 {
+    match opcode & 0xF000 {
+        0x3000 => opcode ^ 0x7000,
+        0x4000 => opcode ^ 0x7000,
+        0x5000 => opcode ^ 0xC000,
+        0x9000 => opcode ^ 0xC000,
+        _ => opcode ^ (0x9E ^ 0xA1),
+    }
+}
+
+impl Compiler {
+    fn new(source: &str) -> Self {
+        let mut tokens = tokenize(source);
+        tokens.reverse();
+        Compiler {
+            tokens,
+            rom: Vec::new(),
+            here: PROGRAM_START as usize,
+            line: 1,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            started: false,
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn compile(mut self) -> Result<Vec<u8>, (usize, String)> {
+        while !self.tokens.is_empty() {
+            self.statement().map_err(|message| (self.line, message))?;
+        }
+
+        if let Some(&(ref block, line)) = self.blocks.last() {
+            let message = match *block {
+                Block::Loop(..) => "loop without again",
+                _ => "begin without end",
+            };
+            return Err((line, message.to_string()));
+        }
+        if !self.labels.contains_key("main") {
+            return Err((self.line, "This program has no main label".to_string()));
+        }
+        for fixup in &self.fixups {
+            let address = match self.labels.get(&fixup.label.text) {
+                Some(&address) => address,
+                None => return Err((fixup.label.line,
+                                    format!("Undefined label: {}", fixup.label.text))),
+            };
+            let index = fixup.address - PROGRAM_START as usize;
+            if fixup.long {
+                self.rom[index] = (address >> 8) as u8;
+                self.rom[index + 1] = address as u8;
+            } else if address > 0xFFF {
+                return Err((fixup.label.line,
+                            format!("{} at {:#X} is out of reach", fixup.label.text, address)));
+            } else {
+                self.rom[index] |= (address >> 8) as u8;
+                self.rom[index + 1] = address as u8;
+            }
+        }
+        Ok(self.rom)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        match self.tokens.pop() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token)
+            }
+            None => Err("Unexpected end of file".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(format!("Expected {}, found {}", text, token.text));
+        }
+        Ok(())
+    }
+
+    /// Jumps to `main` before the first instruction, unless that's `main`.
+    fn start(&mut self) -> Result<(), String> {
+        if !self.started {
+            self.started = true;
+            if self.labels.get("main") != Some(&self.here) {
+                let main = Token { text: "main".to_string(), line: self.line };
+                self.emit_address(0x1000, &main)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), String> {
+        self.start()?;
+        let index = self.here - PROGRAM_START as usize;
+        if self.here >= 0x10000 {
+            return Err("Program doesn't fit in memory".to_string());
+        }
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16) -> Result<(), String> {
+        self.emit_byte((opcode >> 8) as u8)?;
+        self.emit_byte(opcode as u8)
+    }
+
+    /// Emits an instruction with a 12 bit address, which may be a label
+    /// that's defined further on.
+    fn emit_address(&mut self, opcode: u16, target: &Token) -> Result<(), String> {
+        if let Some(address) = self.known_value(&target.text) {
+            if !(0..=0xFFF).contains(&address) {
+                return Err(format!("{} is out of reach", target.text));
+            }
+            return self.emit(opcode | address as u16);
+        }
+        if !target.text.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return Err(format!("Bad address: {}", target.text));
+        }
+        self.fixups.push(Fixup { address: self.here, long: false, label: target.clone() });
+        self.emit(opcode)
+    }
+
+    /// Fills in the address of a jump emitted earlier.
+    fn patch(&mut self, address: usize, target: usize) -> Result<(), String> {
+        if target > 0xFFF {
+            return Err(format!("Jump to {:#X} is out of reach", target));
+        }
+        let index = address - PROGRAM_START as usize;
+        self.rom[index] = self.rom[index] & 0xF0 | (target >> 8) as u8;
+        self.rom[index + 1] = target as u8;
+        Ok(())
+    }
+
+    /// Emits a jump to be filled in later, returning its address.
+    fn emit_placeholder(&mut self) -> Result<usize, String> {
+        let address = self.here;
+        self.emit(0x1000)?;
+        Ok(address)
+    }
+
+    /// The value of a number, constant or label that's already defined.
+    fn known_value(&self, text: &str) -> Option<i64> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).cloned())
+            .or_else(|| self.labels.get(text).map(|&address| address as i64))
+    }
+
+    fn value(&mut self) -> Result<i64, String> {
+        let token = self.next()?;
+        self.known_value(&token.text).ok_or_else(|| format!("Unknown value: {}", token.text))
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let value = if self.peek() == Some("{") { self.calc()? } else { self.value()? };
+        if !(-128..=255).contains(&value) {
+            return Err(format!("{} doesn't fit in a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        let value = self.value()?;
+        if !(0..=15).contains(&value) {
+            return Err(format!("{} doesn't fit in a nibble", value));
+        }
+        Ok(value as u16)
+    }
+
+    fn register_named(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).cloned())
+    }
+
+    fn register(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+        self.register_named(&token.text)
+            .map(|x| x as u16)
+            .ok_or_else(|| format!("Expected a register, found {}", token.text))
+    }
+
+    fn define_label(&mut self, name: Token) -> Result<(), String> {
+        if self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            return Err(format!("{} is already defined", name.text));
+        }
+        if name.text != "main" {
+            self.start()?;
+        }
+        self.labels.insert(name.text, self.here);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: Token, value: i64) -> Result<(), String> {
+        if self.labels.contains_key(&name.text) {
+            return Err(format!("{} is already defined", name.text));
+        }
+        self.constants.insert(name.text, value);
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(name)?;
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.value()?;
+                self.define_constant(name, value)?;
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc()?;
+                self.define_constant(name, value)?;
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let x = self.register()?;
+                self.aliases.insert(name.text, x as u8);
+            }
+            ":org" => {
+                let address = self.value()?;
+                if address < PROGRAM_START as i64 || address > 0xFFFF {
+                    return Err(format!("Can't put code at {:#X}", address));
+                }
+                self.here = address as usize;
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(byte)?;
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_address(0x2000, &target)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":macro" => self.define_macro()?,
+            ";" | "return" => self.emit(0x00EE)?,
+            "clear" => self.emit(0x00E0)?,
+            "hires" => self.emit(0x00FF)?,
+            "lores" => self.emit(0x00FE)?,
+            "exit" => self.emit(0x00FD)?,
+            "scroll-left" => self.emit(0x00FC)?,
+            "scroll-right" => self.emit(0x00FB)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n)?;
+            }
+            "audio" => self.emit(0xF002)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let target = self.next()?;
+                let opcode = match token.text.as_str() {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                self.emit_address(opcode, &target)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | x << 8)?;
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(0xF075 | x << 8)?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(0xF085 | x << 8)?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let low = if token.text == "save" { 0x2 } else { 0x3 };
+                    self.emit(0x5000 | x << 8 | y << 4 | low)?;
+                } else {
+                    let low = if token.text == "save" { 0x55 } else { 0x65 };
+                    self.emit(0xF000 | x << 8 | low)?;
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let low = match token.text.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | x << 8 | low)?;
+            }
+            "i" => self.index_statement()?,
+            "if" => self.if_statement()?,
+            "else" => {
+                let jump = match self.blocks.pop() {
+                    Some((Block::Begin(jump), _)) => jump,
+                    _ => return Err("else without begin".to_string()),
+                };
+                let skip = self.emit_placeholder()?;
+                let here = self.here;
+                self.patch(jump, here)?;
+                self.blocks.push((Block::Else(skip), token.line));
+            }
+            "end" => {
+                match self.blocks.pop() {
+                    Some((Block::Begin(jump), _)) | Some((Block::Else(jump), _)) => {
+                        let here = self.here;
+                        self.patch(jump, here)?;
+                    }
+                    _ => return Err("end without begin".to_string()),
+                }
+            }
+            "loop" => self.blocks.push((Block::Loop(self.here, Vec::new()), token.line)),
+            "while" => {
+                let skip = self.condition()?;
+                self.emit(invert_skip(skip))?;
+                let jump = self.emit_placeholder()?;
+                for &mut (ref mut block, _) in self.blocks.iter_mut().rev() {
+                    if let Block::Loop(_, ref mut breaks) = *block {
+                        breaks.push(jump);
+                        return Ok(());
+                    }
+                }
+                return Err("while without loop".to_string());
+            }
+            "again" => {
+                let (start, breaks) = match self.blocks.pop() {
+                    Some((Block::Loop(start, breaks), _)) => (start, breaks),
+                    _ => return Err("again without loop".to_string()),
+                };
+                let jump = self.emit_placeholder()?;
+                self.patch(jump, start)?;
+                let here = self.here;
+                for jump in breaks {
+                    self.patch(jump, here)?;
+                }
+            }
+            text if self.register_named(text).is_some() => {
+                let x = self.register_named(text).unwrap() as u16;
+                self.register_statement(x)?;
+            }
+            text if self.macros.contains_key(text) => self.expand(&token)?,
+            text if parse_number(text).is_some() || self.constants.contains_key(text) => {
+                self.tokens.push(token);
+                let byte = self.byte()?;
+                self.emit_byte(byte)?;
+            }
+            text if text.starts_with(':') || text == "{" || text == "}" => {
+                return Err(format!("Unexpected {}", text));
+            }
+            // Anything else is a subroutine to call
+            _ => self.emit_address(0x2000, &token)?,
+        }
+        Ok(())
+    }
+
+    /// Compiles the statements that start with `i`.
+    fn index_statement(&mut self) -> Result<(), String> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.emit(0xF01E | x << 8)
+            }
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let low = if self.next()?.text == "hex" { 0x29 } else { 0x30 };
+                    let x = self.register()?;
+                    self.emit(0xF000 | x << 8 | low)
+                }
+                Some("long") => {
+                    self.next()?;
+                    let target = self.next()?;
+                    self.emit(0xF000)?;
+                    match self.known_value(&target.text) {
+                        Some(address) if (0..=0xFFFF).contains(&address) =>
+                            self.emit(address as u16),
+                        Some(_) => Err(format!("{} doesn't fit in 16 bits", target.text)),
+                        None => {
+                            self.fixups.push(Fixup { address: self.here, long: true, label: target });
+                            self.emit(0x0000)
+                        }
+                    }
+                }
+                _ => {
+                    let target = self.next()?;
+                    self.emit_address(0xA000, &target)
+                }
+            },
+            _ => Err(format!("Unknown operator i {}", op.text)),
+        }
+    }
+
+    /// Compiles the statements that start with register `x`.
+    fn register_statement(&mut self, x: u16) -> Result<(), String> {
+        let op = self.next()?;
+        let special = op.text == ":=" &&
+            matches!(self.peek(), Some("delay") | Some("key") | Some("random"));
+        if special {
+            match self.next()?.text.as_str() {
+                "delay" => return self.emit(0xF007 | x << 8),
+                "key" => return self.emit(0xF00A | x << 8),
+                _ => {
+                    let mask = self.byte()? as u16;
+                    return self.emit(0xC000 | x << 8 | mask);
+                }
+            }
+        }
+
+        let source = self.next()?;
+        if let Some(y) = self.register_named(&source.text) {
+            let low = match op.text.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return Err(format!("Unknown operator {}", op.text)),
+            };
+            return self.emit(0x8000 | x << 8 | (y as u16) << 4 | low);
+        }
+
+        self.tokens.push(source);
+        let byte = self.byte()? as u16;
+        match op.text.as_str() {
+            ":=" => self.emit(0x6000 | x << 8 | byte),
+            "+=" => self.emit(0x7000 | x << 8 | byte),
+            "-=" => self.emit(0x7000 | x << 8 | (byte.wrapping_neg() & 0xFF)),
+            _ => Err(format!("{} needs a register", op.text)),
+        }
+    }
+
+    /// Compiles `if cond then` and `if cond begin`.
+    fn if_statement(&mut self) -> Result<(), String> {
+        let line = self.line;
+        let skip = self.condition()?;
+        match self.next()?.text.as_str() {
+            "then" => self.emit(skip),
+            "begin" => {
+                self.emit(invert_skip(skip))?;
+                let jump = self.emit_placeholder()?;
+                self.blocks.push((Block::Begin(jump), line));
+                Ok(())
+            }
+            text => Err(format!("Expected then or begin, found {}", text)),
+        }
+    }
+
+    /// Compiles a condition, returning the skip instruction that skips the
+    /// next instruction when the condition is false.  Comparisons other than
+    /// equality work it out in `vF` first.
+    fn condition(&mut self) -> Result<u16, String> {
+        let x = self.register()?;
+        let op = self.next()?;
+        match op.text.as_str() {
+            "key" => return Ok(0xE0A1 | x << 8),
+            "-key" => return Ok(0xE09E | x << 8),
+            _ => {}
+        }
+
+        let rhs = self.next()?;
+        let y = self.register_named(&rhs.text).map(|y| y as u16);
+        match op.text.as_str() {
+            "==" | "!=" => {
+                let equal = op.text == "==";
+                return Ok(match y {
+                    Some(y) if equal => 0x9000 | x << 8 | y << 4,
+                    Some(y) => 0x5000 | x << 8 | y << 4,
+                    None => {
+                        self.tokens.push(rhs);
+                        let byte = self.byte()? as u16;
+                        (if equal { 0x4000 } else { 0x3000 }) | x << 8 | byte
+                    }
+                });
+            }
+            "<" | ">" | "<=" | ">=" => {}
+            _ => return Err(format!("Unknown comparison {}", op.text)),
+        }
+
+        // vF := rhs, then subtract into vF.  This relies on 8XY5 and 8XY7
+        // writing the flag after the difference, so that vF ends up holding
+        // the flag
+        match y {
+            Some(y) => self.emit(0x8F00 | y << 4)?,
+            None => {
+                self.tokens.push(rhs);
+                let byte = self.byte()? as u16;
+                self.emit(0x6F00 | byte)?;
+            }
+        }
+        match op.text.as_str() {
+            // vF = x - rhs, with vF set when x >= rhs
+            "<" => {
+                self.emit(0x8F07 | x << 4)?;
+                Ok(0x3F01)
+            }
+            ">=" => {
+                self.emit(0x8F07 | x << 4)?;
+                Ok(0x3F00)
+            }
+            // vF = rhs - x, with vF set when x <= rhs
+            ">" => {
+                self.emit(0x8F05 | x << 4)?;
+                Ok(0x3F01)
+            }
+            _ => {
+                self.emit(0x8F05 | x << 4)?;
+                Ok(0x3F00)
+            }
+        }
+    }
+
+    /// Reads the tokens between a pair of braces.
+    fn braced(&mut self) -> Result<Vec<Token>, String> {
+        self.expect("{")?;
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.next()?;
+        let mut args = Vec::new();
+        while self.peek().is_some_and(|text| text != "{") {
+            args.push(self.next()?.text);
+        }
+        let body = self.braced()?;
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &Token) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("Too many macro expansions at {}", name.text));
+        }
+        let nargs = self.macros[&name.text].args.len();
+        let mut values = HashMap::new();
+        for i in 0..nargs {
+            let value = self.next()?.text;
+            values.insert(self.macros[&name.text].args[i].clone(), value);
+        }
+        let body: Vec<Token> = self.macros[&name.text].body.iter().rev()
+            .map(|token| Token {
+                text: values.get(&token.text).cloned().unwrap_or_else(|| token.text.clone()),
+                line: token.line,
+            })
+            .collect();
+        self.tokens.extend(body);
+        Ok(())
+    }
+
+    /// Works out a braced `:calc` expression.
+    fn calc(&mut self) -> Result<i64, String> {
+        let mut tokens = self.braced()?;
+        tokens.reverse();
+        let value = self.calc_expression(&mut tokens)?;
+        match tokens.pop() {
+            Some(token) => Err(format!("Unexpected {} in expression", token.text)),
+            None => Ok(value),
+        }
+    }
+
+    fn calc_expression(&self, tokens: &mut Vec<Token>) -> Result<i64, String> {
+        let lhs = self.calc_term(tokens)?;
+        let op = match tokens.last() {
+            Some(token) if token.text != ")" => token.text.clone(),
+            _ => return Ok(lhs),
+        };
+        tokens.pop();
+        let rhs = self.calc_expression(tokens)?;
+        Ok(match op.as_str() {
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => return Err("Division by zero".to_string()),
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => lhs & rhs,
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            _ => return Err(format!("Unknown operator {}", op)),
+        })
+    }
+
+    fn calc_term(&self, tokens: &mut Vec<Token>) -> Result<i64, String> {
+        let token = match tokens.pop() {
+            Some(token) => token,
+            None => return Err("Expression ends early".to_string()),
+        };
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(tokens)?;
+                match tokens.pop() {
+                    Some(ref token) if token.text == ")" => Ok(value),
+                    _ => Err("Missing )".to_string()),
+                }
+            }
+            "-" => Ok(-self.calc_term(tokens)?),
+            "~" => Ok(!self.calc_term(tokens)?),
+            "HERE" => Ok(self.here as i64),
+            text => self.known_value(text).ok_or_else(|| format!("Unknown value: {}", text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::compile;
+    use Chip8;
+
+    #[test]
+    fn compiles_statements() {
+        let rom = compile("
+            : main
+                clear
+                v1 := 0x2F   v2 += v1   v3 -= 1
+                i := sprite  sprite v1 v2 5
+                delay := v1  v0 := key
+                save v1 - v3
+                loop again
+            : sprite 0xFF 0b10000001 -1
+        ").unwrap();
+        assert_eq!(rom, vec![
+            0x00, 0xE0, 0x61, 0x2F, 0x82, 0x14, 0x73, 0xFF, 0xA2, 0x14, 0xD1, 0x25,
+            0xF1, 0x15, 0xF0, 0x0A, 0x51, 0x32, 0x12, 0x12, 0xFF, 0x81, 0xFF,
+        ]);
+    }
+
+    #[test]
+    fn jumps_to_main() {
+        let rom = compile(": draw return\n: main draw").unwrap();
+        assert_eq!(rom, vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+        assert_eq!(compile(": start return").unwrap_err().message,
+                   "This program has no main label");
+    }
+
+    #[test]
+    fn conditionals() {
+        let rom = compile("
+            : main
+                if v1 == 3 then v2 := 1
+                if v1 key begin
+                    v2 := 2
+                else
+                    v2 := 3
+                end
+        ").unwrap();
+        assert_eq!(rom, vec![
+            0x41, 0x03, 0x62, 0x01,
+            0xE1, 0x9E, 0x12, 0x0C, 0x62, 0x02, 0x12, 0x0E, 0x62, 0x03,
+        ]);
+    }
+
+    #[test]
+    fn comparisons() {
+        let run = |condition: &str, a: u8, b: u8| {
+            let source = format!(": main v0 := {} v1 := {} v2 := 0 if {} then v2 := 1 exit",
+                                 a, b, condition);
+            let mut chip = Chip8::default();
+            chip.load(&compile(&source).unwrap());
+            chip.run_frame(20).unwrap();
+            chip.registers()[2] == 1
+        };
+        for &(a, b) in &[(1, 2), (2, 2), (3, 2)] {
+            assert_eq!(run("v0 < v1", a, b), a < b);
+            assert_eq!(run("v0 > v1", a, b), a > b);
+            assert_eq!(run("v0 <= v1", a, b), a <= b);
+            assert_eq!(run("v0 >= 2", a, b), a >= 2);
+            assert_eq!(run("v0 != v1", a, b), a != b);
+        }
+    }
+
+    #[test]
+    fn loops_run() {
+        let mut chip = Chip8::default();
+        chip.load(&compile("
+            : main
+                v0 := 0
+                loop
+                    v0 += 3
+                    while v0 != 30
+                    v1 += 1
+                again
+                exit
+        ").unwrap());
+        chip.run_frame(200).unwrap();
+        assert_eq!(chip.registers()[0], 30);
+        assert_eq!(chip.registers()[1], 9);
+    }
+
+    #[test]
+    fn constants_aliases_macros_and_calc() {
+        let rom = compile("
+            :const SPEED 2
+            :alias x v4
+            :calc DOUBLE { SPEED * 2 + 1 }
+            :macro move reg amount { reg += amount }
+            : main
+                move x DOUBLE
+                x := SPEED
+                :byte { ( 1 << 4 ) | 3 }
+                :org 0x208
+                :calc END { HERE }
+                :byte { END - 0x200 }
+        ").unwrap();
+        // Right to left, so SPEED * (2 + 1)
+        assert_eq!(rom, vec![0x74, 0x06, 0x64, 0x02, 0x13, 0x00, 0x00, 0x00, 0x08]);
+    }
+
+    #[test]
+    fn errors_give_line_numbers() {
+        let error = compile(": main\n  clear\n  v1 := 300").unwrap_err();
+        assert_eq!((error.line, error.message.as_str()), (3, "300 doesn't fit in a byte"));
+        let error = compile(": main\n  nowhere\n").unwrap_err();
+        assert_eq!((error.line, error.message.as_str()), (2, "Undefined label: nowhere"));
+        let error = compile(": main\n  if v0 == 1 begin\n  clear").unwrap_err();
+        assert_eq!((error.line, error.message.as_str()), (2, "begin without end"));
+        let error = compile(": main\n  :org 0x1000\n  loop\n  again").unwrap_err();
+        assert_eq!((error.line, error.message.as_str()), (4, "Jump to 0x1000 is out of reach"));
+    }
+}
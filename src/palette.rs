@@ -0,0 +1,140 @@
+//! The colours the display is drawn in.
+
+use std::str::FromStr;
+
+/// A colour as red, green and blue.
+pub type Color = [u8; 3];
+
+/// The names of the built-in themes, in the order the theme hotkey cycles
+/// through them.
+pub const THEMES: [&str; 5] = ["classic", "green", "amber", "lcd", "contrast"];
+
+/// The colour of a pixel for each combination of XO-CHIP planes it is lit
+/// on: neither (the background), plane 1 (the foreground), plane 2 and both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::classic()
+    }
+}
+
+impl Palette {
+    /// Yellow on black, as this emulator has always drawn.
+    pub fn classic() -> Self {
+        Palette { colors: [[0, 0, 0], [255, 255, 0], [255, 102, 0], [102, 34, 0]] }
+    }
+
+    /// A green phosphor monitor.
+    pub fn green() -> Self {
+        Palette { colors: [[8, 24, 8], [51, 255, 102], [24, 140, 56], [170, 255, 190]] }
+    }
+
+    /// An amber phosphor monitor.
+    pub fn amber() -> Self {
+        Palette { colors: [[20, 12, 0], [255, 176, 0], [160, 100, 0], [255, 220, 140]] }
+    }
+
+    /// A grey LCD, dark pixels on a light screen.
+    pub fn lcd() -> Self {
+        Palette { colors: [[199, 204, 196], [40, 44, 40], [120, 124, 118], [80, 84, 79]] }
+    }
+
+    /// White on black, with bright, distinct colours for the other planes.
+    pub fn high_contrast() -> Self {
+        Palette { colors: [[0, 0, 0], [255, 255, 255], [0, 170, 255], [255, 0, 170]] }
+    }
+
+    /// The colour of a pixel lit on the given planes.
+    pub fn color(&self, planes: u8) -> Color {
+        self.colors[planes as usize & 3]
+    }
+
+    /// The built-in theme after this one, and its name.  A palette that isn't
+    /// one of the themes is followed by the first theme.
+    pub fn next_theme(&self) -> (&'static str, Palette) {
+        let current = THEMES.iter().position(|name| name.parse() == Ok(*self));
+        let name = THEMES[current.map_or(0, |index| (index + 1) % THEMES.len())];
+        (name, name.parse().unwrap())
+    }
+
+    /// Reads four comma separated colours, for the background, plane 1,
+    /// plane 2 and both planes.
+    pub fn parse_colors(text: &str) -> Result<Self, String> {
+        let colors = text.split(',')
+            .map(|color| parse_color(color.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if colors.len() != 4 {
+            return Err(format!("Expected four colours, not {}", colors.len()));
+        }
+        let mut palette = Palette::default();
+        palette.colors.copy_from_slice(&colors);
+        Ok(palette)
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Looks up a theme by name: `classic`, `green`, `amber`, `lcd` or
+    /// `contrast`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "classic" => Ok(Palette::classic()),
+            "green" => Ok(Palette::green()),
+            "amber" => Ok(Palette::amber()),
+            "lcd" => Ok(Palette::lcd()),
+            "contrast" => Ok(Palette::high_contrast()),
+            _ => Err(format!("Unknown theme: {}", name)),
+        }
+    }
+}
+
+/// Reads a colour written as `RRGGBB` in hex, with or without a leading
+/// `#`.
+pub fn parse_color(text: &str) -> Result<Color, String> {
+    let hex = text.trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Bad colour {}, expected RRGGBB", text));
+    }
+    let mut color = [0; 3];
+    for (i, channel) in color.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    Ok(color)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_color, Palette, THEMES};
+
+    #[test]
+    fn themes() {
+        for name in THEMES.iter() {
+            assert!(name.parse::<Palette>().is_ok(), "{}", name);
+        }
+        assert!("sepia".parse::<Palette>().is_err());
+        assert_eq!(Palette::default().color(1), [255, 255, 0]);
+        assert_eq!(Palette::lcd().color(0), [199, 204, 196]);
+
+        assert_eq!(Palette::classic().next_theme(), ("green", Palette::green()));
+        assert_eq!(Palette::high_contrast().next_theme(), ("classic", Palette::classic()));
+        let custom = Palette { colors: [[1, 2, 3]; 4] };
+        assert_eq!(custom.next_theme(), ("classic", Palette::classic()));
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#FF8000"), Ok([255, 128, 0]));
+        assert_eq!(parse_color("0a0B0c"), Ok([10, 11, 12]));
+        assert!(parse_color("#FFF").is_err());
+        assert!(parse_color("#GG0000").is_err());
+
+        let palette = Palette::parse_colors("000000, ffffff, #ff0000, 00ff00").unwrap();
+        assert_eq!(palette.colors, [[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 255, 0]]);
+        assert!(Palette::parse_colors("000000,ffffff").is_err());
+    }
+}
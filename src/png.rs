@@ -0,0 +1,226 @@
+//! Screenshots of the display as PNG images.
+//!
+//! Each pixel is stored as an index into the four colours of the palette.
+//! The image data is compressed with deflate's fixed Huffman codes, copying
+//! runs from the pixel to the left or the row above, which is all it takes
+//! to shrink the large flat areas a CHIP-8 display is made of.
+
+use checksum::{adler32, crc32_update};
+use palette::Palette;
+use state::put_u32;
+use Chip8;
+
+/// The bytes every PNG file starts with.
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// The shortest and longest runs deflate can copy.
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// The shortest run each length code stands for, and how many extra bits
+/// follow it to give the rest.
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// The shortest distance each distance code stands for, and how many extra
+/// bits follow it to give the rest.
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                  257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                                  8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                                  7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Packs values into bytes least significant bit first, as both deflate and
+/// GIF's LZW want them.
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the low `count` bits of a value.
+    pub(crate) fn write(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which goes most significant bit first.
+    fn code(&mut self, code: u32, length: u32) {
+        let reversed = (0..length).fold(0, |reversed, bit| reversed << 1 | (code >> bit & 1));
+        self.write(reversed, length);
+    }
+
+    /// Writes a literal/length symbol in the fixed Huffman code.
+    fn symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    /// Writes a copy of the `length` bytes found `distance` bytes back.
+    fn copy(&mut self, length: usize, distance: usize) {
+        let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+        self.symbol(257 + code as u16);
+        self.write((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+        let code = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+        self.code(code as u32, 5);
+        self.write((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+    }
+
+    /// Pads out the last byte and hands back the stream.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// How many bytes from `start` repeat the ones `distance` bytes before.
+fn match_length(data: &[u8], start: usize, distance: usize) -> usize {
+    data[start..].iter()
+        .zip(&data[start - distance..])
+        .take(MAX_MATCH)
+        .take_while(|&(byte, earlier)| byte == earlier)
+        .count()
+}
+
+/// Compresses data into a single deflate block, copying runs from one byte
+/// back or from `stride` bytes back.
+fn deflate(data: &[u8], stride: usize) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    // The final block, with fixed Huffman codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = [1, stride].iter()
+            .filter(|&&distance| distance <= position)
+            .map(|&distance| (match_length(data, position, distance), distance))
+            .max()
+            .unwrap_or((0, 0));
+        if length >= MIN_MATCH {
+            bits.copy(length, distance);
+            position += length;
+        } else {
+            bits.symbol(data[position] as u16);
+            position += 1;
+        }
+    }
+    bits.symbol(256);
+    bits.finish()
+}
+
+/// Appends a chunk, with its length and CRC.
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    put_u32(out, data.len() as u32);
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    put_u32(out, crc32_update(crc32_update(0, kind), data));
+}
+
+/// Encodes an image as a PNG.  Each pixel is given, row by row, as the
+/// planes it's lit on, which picks its colour from the palette.
+pub fn encode(width: usize, height: usize, pixels: &[u8], palette: &Palette) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height, "The image should be {}x{}", width, height);
+
+    // Each row starts with its filter type, which is always none
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width.max(1)) {
+        raw.push(0);
+        raw.extend(row.iter().map(|&planes| planes & 3));
+    }
+    let mut data = vec![0x78, 0x01];
+    data.extend(deflate(&raw, width + 1));
+    put_u32(&mut data, adler32(&raw));
+
+    let mut header = Vec::with_capacity(13);
+    put_u32(&mut header, width as u32);
+    put_u32(&mut header, height as u32);
+    // 8 bits a pixel, indexed colour, deflate, no interlacing
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+
+    let mut out = SIGNATURE.to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"PLTE", &palette.colors.concat());
+    chunk(&mut out, b"IDAT", &data);
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+/// Encodes the chip's display as a PNG in the given colours, with each
+/// pixel scaled up to a `scale` by `scale` square.
+pub fn screenshot(chip: &Chip8, palette: &Palette, scale: usize) -> Vec<u8> {
+    let (width, height) = (chip.width(), chip.height());
+    let mut pixels = Vec::with_capacity(width * height * scale * scale);
+    for row in chip.graphics.chunks(width) {
+        for _ in 0..scale {
+            for &planes in row {
+                pixels.extend((0..scale).map(|_| planes));
+            }
+        }
+    }
+    encode(width * scale, height * scale, &pixels, palette)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode, screenshot};
+    use checksum::crc32;
+    use palette::Palette;
+    use Chip8;
+
+    /// Splits a PNG into its chunks, checking their CRCs.
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from(rest[0]) << 24 | u32::from(rest[1]) << 16 |
+                         u32::from(rest[2]) << 8 | u32::from(rest[3]);
+            let (body, next) = rest[4..].split_at(length as usize + 4);
+            let crc = &next[..4];
+            let expected = crc32(body);
+            assert_eq!(crc, [(expected >> 24) as u8, (expected >> 16) as u8,
+                             (expected >> 8) as u8, expected as u8]);
+            chunks.push((String::from_utf8(body[..4].to_vec()).unwrap(), body[4..].to_vec()));
+            rest = &next[4..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn structure() {
+        let palette = Palette::classic();
+        let png = encode(3, 2, &[0, 1, 2, 3, 0, 1], &palette);
+        let chunks = chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|chunk| chunk.0.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "PLTE", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 3, 0, 0, 0]);
+        assert_eq!(chunks[1].1, palette.colors.concat());
+        assert!(chunks[3].1.is_empty());
+    }
+
+    #[test]
+    fn scaled_screenshot() {
+        let mut chip = Chip8::default();
+        chip.graphics[0] = 1;
+        let chunks = chunks(&screenshot(&chip, &Palette::default(), 4));
+        assert_eq!(&chunks[0].1[..8], [0, 0, 1, 0, 0, 0, 0, 128]);
+        // A nearly blank display compresses to next to nothing
+        assert!(chunks[2].1.len() < 1000, "{} bytes", chunks[2].1.len());
+    }
+}
@@ -0,0 +1,82 @@
+use std::str::FromStr;
+
+/// The behaviour of the instructions that differ between CHIP-8
+/// interpreters.
+///
+/// Games are written against a particular interpreter, so picking the
+/// matching profile fixes games that otherwise glitch or crash.  The default
+/// profile matches the behaviour this emulator has always had.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 0x8XY6 and 0x8XYE shift regY into regX, rather than shifting regX in
+    /// place.
+    pub shift_uses_vy: bool,
+    /// 0xFX55 and 0xFX65 leave the index pointing just past the last register
+    /// stored or loaded, rather than leaving it unchanged.
+    pub load_store_increments_index: bool,
+    /// 0xBNNN jumps to NNN + regX (where X is the highest nibble of NNN),
+    /// rather than NNN + reg0.
+    pub jump_uses_vx: bool,
+    /// 0x8XY1, 0x8XY2 and 0x8XY3 reset regF to 0.
+    pub logic_resets_vf: bool,
+    /// 0xDXYN wraps sprites around the edges of the screen, rather than
+    /// clipping them.
+    pub wrap_sprites: bool,
+}
+
+impl Default for Quirks {
+    /// The profile this emulator used before quirks were configurable.
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_index: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// The original interpreter on the COSMAC VIP.
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_index: true,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            wrap_sprites: false,
+        }
+    }
+
+    /// The CHIP-48 interpreter on the HP-48 calculators.
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_index: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+        }
+    }
+
+    /// The SUPER-CHIP 1.1 interpreter.
+    pub fn schip() -> Self {
+        Quirks::chip48()
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    /// Looks up a profile by name: `default`, `vip`, `chip48` or `schip`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "default" => Ok(Quirks::default()),
+            "vip" => Ok(Quirks::cosmac_vip()),
+            "chip48" => Ok(Quirks::chip48()),
+            "schip" => Ok(Quirks::schip()),
+            _ => Err(format!("Unknown quirks profile: {}", name)),
+        }
+    }
+}
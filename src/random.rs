@@ -0,0 +1,79 @@
+/// The random number generator behind 0xCXNN.
+///
+/// This is xorshift64*, which is fast, good enough for games, and keeps all
+/// of its state in a single number, so a run can be reproduced from its seed
+/// and saved along with the rest of the chip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    /// Constructs a generator from the given seed.  Any seed is fine,
+    /// including 0.
+    pub fn new(seed: u64) -> Self {
+        // Scramble the seed (with splitmix64's finalizer) so that similar
+        // seeds give different sequences, and xorshift never sees a 0 state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Random::from_state(z)
+    }
+
+    /// Constructs a generator that carries on from a saved `state()`.
+    pub fn from_state(state: u64) -> Self {
+        Random { state: if state == 0 { 1 } else { state } }
+    }
+
+    /// The generator's internal state.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Generates the next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Generates a random byte, anywhere from 0 to 255.
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Random;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Random::new(42);
+        let mut b = Random::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert!(Random::new(43).next_u64() != Random::new(42).next_u64());
+    }
+
+    #[test]
+    fn resumes_from_state() {
+        let mut a = Random::new(7);
+        a.next_u64();
+        let mut b = Random::from_state(a.state());
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+
+    #[test]
+    fn covers_every_byte() {
+        let mut random = Random::new(0);
+        let mut seen = [false; 256];
+        for _ in 0..10000 {
+            seen[random.next_u8() as usize] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+
+/// Keeps the recent history of a chip's save states so that the emulation
+/// can be run backwards.
+///
+/// Only the newest state is kept whole.  Every older state is stored as the
+/// difference between it and the state after it, XORed together and run
+/// length encoded, which is tiny since a frame rarely changes much of the
+/// machine.  Stepping back undoes the newest difference.
+pub struct Rewind {
+    latest: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>,
+    bytes: usize,
+    max_frames: usize,
+    max_bytes: usize,
+}
+
+impl Rewind {
+    /// Constructs a Rewind that keeps up to `max_frames` frames of history,
+    /// dropping the oldest frames sooner if they take up more than
+    /// `max_bytes`.
+    pub fn new(max_frames: usize, max_bytes: usize) -> Self {
+        Rewind {
+            latest: Vec::new(),
+            deltas: VecDeque::new(),
+            bytes: 0,
+            max_frames,
+            max_bytes,
+        }
+    }
+
+    /// Records the state after a frame has run.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.latest.len() == state.len() {
+            let delta = encode_delta(&self.latest, &state);
+            self.bytes += delta.len();
+            self.deltas.push_back(delta);
+        } else {
+            // States of different sizes can't be diffed, so start over
+            self.deltas.clear();
+            self.bytes = 0;
+        }
+        self.latest = state;
+
+        while self.deltas.len() > self.max_frames || self.bytes > self.max_bytes {
+            match self.deltas.pop_front() {
+                Some(delta) => self.bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Steps back a frame, returning the state to restore, or `None` if
+    /// there is no history left.
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        self.bytes -= delta.len();
+        apply_delta(&mut self.latest, &delta);
+        Some(&self.latest)
+    }
+}
+
+/// Appends `value` as a little-endian base 128 varint.
+fn put_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads a varint written by `put_varint`, advancing `pos` past it.
+fn get_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// Encodes `old XOR new` as pairs of (number of unchanged bytes, number of
+/// changed bytes) followed by the changed bytes XORed together.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < new.len() {
+        let same = old[pos..].iter().zip(&new[pos..]).take_while(|&(a, b)| a == b).count();
+        pos += same;
+        let changed = old[pos..].iter().zip(&new[pos..]).take_while(|&(a, b)| a != b).count();
+        put_varint(&mut out, same);
+        put_varint(&mut out, changed);
+        out.extend(old[pos..pos + changed].iter().zip(&new[pos..pos + changed]).map(|(a, b)| a ^ b));
+        pos += changed;
+    }
+    out
+}
+
+/// XORs a delta made by `encode_delta` back into `state`.  Since XOR undoes
+/// itself this turns either of the two states into the other.
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut read = 0;
+    while read < delta.len() {
+        pos += get_varint(delta, &mut read);
+        let changed = get_varint(delta, &mut read);
+        for byte in &mut state[pos..pos + changed] {
+            *byte ^= delta[read];
+            read += 1;
+        }
+        pos += changed;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply_delta, encode_delta, get_varint, put_varint, Rewind};
+
+    #[test]
+    fn varints() {
+        let mut out = Vec::new();
+        for &value in &[0, 1, 127, 128, 300, 1 << 40] {
+            put_varint(&mut out, value);
+        }
+        assert_eq!(&out[..5], &[0, 1, 0x7F, 0x80, 0x01]);
+        let mut pos = 0;
+        for &value in &[0, 1, 127, 128, 300, 1 << 40] {
+            assert_eq!(get_varint(&out, &mut pos), value);
+        }
+        assert_eq!(pos, out.len());
+    }
+
+    #[test]
+    fn deltas_undo_themselves() {
+        let old = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let new = vec![1, 9, 9, 4, 5, 6, 7, 0];
+        let delta = encode_delta(&old, &new);
+        // Skip 1, change 2, skip 4, change 1
+        assert_eq!(delta, [1, 2, 2 ^ 9, 3 ^ 9, 4, 1, 8]);
+
+        let mut state = old.clone();
+        apply_delta(&mut state, &delta);
+        assert_eq!(state, new);
+        apply_delta(&mut state, &delta);
+        assert_eq!(state, old);
+
+        // An unchanged state is just a run of unchanged bytes
+        assert_eq!(encode_delta(&old, &old), [8, 0]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let states: Vec<Vec<u8>> = (0..10u8).map(|frame| vec![frame, 0, frame * 2, 7]).collect();
+        let mut rewind = Rewind::new(100, 1000);
+        for state in &states {
+            rewind.push(state.clone());
+        }
+        for state in states[..9].iter().rev() {
+            assert_eq!(rewind.pop(), Some(&state[..]));
+        }
+        assert_eq!(rewind.pop(), None);
+
+        // Playing on after stepping back keeps what's left of the history
+        let mut rewind = Rewind::new(100, 1000);
+        for state in &states[..5] {
+            rewind.push(state.clone());
+        }
+        rewind.pop();
+        rewind.pop();
+        rewind.push(vec![42; 4]);
+        assert_eq!(rewind.pop(), Some(&states[2][..]));
+        assert_eq!(rewind.pop(), Some(&states[1][..]));
+    }
+
+    #[test]
+    fn size_change_resets() {
+        let mut rewind = Rewind::new(100, 1000);
+        rewind.push(vec![1, 2]);
+        rewind.push(vec![3, 4]);
+        rewind.push(vec![5, 6, 7]);
+        assert_eq!(rewind.pop(), None);
+
+        rewind.push(vec![8, 9, 10]);
+        assert_eq!(rewind.pop(), Some(&[5, 6, 7][..]));
+    }
+
+    #[test]
+    fn eviction() {
+        // Only the last three frames are kept
+        let mut rewind = Rewind::new(3, 1000);
+        for frame in 0..10 {
+            rewind.push(vec![frame; 4]);
+        }
+        for frame in (6..9).rev() {
+            assert_eq!(rewind.pop(), Some(&[frame; 4][..]));
+        }
+        assert_eq!(rewind.pop(), None);
+
+        // Each delta here is 6 bytes, so a 20 byte budget keeps three
+        let mut rewind = Rewind::new(100, 20);
+        for frame in 0..10 {
+            rewind.push(vec![frame + 1; 4]);
+        }
+        assert_eq!(rewind.bytes, 18);
+        for frame in (7..10).rev() {
+            assert_eq!(rewind.pop(), Some(&[frame; 4][..]));
+        }
+        assert_eq!(rewind.pop(), None);
+        assert_eq!(rewind.bytes, 0);
+    }
+}
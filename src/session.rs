@@ -0,0 +1,220 @@
+use capture::unused_path;
+use chip8::{Chip8, Chip8Error, CycleOutcome};
+use chip8::frontend::{Command, Controller, FRAME_RATE};
+use chip8::movie::{Movie, Replay};
+use chip8::palette::Palette;
+use chip8::png;
+use debugger::Debugger;
+use rewind::Rewind;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+/// The number of save state slots.
+const NSLOTS : u8 = 10;
+
+/// The most memory (in bytes) the rewind history may use.
+const REWIND_MAX_BYTES : usize = 32 * 1024 * 1024;
+
+/// The file that a save state slot is kept in, next to the ROM.
+fn state_path(rom: &str, slot: u8) -> String {
+    format!("{}.state{}", rom, slot)
+}
+
+/// Saves the chip's state into the given file.
+fn save_state(chip: &Chip8, path: &str) -> io::Result<()> {
+    File::create(path)?.write_all(&chip.save_state())
+}
+
+/// Restores the chip's state from the given file.
+fn load_state(chip: &mut Chip8, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut state = Vec::new();
+    File::open(path)?.read_to_end(&mut state)?;
+    chip.load_state(&state)?;
+    Ok(())
+}
+
+/// The save states, rewinding, debugging and movies that every frontend
+/// offers around the chip, as the emulator's controller.
+pub struct Session {
+    rom: String,
+    slot: u8,
+    screenshot_scale: usize,
+    rewind: Rewind,
+    rewinding: bool,
+    keep_history: bool,
+    debugger: Option<Debugger>,
+    status: Option<String>,
+    recording: Option<(Movie, String)>,
+    replay: Option<Replay>,
+    verify: bool,
+    failure: Option<String>,
+}
+
+impl Session {
+    /// Constructs a Session for the game loaded from `rom`, keeping
+    /// `rewind_seconds` of history and scaling screenshots up by
+    /// `screenshot_scale`.
+    pub fn new(rom: &str, rewind_seconds: usize, debug: bool, screenshot_scale: usize) -> Self {
+        Session {
+            rom: rom.to_string(),
+            slot: 0,
+            screenshot_scale,
+            rewind: Rewind::new(rewind_seconds * FRAME_RATE as usize, REWIND_MAX_BYTES),
+            rewinding: false,
+            keep_history: rewind_seconds > 0,
+            debugger: if debug { Some(Debugger::new()) } else { None },
+            status: None,
+            recording: None,
+            replay: None,
+            verify: false,
+            failure: None,
+        }
+    }
+
+    /// Records every frame into the movie, to be saved to `path` by
+    /// `finish`.
+    pub fn record(&mut self, movie: Movie, path: &str) {
+        self.recording = Some((movie, path.to_string()));
+    }
+
+    /// Plays the keys back from a movie rather than the frontend.  If
+    /// `verify` is set the game stops as soon as the display differs from
+    /// the recording.
+    pub fn replay(&mut self, replay: Replay, verify: bool) {
+        self.replay = Some(replay);
+        self.verify = verify;
+    }
+
+    /// Saves the movie being recorded, once the game is over.
+    /// # Errors
+    /// Returns why a verified replay stopped, or why the movie couldn't be
+    /// saved.
+    pub fn finish(self) -> Result<(), String> {
+        if let Some((movie, path)) = self.recording {
+            File::create(&path)
+                .and_then(|mut file| file.write_all(&movie.save()))
+                .map_err(|e| format!("Couldn't save movie to {}: {}", path, e))?;
+            println!("Saved {} frames to {}", movie.frames.len(), path);
+        }
+        if let Some(ref replay) = self.replay {
+            println!("Replayed {} of {} frames", replay.frame(), replay.movie().frames.len());
+        }
+        match self.failure {
+            Some(failure) => Err(failure),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Controller for Session {
+    /// Acts on a hotkey, returning false if the player wants to quit.
+    fn command(&mut self, chip: &mut Chip8, command: Command) -> bool {
+        self.rewinding = false;
+        match command {
+            Command::Quit => return false,
+            Command::Continue => {}
+            Command::SaveState => {
+                let path = state_path(&self.rom, self.slot);
+                self.status = Some(match save_state(chip, &path) {
+                    Ok(()) => format!("Saved state to {}", path),
+                    Err(e) => format!("Couldn't save state to {}: {}", path, e),
+                });
+            }
+            Command::LoadState if self.recording.is_some() || self.replay.is_some() => {
+                self.status = Some("States can't be loaded during a movie".to_string());
+            }
+            Command::LoadState => {
+                let path = state_path(&self.rom, self.slot);
+                self.status = Some(match load_state(chip, &path) {
+                    Ok(()) => {
+                        chip.draw_flag = true;
+                        format!("Loaded state from {}", path)
+                    }
+                    Err(e) => format!("Couldn't load state from {}: {}", path, e),
+                });
+            }
+            Command::PrevSlot => {
+                self.slot = (self.slot + NSLOTS - 1) % NSLOTS;
+                self.status = Some(format!("Save slot {}", self.slot));
+            }
+            Command::NextSlot => {
+                self.slot = (self.slot + 1) % NSLOTS;
+                self.status = Some(format!("Save slot {}", self.slot));
+            }
+            Command::Rewind if self.replay.is_some() => {}
+            Command::Rewind => {
+                self.rewinding = true;
+                if let Some(state) = self.rewind.pop() {
+                    chip.load_state(state).unwrap();
+                    chip.draw_flag = true;
+                    // The rewound frames are taken back out of the movie
+                    if let Some((ref mut movie, _)) = self.recording {
+                        movie.frames.pop();
+                    }
+                }
+            }
+            Command::Break => {
+                if let Some(ref mut debugger) = self.debugger {
+                    debugger.interrupt();
+                }
+            }
+            // The emulator passes theme changes and recording to the
+            // display, and asks for screenshots itself since only the
+            // display knows the colours
+            Command::NextTheme | Command::Screenshot | Command::Record => {}
+        }
+        true
+    }
+
+    /// Runs a frame on the chip, unless it's being rewound.  Returns false
+    /// once the game exits, the debugger quits or the replay ends.
+    fn run_frame(&mut self, chip: &mut Chip8, ipf: usize) -> Result<bool, Chip8Error> {
+        if self.rewinding {
+            return Ok(true);
+        }
+        if let Some(ref replay) = self.replay {
+            if !replay.press_keys(chip) {
+                return Ok(false);
+            }
+        }
+        let outcome = match self.debugger {
+            Some(ref mut debugger) => match debugger.run_frame(chip, ipf) {
+                Some(outcome) => outcome,
+                None => return Ok(false),
+            },
+            None => chip.run_frame(ipf)?,
+        };
+        if let Some((ref mut movie, _)) = self.recording {
+            movie.record(chip);
+        }
+        if let Some(ref mut replay) = self.replay {
+            if let Err(e) = replay.finish_frame(chip) {
+                if self.verify {
+                    self.failure = Some(e.to_string());
+                    return Ok(false);
+                }
+            }
+        }
+        if outcome == CycleOutcome::Exit {
+            return Ok(false);
+        }
+        if self.keep_history {
+            self.rewind.push(chip.save_state());
+        }
+        Ok(true)
+    }
+
+    /// Saves the screenshot next to the ROM.
+    fn screenshot(&mut self, chip: &Chip8, palette: Palette) {
+        let path = unused_path(&self.rom, "png");
+        let image = png::screenshot(chip, &palette, self.screenshot_scale);
+        self.status = Some(match File::create(&path).and_then(|mut file| file.write_all(&image)) {
+            Ok(()) => format!("Saved screenshot to {}", path),
+            Err(e) => format!("Couldn't save screenshot to {}: {}", path, e),
+        });
+    }
+
+    fn take_status(&mut self) -> Option<String> {
+        self.status.take()
+    }
+}
@@ -0,0 +1,303 @@
+//! Save states: snapshots of a whole `Chip8` that can be restored later.
+//!
+//! A state is a header followed by the machine, all big-endian:
+//!
+//! ```text
+//! magic "C8ST" | version u16 | ROM CRC-32 u32 | machine
+//! ```
+//!
+//! The machine part is always the same size, whatever the chip was doing, so
+//! states of the same version can be compared byte for byte.
+
+use std::error::Error;
+use std::fmt;
+
+use super::{Chip8, Random, HIRES_HEIGHT, HIRES_WIDTH, NFLAGS, NMEM, NPLANES, NREG, PATTERN_SIZE};
+
+/// Marks the start of every save state.
+const MAGIC: &[u8; 4] = b"C8ST";
+
+/// The version of the format written by `save_state`.  Version 2 added the
+/// random number generator.  Version 3 changed the sound flag from "the
+/// sound timer just ran out" to "the sound timer was running".
+pub const STATE_VERSION: u16 = 3;
+
+/// The size of the header before the machine.
+const HEADER_SIZE: usize = 10;
+
+/// The largest number of pixels the display can have.
+const MAX_PIXELS: usize = (HIRES_WIDTH * HIRES_HEIGHT) as usize;
+
+/// The reasons a save state can't be loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data isn't a save state at all.
+    BadMagic,
+    /// The state was saved in a format this version can't read.
+    UnsupportedVersion(u16),
+    /// The state was saved while playing a different ROM.
+    RomMismatch { expected: u32, found: u32 },
+    /// The state ends early or has data left over.
+    Truncated,
+    /// The state has values that the chip could never be in.
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic =>
+                write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) =>
+                write!(f, "Unsupported save state version {}", version),
+            StateError::RomMismatch { expected, found } =>
+                write!(f, "Save state is for ROM {:08X}, not {:08X}", found, expected),
+            StateError::Truncated =>
+                write!(f, "Save state is the wrong length"),
+            StateError::Corrupt =>
+                write!(f, "Save state is corrupt"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Reads big-endian values from the front of a byte slice.
+pub(crate) struct Reader<'a> {
+    pub(crate) data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (front, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(front)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.bytes(4)?;
+        Ok((bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 |
+           (bytes[2] as u32) << 8 | bytes[3] as u32)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        Ok((self.u32()? as u64) << 32 | self.u32()? as u64)
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+}
+
+pub(crate) fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+pub(crate) fn put_u32(out: &mut Vec<u8>, value: u32) {
+    put_u16(out, (value >> 16) as u16);
+    put_u16(out, value as u16);
+}
+
+pub(crate) fn put_u64(out: &mut Vec<u8>, value: u64) {
+    put_u32(out, (value >> 32) as u32);
+    put_u32(out, value as u32);
+}
+
+impl Chip8 {
+    /// Snapshots the whole machine.  The quirks aren't part of the snapshot,
+    /// since they're a setting rather than something the game changes.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE + NMEM + MAX_PIXELS + 128);
+        out.extend_from_slice(MAGIC);
+        put_u16(&mut out, STATE_VERSION);
+        put_u32(&mut out, self.rom_hash);
+
+        put_u16(&mut out, self.opcode);
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.reg);
+        put_u16(&mut out, self.index);
+        put_u16(&mut out, self.pc);
+        out.push(self.hires as u8);
+        out.extend_from_slice(&self.graphics);
+        out.resize(out.len() + MAX_PIXELS - self.graphics.len(), 0);
+        out.push(self.planes);
+        out.push(self.timer_delay);
+        out.push(self.timer_sound);
+        for address in self.stack.iter() {
+            put_u16(&mut out, *address);
+        }
+        put_u16(&mut out, self.sp);
+        out.extend_from_slice(&self.rpl);
+        out.extend_from_slice(&self.key);
+        out.push(self.sound_active as u8);
+        out.push(self.draw_flag as u8);
+        out.push(self.audio_pattern.is_some() as u8);
+        out.extend_from_slice(&self.audio_pattern.unwrap_or([0; PATTERN_SIZE]));
+        out.push(self.pitch);
+        put_u64(&mut out, self.rng.state());
+        out
+    }
+
+    /// Restores the machine from a snapshot made by `save_state` while
+    /// playing the same ROM.  The chip is left untouched if the snapshot
+    /// can't be loaded.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader { data: state };
+        if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let rom_hash = reader.u32()?;
+        if rom_hash != self.rom_hash {
+            return Err(StateError::RomMismatch { expected: self.rom_hash, found: rom_hash });
+        }
+
+        // Read everything before changing the chip, so a bad state can't
+        // leave it half loaded
+        let opcode = reader.u16()?;
+        let memory = reader.bytes(NMEM)?;
+        let reg = reader.bytes(NREG)?;
+        let index = reader.u16()?;
+        let pc = reader.u16()?;
+        let hires = reader.bool()?;
+        let graphics = reader.bytes(MAX_PIXELS)?;
+        let planes = reader.u8()?;
+        let timer_delay = reader.u8()?;
+        let timer_sound = reader.u8()?;
+        let mut stack = [0; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let sp = reader.u16()?;
+        let rpl = reader.bytes(NFLAGS)?;
+        let key = reader.bytes(16)?;
+        let sound_active = reader.bool()?;
+        let draw_flag = reader.bool()?;
+        let has_pattern = reader.bool()?;
+        let pattern = reader.bytes(PATTERN_SIZE)?;
+        let pitch = reader.u8()?;
+        let rng = reader.u64()?;
+        if !reader.data.is_empty() {
+            return Err(StateError::Truncated);
+        }
+        if sp as usize > stack.len() || planes >= 1 << NPLANES {
+            return Err(StateError::Corrupt);
+        }
+
+        self.opcode = opcode;
+        self.memory.copy_from_slice(memory);
+        self.reg.copy_from_slice(reg);
+        self.index = index;
+        self.pc = pc;
+        self.hires = hires;
+        let npixels = self.width() * self.height();
+        self.graphics = graphics[..npixels].to_vec();
+        self.planes = planes;
+        self.timer_delay = timer_delay;
+        self.timer_sound = timer_sound;
+        self.stack = stack;
+        self.sp = sp;
+        self.rpl.copy_from_slice(rpl);
+        self.key.copy_from_slice(key);
+        self.sound_active = sound_active;
+        self.draw_flag = draw_flag;
+        self.audio_pattern = if has_pattern {
+            let mut buffer = [0; PATTERN_SIZE];
+            buffer.copy_from_slice(pattern);
+            Some(buffer)
+        } else {
+            None
+        };
+        self.pitch = pitch;
+        self.rng = Random::from_state(rng);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StateError;
+    use Chip8;
+
+    fn playing_chip() -> Chip8 {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0x00, 0xFF, 0x60, 0x05, 0xA2, 0x00, 0xD0, 0x05, 0x22, 0x00]);
+        chip.run_frame(5).unwrap();
+        chip
+    }
+
+    #[test]
+    fn round_trip() {
+        let chip = playing_chip();
+        let state = chip.save_state();
+
+        let mut other = Chip8::default();
+        other.load(&vec![0x00, 0xFF, 0x60, 0x05, 0xA2, 0x00, 0xD0, 0x05, 0x22, 0x00]);
+        other.load_state(&state).unwrap();
+        assert_eq!(other.save_state(), state);
+        assert_eq!(other.pc, chip.pc);
+        assert_eq!(other.graphics, chip.graphics);
+        assert_eq!(other.stack, chip.stack);
+    }
+
+    #[test]
+    fn restores_random_numbers() {
+        let mut chip = Chip8::default();
+        chip.load(&vec![0xC1, 0xFF, 0x12, 0x00]);
+        let state = chip.save_state();
+        chip.run_frame(2).unwrap();
+        let first = chip.reg[1];
+
+        chip.load_state(&state).unwrap();
+        chip.run_frame(2).unwrap();
+        assert_eq!(chip.reg[1], first);
+    }
+
+    #[test]
+    fn same_size_in_any_mode() {
+        let chip = playing_chip();
+        assert!(chip.hires());
+        assert_eq!(chip.save_state().len(), Chip8::default().save_state().len());
+    }
+
+    #[test]
+    fn rejects_bad_states() {
+        let mut chip = playing_chip();
+        let state = chip.save_state();
+
+        assert_eq!(chip.load_state(b"nope"), Err(StateError::BadMagic));
+        assert_eq!(chip.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+
+        let mut future = state.clone();
+        future[5] = 99;
+        assert_eq!(chip.load_state(&future), Err(StateError::UnsupportedVersion(99)));
+
+        let mut other = Chip8::default();
+        other.load(&vec![0x12, 0x00]);
+        match other.load_state(&state) {
+            Err(StateError::RomMismatch { .. }) => {}
+            result => panic!("Expected a ROM mismatch, got {:?}", result),
+        }
+        assert_eq!(other.pc, 0x200);
+    }
+}
@@ -0,0 +1,355 @@
+extern crate libc;
+
+use chip8::Chip8;
+use chip8::frontend::{AudioSink, Command, InputSource, VideoSink};
+use chip8::keymap::Keymap;
+use chip8::flicker::Filter;
+use chip8::palette::{Color, Palette};
+use std::io::{self, Write};
+use std::mem;
+use std::str::FromStr;
+
+/// How long a key counts as held after the terminal sends it (in frames).
+/// Terminals only report presses, so releases are guessed: a fresh press
+/// is held long enough to bridge the keyboard's delay before it starts
+/// repeating, and each repeat keeps it held a little longer.
+const FIRST_HOLD : u32 = 30;
+const REPEAT_HOLD : u32 = 6;
+
+/// The characters used to draw pixels.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Glyphs {
+    /// Half blocks in low resolution and Braille in high resolution, so
+    /// either fits in an 80x24 terminal
+    Auto,
+    /// Half blocks, two pixels to a character, in color
+    HalfBlock,
+    /// Braille dots, eight pixels to a character, in one color
+    Braille,
+}
+
+impl FromStr for Glyphs {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "auto" => Ok(Glyphs::Auto),
+            "half" => Ok(Glyphs::HalfBlock),
+            "braille" => Ok(Glyphs::Braille),
+            _ => Err(format!("Unknown glyphs: {}", name)),
+        }
+    }
+}
+
+/// Reads the keypad from the terminal, with the terminal in raw mode so that
+/// keys arrive as soon as they're pressed.  The terminal is put back the way
+/// it was when this is dropped.
+pub struct Keys {
+    original: libc::termios,
+    keymap: Keymap,
+    held: [u32; 16],
+    rewind_held: u32,
+}
+
+/// Draws the display in the terminal using ANSI escape codes, on the
+/// alternate screen until this is dropped.
+pub struct Screen {
+    glyphs: Glyphs,
+    filter: Filter,
+    panel: bool,
+    size: (usize, usize),
+    status: String,
+    dirty: bool,
+    /// Why the last frame couldn't be drawn, which stops the emulator
+    failure: Option<String>,
+}
+
+/// Rings the terminal bell, since that's the only sound a terminal can make.
+#[derive(Default)]
+pub struct Bell {
+    beeping: bool,
+}
+
+/// Sets a foreground or background color.
+fn color(out: &mut String, background: bool, [r, g, b]: Color) {
+    out.push_str(&format!("\x1b[{};2;{};{};{}m", if background { 48 } else { 38 }, r, g, b));
+}
+
+impl Keys {
+    /// Switches the terminal to raw mode, pressing hex keys as the keymap
+    /// says.
+    pub fn new(keymap: Keymap) -> io::Result<Self> {
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        // Reads return straight away, with nothing if no key was pressed
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Keys {
+            original,
+            keymap,
+            held: [0; 16],
+            rewind_held: 0,
+        })
+    }
+
+    /// Holds down the hex key bound to the named key, if there is one.
+    fn press(&mut self, name: &str) -> Command {
+        if let Some(key) = self.keymap.key(name) {
+            let held = &mut self.held[key];
+            *held = (*held).max(if *held > 0 { REPEAT_HOLD } else { FIRST_HOLD });
+        }
+        Command::Continue
+    }
+}
+
+impl InputSource for Keys {
+    /// Reads the keys pressed since the last check, returning the first
+    /// hotkey and marking which keys should count as held in the given key
+    /// state array.
+    ///
+    /// The hex keys are bound by the keymap, Backspace rewinds, F5/F9 save and load
+    /// states, F6/F7 change slot, F8 changes theme, and Escape or Ctrl-C quit.
+    fn check(&mut self, keys: &mut [u8; 16]) -> Command {
+        let mut input = Vec::new();
+        let mut buffer = [0u8; 64];
+        loop {
+            let n = unsafe {
+                libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len())
+            };
+            if n <= 0 {
+                break;
+            }
+            input.extend_from_slice(&buffer[..n as usize]);
+        }
+
+        let mut command = Command::Continue;
+        let mut i = 0;
+        while i < input.len() {
+            let byte = input[i];
+            i += 1;
+            let found = match byte {
+                0x03 => Command::Quit,
+                0x1B if i < input.len() && (input[i] == b'[' || input[i] == b'O') => {
+                    // An escape sequence, which ends with a byte from @ to ~
+                    let start = i + 1;
+                    i = input[start..].iter()
+                        .position(|byte| (0x40..=0x7E).contains(byte))
+                        .map_or(input.len(), |end| start + end + 1);
+                    match &input[start..i] {
+                        b"A" => self.press("Up"),
+                        b"B" => self.press("Down"),
+                        b"C" => self.press("Right"),
+                        b"D" => self.press("Left"),
+                        b"15~" => Command::SaveState,
+                        b"17~" => Command::PrevSlot,
+                        b"18~" => Command::NextSlot,
+                        b"19~" => Command::NextTheme,
+                        b"20~" => Command::LoadState,
+                        b"21~" => Command::Screenshot,
+                        b"23~" => Command::Record,
+                        b"24~" => Command::Break,
+                        _ => Command::Continue,
+                    }
+                }
+                0x1B => Command::Quit,
+                0x08 | 0x7F => {
+                    self.rewind_held = if self.rewind_held > 0 { REPEAT_HOLD } else { FIRST_HOLD };
+                    Command::Continue
+                }
+                b' ' => self.press("Space"),
+                b'\r' | b'\n' => self.press("Return"),
+                b'\t' => self.press("Tab"),
+                _ => self.press(&(byte as char).to_string()),
+            };
+            if let Command::Continue = command {
+                command = found;
+            }
+        }
+
+        for (key, held) in keys.iter_mut().zip(self.held.iter_mut()) {
+            *key = (*held > 0) as u8;
+            *held = held.saturating_sub(1);
+        }
+        match command {
+            Command::Continue if self.rewind_held > 0 => {
+                self.rewind_held -= 1;
+                Command::Rewind
+            }
+            _ => command,
+        }
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+impl Screen {
+    /// Switches the terminal to a blank alternate screen.
+    pub fn new(glyphs: Glyphs, filter: Filter, panel: bool) -> io::Result<Self> {
+        // Alternate screen, hidden cursor
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(Screen {
+            glyphs,
+            filter,
+            panel,
+            size: (0, 0),
+            status: String::new(),
+            dirty: false,
+            failure: None,
+        })
+    }
+
+    /// Draws the chip's display, and the register panel if it's enabled.
+    fn draw(&mut self, chip: &Chip8) -> io::Result<()> {
+        let (width, height) = (chip.width(), chip.height());
+        let braille = match self.glyphs {
+            Glyphs::Auto => chip.hires(),
+            Glyphs::HalfBlock => false,
+            Glyphs::Braille => true,
+        };
+        let (cell_width, cell_height) = if braille { (2, 4) } else { (1, 2) };
+        let (columns, rows) = (width / cell_width, height / cell_height);
+
+        let mut out = String::new();
+        if self.size != (columns, rows) {
+            out.push_str("\x1b[2J");
+            self.size = (columns, rows);
+        }
+        out.push_str("\x1b[H");
+        let panel = if self.panel { panel_lines(chip) } else { Vec::new() };
+        self.filter.push(chip);
+        let colors = self.filter.render();
+        let palette = self.filter.palette;
+        let pixel = |x: usize, y: usize| colors[y * width + x];
+
+        for row in 0..rows {
+            if braille {
+                color(&mut out, false, palette.color(1));
+                color(&mut out, true, palette.color(0));
+                for column in 0..columns {
+                    // Braille dots are numbered down the left column first,
+                    // with the bottom row added afterwards
+                    const DOTS : [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+                    let mut bits = 0;
+                    for (dy, dots) in DOTS.iter().enumerate() {
+                        for (dx, dot) in dots.iter().enumerate() {
+                            if pixel(column * 2 + dx, row * 4 + dy) != palette.color(0) {
+                                bits |= dot;
+                            }
+                        }
+                    }
+                    out.push(::std::char::from_u32(0x2800 + bits).unwrap());
+                }
+            } else {
+                // The top pixel is the foreground of an upper half block
+                // and the bottom pixel is its background
+                let mut current = None;
+                for x in 0..columns {
+                    let colors = (pixel(x, row * 2), pixel(x, row * 2 + 1));
+                    if current != Some(colors) {
+                        color(&mut out, false, colors.0);
+                        color(&mut out, true, colors.1);
+                        current = Some(colors);
+                    }
+                    out.push('\u{2580}');
+                }
+            }
+            out.push_str("\x1b[0m");
+            if let Some(line) = panel.get(row) {
+                out.push_str("  ");
+                out.push_str(line);
+            }
+            out.push_str("\x1b[K\r\n");
+        }
+        out.push_str(&self.status);
+        out.push_str("\x1b[K");
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+impl VideoSink for Screen {
+    fn draw_frame(&mut self, chip: &Chip8) {
+        self.dirty = false;
+        if let Err(e) = self.draw(chip) {
+            self.failure = Some(format!("Couldn't draw to the terminal: {}", e));
+        }
+    }
+
+    fn next_theme(&mut self) {
+        let (name, palette) = self.filter.palette.next_theme();
+        self.filter.palette = palette;
+        self.show_status(&format!("Theme {}", name));
+    }
+
+    fn palette(&self) -> Palette {
+        self.filter.palette
+    }
+
+    /// Shows a message under the display until the next one.
+    fn show_status(&mut self, status: &str) {
+        self.status = status.to_string();
+        self.dirty = true;
+    }
+
+    /// The register panel, and blended or fading frames, change every frame
+    /// even when the chip's display doesn't.
+    fn needs_redraw(&self) -> bool {
+        self.panel || self.dirty || self.filter.is_active()
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.failure.clone()
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+    }
+}
+
+impl AudioSink for Bell {
+    /// Rings the bell when the chip starts making a sound.
+    fn set_beep(&mut self, enable: bool) {
+        if enable && !self.beeping {
+            print!("\x07");
+            let _ = io::stdout().flush();
+        }
+        self.beeping = enable;
+    }
+}
+
+/// The lines of the register panel.
+fn panel_lines(chip: &Chip8) -> Vec<String> {
+    let v = chip.registers();
+    let mut lines = vec![
+        format!("PC {:04X}", chip.pc()),
+        format!(" I {:04X}", chip.index()),
+        format!("DT {:02X} ST {:02X}", chip.delay_timer(), chip.sound_timer()),
+    ];
+    for x in (0..16).step_by(2) {
+        lines.push(format!("V{:X} {:02X} V{:X} {:02X}", x, v[x], x + 1, v[x + 1]));
+    }
+    lines.push(format!("SP {:X}", chip.stack().len()));
+    lines
+}
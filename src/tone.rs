@@ -0,0 +1,257 @@
+//! The sound the chip makes, as samples.
+//!
+//! This is shared by everything that plays or records sound, so that a
+//! recording sounds just like the game did.
+
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use PATTERN_SIZE;
+
+/// The sample rate sound is generated at (in Hz).
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// How long the tone takes to fade in or out (in seconds), so that starting
+/// and stopping it doesn't click.
+const FADE_TIME: f32 = 0.005;
+
+/// The number of bits in an XO-CHIP audio pattern.
+const PATTERN_BITS: usize = PATTERN_SIZE * 8;
+
+/// The names of the waveforms, as `Waveform::from_str` takes them.
+pub const WAVEFORMS: [&str; 4] = ["square", "sine", "triangle", "noise"];
+
+/// The shape of the beep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    /// Random levels, changing twice a cycle.
+    Noise,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    /// Parses `square`, `sine`, `triangle` or `noise`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("Unknown waveform: {}", name)),
+        }
+    }
+}
+
+/// How the beep sounds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sound {
+    /// The pitch (in Hz)
+    pub frequency: f32,
+    pub waveform: Waveform,
+    /// How loud it is, from 0 to 1
+    pub volume: f32,
+}
+
+impl Default for Sound {
+    /// A 440 Hz square wave at a quarter volume, as this emulator has
+    /// always beeped.
+    fn default() -> Self {
+        Sound { frequency: 440.0, waveform: Waveform::Square, volume: 0.25 }
+    }
+}
+
+/// Represents the tone the chip makes.  This is the beep, unless the game
+/// has given an XO-CHIP audio pattern to loop instead.  It's silent until
+/// it's started, and fades in and out as it starts and stops.
+pub struct Tone {
+    playing: bool,
+    /// How far faded in the tone is, from 0 to 1, and how much that
+    /// changes each sample while fading
+    fade: f32,
+    fade_inc: f32,
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    waveform: Waveform,
+    /// The level noise is held at, and the state of the generator that
+    /// picks the next one
+    noise: (f32, u32),
+    pattern: Option<[u8; PATTERN_SIZE]>,
+    pattern_inc: f32,
+    sample_rate: f32,
+}
+
+impl Tone {
+    /// Constructs the given beep, generated at the given sample rate.
+    pub fn new(sample_rate: u32, sound: Sound) -> Self {
+        let mut tone = Tone {
+            playing: false,
+            fade: 0.0,
+            fade_inc: 1.0 / (FADE_TIME * sample_rate as f32),
+            phase_inc: sound.frequency / sample_rate as f32,
+            phase: 0.0,
+            volume: sound.volume,
+            waveform: sound.waveform,
+            noise: (0.0, 0x2545_F491),
+            pattern: None,
+            pattern_inc: 0.0,
+            sample_rate: sample_rate as f32,
+        };
+        tone.next_noise();
+        tone
+    }
+
+    /// The beep's level at the current phase, between -1 and 1.
+    fn level(&self) -> f32 {
+        match self.waveform {
+            Waveform::Square => if self.phase <= 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => self.noise.0,
+        }
+    }
+
+    /// Picks a new noise level, with a xorshift generator so that the noise
+    /// is the same every time.
+    fn next_noise(&mut self) {
+        let mut state = self.noise.1;
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        self.noise = (state as f32 / u32::MAX as f32 * 2.0 - 1.0, state);
+    }
+
+    /// Starts or stops the tone.
+    pub fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+
+    /// Sets the XO-CHIP audio pattern to play instead of the square wave,
+    /// and the rate (in bits per second) to play it at.
+    pub fn set_pattern(&mut self, pattern: Option<[u8; PATTERN_SIZE]>, rate: f32) {
+        self.pattern = pattern;
+        self.pattern_inc = rate / PATTERN_BITS as f32 / self.sample_rate;
+    }
+
+    /// Generates the next samples of the tone.
+    pub fn fill(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            if self.playing {
+                self.fade = (self.fade + self.fade_inc).min(1.0);
+            } else {
+                self.fade = (self.fade - self.fade_inc).max(0.0);
+                if self.fade == 0.0 {
+                    // Start the next beep from the beginning of a cycle
+                    self.phase = 0.0;
+                    *x = 0.0;
+                    continue;
+                }
+            }
+            match self.pattern {
+                Some(pattern) => {
+                    // Play the pattern's bits, a whole pattern per phase
+                    let bit = (self.phase * PATTERN_BITS as f32) as usize % PATTERN_BITS;
+                    let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                    *x = if high { self.volume } else { -self.volume };
+                    self.phase = (self.phase + self.pattern_inc) % 1.0;
+                }
+                None => {
+                    *x = self.level() * self.volume;
+                    let half = self.phase >= 0.5;
+                    self.phase = (self.phase + self.phase_inc) % 1.0;
+                    if self.waveform == Waveform::Noise && half != (self.phase >= 0.5) {
+                        self.next_noise();
+                    }
+                }
+            }
+            *x *= self.fade;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Sound, Tone, Waveform, WAVEFORMS};
+
+    /// A tone that's already faded in.
+    fn playing(sample_rate: u32, sound: Sound) -> Tone {
+        let mut tone = Tone::new(sample_rate, sound);
+        tone.set_playing(true);
+        tone.fade = 1.0;
+        tone
+    }
+
+    fn samples(waveform: Waveform) -> [f32; 8] {
+        let mut tone = playing(8000, Sound { frequency: 1000.0, waveform, volume: 0.5 });
+        let mut samples = [0.0; 8];
+        tone.fill(&mut samples);
+        samples
+    }
+
+    #[test]
+    fn square_wave() {
+        let mut tone = playing(4400, Sound::default());
+        let mut samples = [0.0; 10];
+        tone.fill(&mut samples);
+        assert_eq!(&samples[..6], &[0.25; 6]);
+        assert_eq!(&samples[6..], &[-0.25; 4]);
+    }
+
+    #[test]
+    fn waveforms() {
+        for name in WAVEFORMS.iter() {
+            assert!(name.parse::<Waveform>().is_ok(), "{}", name);
+        }
+        assert!("sawtooth".parse::<Waveform>().is_err());
+
+        let sine = samples(Waveform::Sine);
+        assert!(sine[0].abs() < 1e-6);
+        assert!((sine[2] - 0.5).abs() < 1e-6);
+        assert!((sine[6] + 0.5).abs() < 1e-6);
+        assert_eq!(samples(Waveform::Triangle)[..5], [-0.5, -0.25, 0.0, 0.25, 0.5]);
+
+        // Noise is held for half a cycle, and is the same every time
+        let noise = samples(Waveform::Noise);
+        assert!(noise.iter().all(|sample| sample.abs() <= 0.5));
+        assert_ne!(noise[0], 0.0);
+        assert_eq!(noise[0], noise[3]);
+        assert_ne!(noise[3], noise[4]);
+        assert_eq!(noise, samples(Waveform::Noise));
+    }
+
+    #[test]
+    fn pattern() {
+        let mut tone = playing(128, Sound::default());
+        let mut pattern = [0; 16];
+        pattern[0] = 0xF0;
+        // One bit a sample
+        tone.set_pattern(Some(pattern), 128.0);
+        let mut samples = [0.0; 8];
+        tone.fill(&mut samples);
+        assert_eq!(samples, [0.25, 0.25, 0.25, 0.25, -0.25, -0.25, -0.25, -0.25]);
+    }
+
+    #[test]
+    fn fades_in_and_out() {
+        // Fading takes 100 samples, and a cycle 800
+        let mut tone = Tone::new(20000, Sound { frequency: 25.0, ..Sound::default() });
+        let mut samples = [1.0; 300];
+        tone.fill(&mut samples);
+        assert!(samples.iter().all(|&sample| sample == 0.0));
+
+        tone.set_playing(true);
+        tone.fill(&mut samples);
+        assert!((samples[0] - 0.0025).abs() < 1e-6);
+        assert!((samples[49] - 0.125).abs() < 1e-4);
+        assert!(samples[99..].iter().all(|&sample| (sample - 0.25).abs() < 1e-4));
+
+        tone.set_playing(false);
+        tone.fill(&mut samples);
+        assert!(samples.windows(2).all(|pair| (pair[0] - pair[1]).abs() < 0.01));
+        assert!(samples[100..].iter().all(|&sample| sample == 0.0));
+    }
+}
@@ -0,0 +1,171 @@
+//! Sound recordings as WAV files: 16-bit mono PCM.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use frontend::{AudioSink, FRAME_RATE};
+use tone::{Sound, Tone, SAMPLE_RATE};
+use PATTERN_SIZE;
+
+/// The size of the header before the samples.
+const HEADER_SIZE: u32 = 44;
+
+/// Writes samples to a WAV file as they're generated.  The header gives the
+/// length, so it's filled in by `finish`.
+pub struct Writer<W: Write + Seek> {
+    out: W,
+    sample_rate: u32,
+    samples: u32,
+}
+
+/// Writes a little-endian u16, as WAV files have them, unlike the
+/// big-endian `put_u16` save states use.
+fn put_u16_le(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+}
+
+/// Writes a little-endian u32.
+fn put_u32_le(out: &mut Vec<u8>, value: u32) {
+    put_u16_le(out, value as u16);
+    put_u16_le(out, (value >> 16) as u16);
+}
+
+/// The header for the given number of samples.
+fn header(sample_rate: u32, samples: u32) -> Vec<u8> {
+    let data_size = samples * 2;
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(b"RIFF");
+    put_u32_le(&mut header, HEADER_SIZE - 8 + data_size);
+    header.extend_from_slice(b"WAVEfmt ");
+    put_u32_le(&mut header, 16);
+    // PCM, one channel
+    put_u16_le(&mut header, 1);
+    put_u16_le(&mut header, 1);
+    put_u32_le(&mut header, sample_rate);
+    put_u32_le(&mut header, sample_rate * 2);
+    // Two bytes a sample, 16 bits
+    put_u16_le(&mut header, 2);
+    put_u16_le(&mut header, 16);
+    header.extend_from_slice(b"data");
+    put_u32_le(&mut header, data_size);
+    header
+}
+
+impl<W: Write + Seek> Writer<W> {
+    /// Starts a WAV file of samples at the given rate.
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        out.write_all(&header(sample_rate, 0))?;
+        Ok(Writer { out, sample_rate, samples: 0 })
+    }
+
+    /// Adds samples between -1 and 1.
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            put_u16_le(&mut data, (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16 as u16);
+        }
+        self.samples += samples.len() as u32;
+        self.out.write_all(&data)
+    }
+
+    /// Fills in the length and hands back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header(self.sample_rate, self.samples))?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Records the chip's sound into a WAV file instead of playing it, with
+/// exactly a frame's worth of samples for every frame.
+pub struct Sink<W: Write + Seek> {
+    writer: Writer<W>,
+    tone: Tone,
+    samples: Vec<f32>,
+    /// The first error writing the file, which stops the recording
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Seek> Sink<W> {
+    /// Starts recording the given beep.
+    pub fn new(out: W, sound: Sound) -> io::Result<Self> {
+        Ok(Sink {
+            writer: Writer::new(out, SAMPLE_RATE)?,
+            tone: Tone::new(SAMPLE_RATE, sound),
+            samples: vec![0.0; (SAMPLE_RATE / FRAME_RATE) as usize],
+            error: None,
+        })
+    }
+
+    /// Ends the recording, handing back the writer.
+    /// # Errors
+    /// Returns the first error there was writing the file.
+    pub fn finish(self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => self.writer.finish(),
+        }
+    }
+}
+
+impl<W: Write + Seek> AudioSink for Sink<W> {
+    /// Writes the next frame of sound: the beep while the sound timer runs,
+    /// and silence otherwise.
+    fn set_beep(&mut self, enable: bool) {
+        self.tone.set_playing(enable);
+        self.tone.fill(&mut self.samples);
+        if self.error.is_none() {
+            self.error = self.writer.write(&self.samples).err();
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; PATTERN_SIZE]>, rate: f32) {
+        self.tone.set_pattern(pattern, rate);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::{Sink, Writer};
+    use frontend::AudioSink;
+    use tone::Sound;
+
+    #[test]
+    fn writes_samples() {
+        let mut writer = Writer::new(Cursor::new(Vec::new()), 8000).unwrap();
+        writer.write(&[0.0, 1.0, -1.0]).unwrap();
+        writer.write(&[2.0]).unwrap();
+        let wav = writer.finish().unwrap().into_inner();
+
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[4..8], &[44, 0, 0, 0]);
+        assert_eq!(&wav[24..28], &[0x40, 0x1F, 0, 0]);
+        assert_eq!(&wav[40..44], &[8, 0, 0, 0]);
+        assert_eq!(&wav[44..], &[0, 0, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn sink_records_frames() {
+        let mut sink = Sink::new(Cursor::new(Vec::new()), Sound::default()).unwrap();
+        for &beep in &[false, true, true, false, false] {
+            sink.set_beep(beep);
+        }
+        let wav = sink.finish().unwrap().into_inner();
+        let samples: Vec<i16> = wav[44..].chunks(2)
+            .map(|sample| sample[0] as i16 | (sample[1] as i16) << 8)
+            .collect();
+
+        // 735 samples a frame, beeping for exactly two frames, and fading in
+        // and out over 5 ms
+        assert_eq!(samples.len(), 735 * 5);
+        assert!(samples[..735].iter().all(|&sample| sample == 0));
+        assert!(samples[735].abs() < 100);
+        assert!(samples[735 + 221..735 * 3].iter().all(|&sample| sample.abs() == 8192));
+        assert!(samples[735 * 3..735 * 3 + 220].iter().any(|&sample| sample != 0));
+        assert!(samples[735 * 3 + 221..].iter().all(|&sample| sample == 0));
+    }
+}